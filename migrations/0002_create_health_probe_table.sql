@@ -0,0 +1,5 @@
+-- Scratch table written by the deep health check; rows are always rolled back
+CREATE TABLE IF NOT EXISTS health_probe (
+    id SERIAL PRIMARY KEY,
+    checked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+-- Responses to requests carrying an Idempotency-Key, replayed when the same
+-- key is retried before it expires. Kept in the database so replays survive
+-- restarts and work across instances.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key VARCHAR(255) PRIMARY KEY,
+    status SMALLINT NOT NULL,
+    body TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
@@ -0,0 +1,2 @@
+-- Case-insensitive email lookups, used to reject case-variant duplicates.
+CREATE INDEX IF NOT EXISTS idx_users_lower_email ON users(lower(email));
@@ -0,0 +1,3 @@
+-- Inline change history: each update appends {"at": ..., "fields": {...}}
+-- with the new value of every field it changed.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS changes JSONB NOT NULL DEFAULT '[]'::jsonb;
@@ -0,0 +1,3 @@
+-- Soft delete: a set deleted_at hides the user from regular reads while
+-- keeping the row for admins and history.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
@@ -0,0 +1,5 @@
+-- Optional given and family names. When either is set, name holds the two
+-- combined; existing users keep name alone.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS first_name VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS last_name VARCHAR(255);
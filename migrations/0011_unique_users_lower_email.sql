@@ -0,0 +1,20 @@
+-- Case-insensitive email uniqueness, enforced by the database instead of
+-- only by the inserts' own checks. Refuses to run while users differ only in
+-- the case of their email, since the unique index could not be built.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(lower_email, ', ' ORDER BY lower_email) INTO duplicates
+    FROM (
+        SELECT lower(email) AS lower_email FROM users
+        GROUP BY lower(email) HAVING COUNT(*) > 1
+    ) AS variants;
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'users.email has case-variant duplicates: %', duplicates
+            USING HINT = 'Merge or rename the duplicate users (GET /admin/diagnostics/duplicate-emails lists them), then restart to rerun migrations';
+    END IF;
+END $$;
+
+DROP INDEX IF EXISTS idx_users_lower_email;
+CREATE UNIQUE INDEX IF NOT EXISTS users_lower_email_key ON users(lower(email));
@@ -0,0 +1,161 @@
+//! Per-request access log
+//!
+//! One info-level line is logged for every completed request. It is emitted
+//! inside the request span, so the span's `method`, `route` and `request_id`
+//! appear next to the event's `status`, `latency_ms` and `response_size`:
+//!
+//! ```text
+//! INFO request{method=GET uri=/users/1 route=/users/:id request_id=...}: rust_basic_api::access_log: request completed status=200 latency_ms=3 response_size=97
+//! ```
+//!
+//! Requests taking `SLOW_REQUEST_MS` or longer log the same line at warn
+//! level as `slow request completed`, so slow endpoints stand out without a
+//! metrics stack.
+
+use crate::metrics::known_body_size;
+use axum::{body::HttpBody, http::Response};
+use std::time::Duration;
+use tower_http::trace::OnResponse;
+use tracing::Span;
+
+/// `on_response` callback of the trace layer logging the access line
+///
+/// `response_size` is omitted for streamed bodies of unknown length.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessLog {
+    slow_threshold: Option<Duration>,
+}
+
+impl AccessLog {
+    /// Log requests at warn level once they take `slow_request_ms`; `0`
+    /// logs every request at info level
+    #[must_use]
+    pub const fn new(slow_request_ms: u64) -> Self {
+        let slow_threshold = if slow_request_ms == 0 {
+            None
+        } else {
+            Some(Duration::from_millis(slow_request_ms))
+        };
+        Self { slow_threshold }
+    }
+
+    fn is_slow(self, latency: Duration) -> bool {
+        self.slow_threshold
+            .is_some_and(|threshold| latency >= threshold)
+    }
+}
+
+impl<B: HttpBody> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let response_size = known_body_size(response.body(), response.headers());
+        let status = response.status().as_u16();
+        let latency_ms = latency.as_millis();
+
+        if self.is_slow(latency) {
+            tracing::warn!(
+                parent: span,
+                status,
+                latency_ms,
+                response_size,
+                "slow request completed"
+            );
+        } else {
+            tracing::info!(
+                parent: span,
+                status,
+                latency_ms,
+                response_size,
+                "request completed"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        request_id::{make_span, propagate_request_id, X_REQUEST_ID},
+        test_utils::{capture_logs, test_config, test_state, unreachable_pool},
+    };
+    use axum::{body::Body, http::Request, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+
+    fn app(slow_request_ms: u64) -> Router {
+        let state = test_state(unreachable_pool(), test_config());
+        Router::new()
+            .route("/items/:id", get(|| async { "ok" }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(30)).await;
+                    "done"
+                }),
+            )
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(make_span)
+                    .on_response(AccessLog::new(slow_request_ms)),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                propagate_request_id,
+            ))
+            .with_state(state)
+    }
+
+    #[tokio::test]
+    async fn test_access_line_has_all_fields() {
+        let (logs, _guard) = capture_logs();
+        let app = app(1000);
+
+        let request = Request::get("/items/7")
+            .header(&X_REQUEST_ID, "req-log-1")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let output = logs.contents();
+        let line = output
+            .lines()
+            .find(|line| line.contains("request completed"))
+            .expect("no access log line");
+        for field in [
+            "method=GET",
+            "route=/items/:id",
+            "request_id=req-log-1",
+            "status=200",
+            "latency_ms=",
+            "response_size=2",
+        ] {
+            assert!(line.contains(field), "missing {field}: {line}");
+        }
+        assert!(line.contains("INFO"), "{line}");
+        assert!(!output.contains("slow request"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_logs_warning() {
+        let (logs, _guard) = capture_logs();
+
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        app(10).oneshot(request).await.unwrap();
+
+        let output = logs.contents();
+        let line = output
+            .lines()
+            .find(|line| line.contains("slow request completed"))
+            .expect("no slow request line");
+        for field in ["WARN", "method=GET", "route=/slow", "latency_ms="] {
+            assert!(line.contains(field), "missing {field}: {line}");
+        }
+    }
+
+    #[test]
+    fn test_zero_threshold_never_warns() {
+        assert!(!AccessLog::new(0).is_slow(Duration::from_secs(90)));
+        assert!(AccessLog::new(10).is_slow(Duration::from_millis(10)));
+        assert!(!AccessLog::new(10).is_slow(Duration::from_millis(9)));
+    }
+}
@@ -0,0 +1,260 @@
+//! Request authentication
+//!
+//! Administrative endpoints are protected by a static API key supplied in the
+//! `X-API-Key` header and compared against the `API_KEY` configuration value.
+//!
+//! Handlers state the [`Role`] they need with [`RequireRole`]. The caller's
+//! [`Principal`] is taken from the request extensions when an
+//! authentication middleware has put one there, and otherwise derived from
+//! the API key: `API_KEY` is an admin, a key listed in `API_KEY_TIERS` a
+//! reader.
+
+use crate::{
+    config::{ApiTier, Config},
+    error::AppError,
+    state::AppState,
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap},
+};
+use std::marker::PhantomData;
+
+/// Header carrying the API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Extractor guarding a handler behind the configured API key
+///
+/// Rejects with [`AppError::Unauthorized`] when the header is missing or
+/// wrong. When no `API_KEY` is configured every request is rejected, so
+/// admin endpoints fail closed.
+#[derive(Debug, Clone, Copy)]
+pub struct RequireApiKey;
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for RequireApiKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if has_valid_api_key(&parts.headers, &state.config) {
+            Ok(Self)
+        } else {
+            Err(AppError::Unauthorized)
+        }
+    }
+}
+
+/// Whether `headers` carry the configured API key
+///
+/// For endpoints open to everyone that offer admins more; always false when
+/// no `API_KEY` is configured.
+#[must_use]
+pub fn has_valid_api_key(headers: &HeaderMap, config: &Config) -> bool {
+    let Some(expected) = config.api_key.as_deref() else {
+        return false;
+    };
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|provided| constant_time_eq(provided.as_bytes(), expected.as_bytes()))
+}
+
+/// Tier of the client key in `headers`, per `API_KEY_TIERS`
+///
+/// Requests without a key, or with one that has no tier, are
+/// [`ApiTier::Basic`].
+#[must_use]
+pub fn tier(headers: &HeaderMap, config: &Config) -> ApiTier {
+    let Some(provided) = headers.get(API_KEY_HEADER).and_then(|v| v.to_str().ok()) else {
+        return ApiTier::Basic;
+    };
+    config
+        .api_key_tiers
+        .iter()
+        .find(|(key, _)| constant_time_eq(provided.as_bytes(), key.as_bytes()))
+        .map_or(ApiTier::Basic, |&(_, tier)| tier)
+}
+
+/// What an authenticated caller may do, in increasing order of privilege
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Client keys: reads only
+    Reader,
+    /// Operators: mutating and administrative endpoints
+    Admin,
+}
+
+/// The authenticated caller of a request
+///
+/// Authentication middleware inserts it into the request extensions; see
+/// [`principal`] for the API key fallback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Privilege of the caller
+    pub role: Role,
+}
+
+/// Caller of the request in `parts`, or `None` if unauthenticated
+///
+/// A [`Principal`] already in the extensions wins; otherwise the `X-API-Key`
+/// header is matched against `API_KEY` (an admin) and then `API_KEY_TIERS`
+/// (a reader).
+#[must_use]
+pub fn principal(parts: &Parts, config: &Config) -> Option<Principal> {
+    if let Some(principal) = parts.extensions.get::<Principal>() {
+        return Some(principal.clone());
+    }
+    if has_valid_api_key(&parts.headers, config) {
+        return Some(Principal { role: Role::Admin });
+    }
+    let provided = parts.headers.get(API_KEY_HEADER)?.to_str().ok()?;
+    config
+        .api_key_tiers
+        .iter()
+        .any(|(key, _)| constant_time_eq(provided.as_bytes(), key.as_bytes()))
+        .then_some(Principal { role: Role::Reader })
+}
+
+/// The least [`Role`] a [`RequireRole`] extractor accepts
+pub trait MinRole {
+    /// Role required
+    const ROLE: Role;
+}
+
+/// Marker requiring [`Role::Admin`]
+#[derive(Debug, Clone, Copy)]
+pub struct AdminRole;
+
+impl MinRole for AdminRole {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Marker requiring [`Role::Reader`] or higher
+#[derive(Debug, Clone, Copy)]
+pub struct ReaderRole;
+
+impl MinRole for ReaderRole {
+    const ROLE: Role = Role::Reader;
+}
+
+/// Extractor guarding a handler behind a minimum role, e.g.
+/// `RequireRole<AdminRole>`
+///
+/// Rejects with [`AppError::Unauthorized`] when the caller is not
+/// authenticated and with [`AppError::Forbidden`] when their role is lower
+/// than `R` requires.
+#[derive(Debug, Clone)]
+pub struct RequireRole<R>(pub Principal, PhantomData<R>);
+
+#[axum::async_trait]
+impl<R: MinRole> FromRequestParts<AppState> for RequireRole<R> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let principal = principal(parts, &state.config).ok_or(AppError::Unauthorized)?;
+        if principal.role < R::ROLE {
+            return Err(AppError::Forbidden);
+        }
+        Ok(Self(principal, PhantomData))
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ApiTier,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::StatusCode,
+        middleware::{self, Next},
+        routing::post,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app(config: Config) -> Router {
+        Router::new()
+            .route("/guarded", post(|_: RequireRole<AdminRole>| async {}))
+            .with_state(test_state(unreachable_pool(), config))
+    }
+
+    fn config() -> Config {
+        Config {
+            api_key: Some("admin-key".to_string()),
+            api_key_tiers: vec![("client-key".to_string(), ApiTier::Premium)],
+            ..test_config()
+        }
+    }
+
+    async fn status(app: Router, key: Option<&str>) -> StatusCode {
+        let mut request = Request::post("/guarded");
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_require_role_authorized() {
+        assert_eq!(
+            status(app(config()), Some("admin-key")).await,
+            StatusCode::OK
+        );
+
+        // A principal from upstream middleware is trusted as is
+        let app = app(config()).layer(middleware::from_fn(
+            |mut request: Request, next: Next| async move {
+                request
+                    .extensions_mut()
+                    .insert(Principal { role: Role::Admin });
+                next.run(request).await
+            },
+        ));
+        assert_eq!(status(app, None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_require_role_insufficient_role_is_forbidden() {
+        assert_eq!(
+            status(app(config()), Some("client-key")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_role_unauthenticated() {
+        assert_eq!(status(app(config()), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(app(config()), Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(app(test_config()), Some("admin-key")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"x"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Request body integrity check
+//!
+//! Clients may send a digest of the request body in `Content-MD5` (base64 MD5
+//! of the body) or `Digest` (comma-separated `algorithm=base64` pairs, of
+//! which `SHA-256` and `MD5` are understood). When either header is present
+//! the middleware here buffers the body, recomputes each supported digest and
+//! answers `400` on a mismatch before the handler runs. Requests without the
+//! headers, or whose `Digest` names only unsupported algorithms, pass through
+//! unchecked.
+
+use crate::{body_timeout::MAX_BODY_BYTES, error::AppError};
+use axum::{
+    body::Body,
+    extract::Request,
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::Md5;
+use sha2::{Digest as _, Sha256};
+
+/// Header carrying the base64 MD5 of the body
+pub const CONTENT_MD5: &str = "content-md5";
+
+/// Header carrying `algorithm=base64` digests of the body
+pub const DIGEST: &str = "digest";
+
+/// Digest algorithms the middleware can verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("md5") {
+            Some(Self::Md5)
+        } else if name.eq_ignore_ascii_case("sha-256") {
+            Some(Self::Sha256)
+        } else {
+            None
+        }
+    }
+
+    fn digest(self, body: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Md5 => Md5::digest(body).to_vec(),
+            Self::Sha256 => Sha256::digest(body).to_vec(),
+        }
+    }
+}
+
+/// Middleware rejecting requests whose body does not match its digest headers
+pub async fn verify_body_digest(request: Request, next: Next) -> Response {
+    let expected = match expected_digests(request.headers()) {
+        Ok(expected) if expected.is_empty() => return next.run(request).await,
+        Ok(expected) => expected,
+        Err(e) => return e.into_response(),
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return AppError::PayloadTooLarge.into_response();
+    };
+    if let Err(e) = verify(&bytes, &expected) {
+        return e.into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// Decoded digests announced by the request headers
+///
+/// Unsupported `Digest` algorithms are skipped.
+///
+/// # Errors
+///
+/// Returns [`AppError::BadRequest`] if a header is not valid text or a
+/// digest value is not valid base64
+fn expected_digests(headers: &HeaderMap) -> Result<Vec<(Algorithm, Vec<u8>)>, AppError> {
+    let mut expected = Vec::new();
+    for value in headers.get_all(CONTENT_MD5) {
+        expected.push((Algorithm::Md5, decode(CONTENT_MD5, value.to_str().ok())?));
+    }
+    for value in headers.get_all(DIGEST) {
+        let value = value
+            .to_str()
+            .map_err(|_| AppError::BadRequest("Digest header is not valid text".to_string()))?;
+        for entry in value.split(',') {
+            let Some((name, encoded)) = entry.trim().split_once('=') else {
+                return Err(AppError::BadRequest(format!(
+                    "Digest entry {:?} is not algorithm=value",
+                    entry.trim()
+                )));
+            };
+            if let Some(algorithm) = Algorithm::parse(name.trim()) {
+                expected.push((algorithm, decode(DIGEST, Some(encoded.trim()))?));
+            }
+        }
+    }
+    Ok(expected)
+}
+
+fn decode(header: &str, value: Option<&str>) -> Result<Vec<u8>, AppError> {
+    value
+        .and_then(|value| STANDARD.decode(value.trim()).ok())
+        .ok_or_else(|| AppError::BadRequest(format!("{header} header is not valid base64")))
+}
+
+/// Check `body` against every expected digest
+fn verify(body: &[u8], expected: &[(Algorithm, Vec<u8>)]) -> Result<(), AppError> {
+    for (algorithm, digest) in expected {
+        if algorithm.digest(body) != *digest {
+            tracing::warn!(?algorithm, "Request body does not match its digest");
+            return Err(AppError::BadRequest(
+                "Request body does not match its digest".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, http::StatusCode, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    const BODY: &[u8] = b"{\"name\":\"Ann\"}";
+
+    async fn send(headers: &[(&str, String)]) -> (StatusCode, Bytes) {
+        let app = Router::new()
+            .route("/", post(|body: Bytes| async move { body }))
+            .layer(middleware::from_fn(verify_body_digest));
+        let mut request = Request::post("/");
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let response = app
+            .oneshot(request.body(Body::from(BODY)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, bytes)
+    }
+
+    #[tokio::test]
+    async fn test_matching_digests_pass() {
+        let md5 = STANDARD.encode(Md5::digest(BODY));
+        let sha = STANDARD.encode(Sha256::digest(BODY));
+
+        let (status, body) = send(&[(CONTENT_MD5, md5.clone())]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], BODY);
+
+        let digest = format!("SHA-256={sha}, md5={md5}, UNIXsum=30637");
+        let (status, body) = send(&[(DIGEST, digest)]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], BODY);
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_digest_is_rejected() {
+        let other = STANDARD.encode(Sha256::digest(b"something else"));
+
+        let (status, body) = send(&[(DIGEST, format!("sha-256={other}"))]).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "Request body does not match its digest");
+
+        let (status, _) = send(&[(CONTENT_MD5, "not base64!".to_string())]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_unsupported_digest_skips_check() {
+        assert_eq!(send(&[]).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&[(DIGEST, "UNIXsum=30637".to_string())]).await.0,
+            StatusCode::OK
+        );
+    }
+}
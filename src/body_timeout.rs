@@ -0,0 +1,165 @@
+//! Request body read timeout
+//!
+//! Slow clients can hold a connection and a handler open by trickling a body
+//! a few bytes at a time. The middleware here reads the whole body up front
+//! under `BODY_READ_TIMEOUT_SECS`, answering `408` if it does not arrive in
+//! time, and hands the buffered body on to the handler. A body whose
+//! `Content-Length` already exceeds [`MAX_BODY_BYTES`] is rejected with `413`
+//! before any of it is read.
+//!
+//! Uploads to [`STREAMED_PATHS`] are not buffered, as they may be far larger
+//! than [`MAX_BODY_BYTES`]; instead each chunk must arrive within the timeout
+//! of the previous one.
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::header::CONTENT_LENGTH,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use std::time::Duration;
+
+/// Largest body buffered by the middleware, matching axum's default limit
+pub(crate) const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Paths whose handlers consume the body as a stream
+pub const STREAMED_PATHS: &[&str] = &["/users/import"];
+
+/// Middleware enforcing the configured body read timeout
+pub async fn limit_body_read_time(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let timeout = Duration::from_secs(state.config.body_read_timeout_secs);
+    if STREAMED_PATHS.contains(&request.uri().path()) {
+        return next
+            .run(request.map(|body| with_idle_timeout(body, timeout)))
+            .await;
+    }
+    match read_body(request, timeout).await {
+        Ok(request) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Buffer the body of `request`, failing if it takes longer than `timeout`
+async fn read_body(request: Request, timeout: Duration) -> Result<Request, AppError> {
+    if request.body().is_end_stream() {
+        return Ok(request);
+    }
+    if content_length(&request).is_some_and(|len| len > MAX_BODY_BYTES) {
+        return Err(AppError::PayloadTooLarge);
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = tokio::time::timeout(timeout, axum::body::to_bytes(body, MAX_BODY_BYTES))
+        .await
+        .map_err(|_| {
+            tracing::warn!(?timeout, "Timed out reading request body");
+            AppError::RequestTimeout
+        })?
+        .map_err(|_| AppError::PayloadTooLarge)?;
+
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+/// The length advertised in the `Content-Length` header, if valid
+fn content_length(request: &Request) -> Option<usize> {
+    request
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Pass `body` through, failing it if any chunk takes longer than `timeout`
+fn with_idle_timeout(body: Body, timeout: Duration) -> Body {
+    let chunks = stream::unfold(Some(body.into_data_stream()), move |chunks| async move {
+        let mut chunks = chunks?;
+        match tokio::time::timeout(timeout, chunks.next()).await {
+            Ok(Some(chunk)) => Some((chunk, Some(chunks))),
+            Ok(None) => None,
+            Err(elapsed) => {
+                tracing::warn!(?timeout, "Timed out waiting for request body chunk");
+                Some((Err(axum::Error::new(elapsed)), None))
+            }
+        }
+    });
+    Body::from_stream(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, http::StatusCode};
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_slow_body_times_out() {
+        let slow = stream::once(async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            Ok::<_, Infallible>(Bytes::from_static(b"{}"))
+        });
+        let request = Request::post("/users")
+            .body(Body::from_stream(slow))
+            .unwrap();
+
+        let err = read_body(request, Duration::from_millis(20))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.into_response().status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_content_length_is_rejected_before_reading() {
+        // The body never yields, so reading any of it would time out instead
+        let request = Request::post("/users")
+            .header(CONTENT_LENGTH, MAX_BODY_BYTES + 1)
+            .body(Body::from_stream(stream::pending::<
+                Result<Bytes, Infallible>,
+            >()))
+            .unwrap();
+
+        let err = read_body(request, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.into_response().status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_fails_on_idle_chunk() {
+        let chunks = stream::iter([Bytes::from_static(b"first")])
+            .chain(stream::once(async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                Bytes::from_static(b"second")
+            }))
+            .map(Ok::<_, Infallible>);
+        let body = with_idle_timeout(Body::from_stream(chunks), Duration::from_millis(20));
+
+        let mut data = body.into_data_stream();
+        assert_eq!(&data.next().await.unwrap().unwrap()[..], b"first");
+        assert!(data.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prompt_body_is_passed_on() {
+        let request = Request::post("/users")
+            .body(Body::from("{\"name\":\"Ann\"}"))
+            .unwrap();
+
+        let request = read_body(request, Duration::from_secs(1)).await.unwrap();
+
+        let bytes = axum::body::to_bytes(request.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"{\"name\":\"Ann\"}");
+    }
+}
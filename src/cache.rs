@@ -0,0 +1,326 @@
+//! Small in-process caches
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+
+/// A row count cached for a short time
+///
+/// Counting a large table is a full scan, so listings reuse a recent count
+/// instead of recounting on every request. The value is refreshed lazily by
+/// the first caller after it expires; concurrent callers that miss together
+/// may each count once.
+#[derive(Debug)]
+pub struct CountCache {
+    ttl: Duration,
+    entry: Mutex<Option<(Instant, i64)>>,
+}
+
+impl CountCache {
+    /// Create an empty cache whose values stay fresh for `ttl`
+    #[must_use]
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+
+    /// Return the cached count, calling `load` if it is missing or stale
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `load`; nothing is cached in that case
+    pub async fn get_or_load<F, Fut, E>(&self, load: F) -> Result<i64, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<i64, E>>,
+    {
+        if let Some((loaded_at, count)) = *self.lock() {
+            if loaded_at.elapsed() < self.ttl {
+                return Ok(count);
+            }
+        }
+
+        let count = load().await?;
+        *self.lock() = Some((Instant::now(), count));
+        Ok(count)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(Instant, i64)>> {
+        self.entry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Coalesces concurrent identical loads into one
+///
+/// Callers asking for the same key while a load for it is in flight wait for
+/// that load and share its value instead of starting their own. Nothing is
+/// kept once the load completes, so later callers load afresh. Errors are not
+/// shared: if the load fails or is cancelled, one of the waiters runs its own.
+#[derive(Debug)]
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> SingleFlight<K, V> {
+    /// Return the value for `key`, joining a load in flight or running `load`
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `load` when this caller's own load fails
+    pub async fn run<F, Fut, E>(&self, key: K, load: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let cell = Arc::clone(self.lock().entry(key.clone()).or_default());
+        let result = cell.get_or_try_init(load).await.cloned();
+
+        let mut in_flight = self.lock();
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            in_flight.remove(&key);
+        }
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, Arc<OnceCell<V>>>> {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A bounded map evicting its least recently used entry, whose entries
+/// also expire `ttl` after insertion
+///
+/// Not synchronized; owners share it behind a lock.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<K, LruEntry<V>>,
+    /// Keys by the tick of their last use, oldest first
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct LruEntry<V> {
+    value: V,
+    inserted_at: Instant,
+    used_at: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    /// Create an empty cache holding at most `capacity` entries for `ttl`
+    #[must_use]
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The fresh value for `key`, marking it as most recently used
+    ///
+    /// An expired entry is dropped and reported as missing.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get(key)?;
+        if entry.inserted_at.elapsed() >= self.ttl {
+            self.remove(key);
+            return None;
+        }
+        let used_at = entry.used_at;
+        let tick = self.next_tick();
+        self.recency.remove(&used_at);
+        self.recency.insert(tick, key.clone());
+        let entry = self.entries.get_mut(key)?;
+        entry.used_at = tick;
+        Some(entry.value.clone())
+    }
+
+    /// Store `value` for `key`, evicting the least recently used entry if
+    /// the cache is full
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            LruEntry {
+                value,
+                inserted_at: Instant::now(),
+                used_at: tick,
+            },
+        );
+    }
+
+    /// Drop the entry for `key`, if any
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used_at);
+        }
+    }
+
+    /// Drop every entry
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Number of entries, including expired ones not yet dropped
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache holds no entries
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    const fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    async fn load_counting(cache: &CountCache, calls: &AtomicUsize) -> i64 {
+        cache
+            .get_or_load(|| async {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Infallible>(i64::try_from(n).unwrap() + 40)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_count_loaded_once_within_ttl() {
+        let cache = CountCache::new(Duration::from_secs(30));
+        let calls = AtomicUsize::new(0);
+
+        for _ in 0..3 {
+            assert_eq!(load_counting(&cache, &calls).await, 40);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_reloaded_after_ttl() {
+        let cache = CountCache::new(Duration::ZERO);
+        let calls = AtomicUsize::new(0);
+
+        assert_eq!(load_counting(&cache, &calls).await, 40);
+        assert_eq!(load_counting(&cache, &calls).await, 41);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2, Duration::from_secs(30));
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+
+        cache.insert(3, "three");
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("one"));
+        assert_eq!(cache.get(&3), Some("three"));
+
+        cache.insert(1, "uno");
+        cache.remove(&3);
+        assert_eq!(cache.get(&1), Some("uno"));
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_lru_entries_expire() {
+        let mut cache = LruCache::new(2, Duration::ZERO);
+        cache.insert(1, "one");
+
+        assert_eq!(cache.get(&1), None);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_coalesces_concurrent_loads() {
+        let flight = Arc::new(SingleFlight::<i32, String>::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let waiters: Vec<_> = (0..20)
+            .map(|_| {
+                let (flight, calls) = (flight.clone(), calls.clone());
+                tokio::spawn(async move {
+                    flight
+                        .run(7, || async {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok::<_, Infallible>("user 7".to_string())
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        for waiter in waiters {
+            assert_eq!(waiter.await.unwrap(), "user 7");
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(flight.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_loads_afresh_after_completion_and_errors() {
+        let flight = SingleFlight::<i32, usize>::default();
+        let calls = AtomicUsize::new(0);
+        let load = || async { Ok::<_, ()>(calls.fetch_add(1, Ordering::SeqCst)) };
+
+        assert_eq!(flight.run(1, load).await, Ok(0));
+        assert_eq!(flight.run(1, load).await, Ok(1));
+        assert_eq!(flight.run(1, || async { Err("down") }).await, Err("down"));
+        assert_eq!(flight.run(1, load).await, Ok(2));
+    }
+}
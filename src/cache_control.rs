@@ -0,0 +1,37 @@
+//! `Cache-Control` headers
+//!
+//! Reads of user resources carry the configured `GET_CACHE_CONTROL` policy so
+//! CDNs and browsers can cache them when that is acceptable; responses to
+//! every other method are marked `no-store`.
+
+use crate::config::Config;
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+
+/// Policy applied to mutating requests, and the default for reads
+pub const NO_STORE: &str = "no-store";
+
+/// `Cache-Control` header for a cacheable read, per configuration
+#[must_use]
+pub fn for_reads(config: &Config) -> [(header::HeaderName, HeaderValue); 1] {
+    // `Config::validate` has checked the value; fall back defensively anyway
+    let value = HeaderValue::from_str(&config.get_cache_control)
+        .unwrap_or_else(|_| HeaderValue::from_static(NO_STORE));
+    [(header::CACHE_CONTROL, value)]
+}
+
+/// Middleware marking responses to non-`GET`/`HEAD` requests as `no-store`
+pub async fn no_store_for_writes(request: Request, next: Next) -> Response {
+    let is_read = matches!(*request.method(), Method::GET | Method::HEAD);
+    let mut response = next.run(request).await;
+    if !is_read {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static(NO_STORE));
+    }
+    response
+}
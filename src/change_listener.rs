@@ -0,0 +1,45 @@
+//! Listener for `users_changed` notifications
+//!
+//! A trigger on `users` publishes every insert, update and delete on the
+//! [`USERS_CHANGED_CHANNEL`]. When `LISTEN_USER_CHANGES` is set the service
+//! subscribes once the database is ready and logs what it receives, as the
+//! starting point for invalidating caches across instances.
+
+use crate::{
+    repository::{self, USERS_CHANGED_CHANNEL},
+    tasks::TaskRegistry,
+};
+use sqlx::PgPool;
+
+/// Subscribe to user changes and log them from a task in `tasks`
+///
+/// The listener reconnects on its own after connection loss; notifications
+/// sent while disconnected are lost. It stops when `tasks` shuts down.
+///
+/// # Errors
+///
+/// Returns an error if the initial subscription fails
+pub async fn spawn(pool: &PgPool, tasks: &mut TaskRegistry) -> Result<(), sqlx::Error> {
+    let mut listener = repository::listen_user_changes(pool).await?;
+    tracing::info!(
+        channel = USERS_CHANGED_CHANNEL,
+        "Listening for user changes"
+    );
+
+    tasks.spawn("user change listener", |mut shutdown| async move {
+        loop {
+            tokio::select! {
+                () = shutdown.requested() => break,
+                received = listener.recv() => match received {
+                    Ok(notification) => {
+                        tracing::info!(payload = notification.payload(), "User changed");
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "User change listener lost its connection");
+                    }
+                },
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,307 @@
+//! Per-client cap on open connections
+//!
+//! Request limits only act once a request has arrived, so a client can still
+//! exhaust file descriptors by opening connections and sending nothing. With
+//! `MAX_CONN_PER_IP` set, [`serve`] closes a connection as soon as it is
+//! accepted when its peer already holds that many open.
+
+use crate::server_config::ServerConfig;
+use axum::Router;
+use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown, service::TowerToHyperService};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+/// How long to pause accepting after an error such as running out of file
+/// descriptors, which would otherwise recur immediately
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Open connections per client IP, bounded by a maximum
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    max: usize,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionLimit {
+    /// Allow up to `max` open connections per IP; `None` if `max` is `0`,
+    /// meaning no limit
+    #[must_use]
+    pub fn new(max: usize) -> Option<Self> {
+        (max > 0).then(|| Self {
+            max,
+            open: Arc::default(),
+        })
+    }
+
+    /// Count a new connection from `ip`, or `None` if it already has the
+    /// maximum open
+    ///
+    /// The connection counts until the returned guard is dropped.
+    #[must_use]
+    pub fn try_acquire(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = open.entry(ip).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            ip,
+            open: self.open.clone(),
+        })
+    }
+
+    /// Connections currently open from `ip`
+    #[must_use]
+    pub fn open(&self, ip: IpAddr) -> usize {
+        let open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        open.get(&ip).copied().unwrap_or_default()
+    }
+}
+
+/// A connection counted by [`ConnectionLimit::try_acquire`]
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    ip: IpAddr,
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Serve `app` on `listener` until `signal` resolves, then wait for open
+/// connections to finish their requests
+///
+/// The explicit counterpart of [`axum::serve`] with graceful shutdown, which
+/// sees each connection as it is accepted so `limit` can turn it away.
+/// Connections are built with `server`'s timeouts.
+///
+/// # Errors
+///
+/// Never fails at present; the `io::Result` matches [`axum::serve`], as
+/// failures to accept are logged and retried
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    server: ServerConfig,
+    limit: Option<ConnectionLimit>,
+    signal: impl Future<Output = ()> + Send,
+) -> io::Result<()> {
+    let builder = server.builder();
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) if is_connection_error(&e) => continue,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            () = &mut signal => break,
+        };
+        let guard = match &limit {
+            Some(limit) => {
+                let Some(guard) = limit.try_acquire(peer.ip()) else {
+                    // Debug only: under attack this fires for every attempt
+                    tracing::debug!(peer = %peer.ip(), "Closing connection over MAX_CONN_PER_IP");
+                    continue;
+                };
+                Some(guard)
+            }
+            None => None,
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "Connection closed with an error");
+            }
+            drop(guard);
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Whether `e` concerns only the connection being accepted, which is then
+/// simply skipped
+pub(crate) fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::net::Ipv4Addr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+    };
+
+    #[test]
+    fn test_connection_limit_counts_per_ip() {
+        assert!(ConnectionLimit::new(0).is_none());
+        let limit = ConnectionLimit::new(2).unwrap();
+        let a = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+
+        let first = limit.try_acquire(a).unwrap();
+        let _second = limit.try_acquire(a).unwrap();
+        assert!(limit.try_acquire(a).is_none());
+        let _other = limit.try_acquire(b).unwrap();
+
+        drop(first);
+        assert_eq!(limit.open(a), 1);
+        assert!(limit.try_acquire(a).is_some());
+    }
+
+    /// Send a request on `stream` and read until the server closes it
+    async fn request(stream: &mut TcpStream) -> String {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .unwrap_or_default();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_closes_connections_beyond_cap() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limit = ConnectionLimit::new(2).unwrap();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            listener,
+            app,
+            ServerConfig::default(),
+            Some(limit.clone()),
+            async {
+                let _ = stopped.await;
+            },
+        ));
+
+        let mut held = Vec::new();
+        for _ in 0..2 {
+            held.push(TcpStream::connect(addr).await.unwrap());
+        }
+        while limit.open(addr.ip()) < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut excess = TcpStream::connect(addr).await.unwrap();
+        assert!(!request(&mut excess).await.contains("hello"));
+
+        let response = request(&mut held[0]).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        while limit.open(addr.ip()) > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut admitted = TcpStream::connect(addr).await.unwrap();
+        assert!(request(&mut admitted).await.contains("hello"));
+
+        drop(held);
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+
+    /// Serve a hello route with `server` until the returned sender fires
+    async fn spawn_hello(server: ServerConfig) -> (std::net::SocketAddr, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        tokio::spawn(serve(listener, app, server, None, async {
+            let _ = stopped.await;
+        }));
+        (addr, stop)
+    }
+
+    #[tokio::test]
+    async fn test_serve_closes_slow_header_reads() {
+        let server = ServerConfig {
+            header_read_timeout: Some(Duration::from_millis(100)),
+            ..ServerConfig::default()
+        };
+        let (addr, _stop) = spawn_hello(server).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: te")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .expect("slow client was not disconnected")
+            .unwrap_or_default();
+        assert!(!response.contains("hello"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn test_serve_without_keep_alive_closes_after_one_request() {
+        let server = ServerConfig {
+            keep_alive: None,
+            ..ServerConfig::default()
+        };
+        let (addr, _stop) = spawn_hello(server).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .expect("connection was kept alive")
+            .unwrap();
+        assert!(response.contains("hello"), "{response}");
+        assert!(
+            response.to_ascii_lowercase().contains("connection: close"),
+            "{response}"
+        );
+    }
+}
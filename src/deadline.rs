@@ -0,0 +1,150 @@
+//! Request deadlines
+//!
+//! The [`enforce_request_timeout`] middleware gives every request a deadline
+//! of `REQUEST_TIMEOUT_SECS` from arrival, or the route's `ROUTE_TIMEOUTS`
+//! entry, and answers `504` when it passes.
+//! The deadline is also stored in the request extensions so handlers can
+//! bound database work by the time actually remaining, via the [`Deadline`]
+//! extractor and [`crate::repository::with_deadline`].
+
+use crate::{config::Config, error::AppError, state::AppState};
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{convert::Infallible, time::Duration};
+use tokio::time::Instant;
+
+/// Point in time by which the current request must be answered
+///
+/// Extracting it never fails; without the middleware there is no deadline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// A deadline at `at`
+    #[must_use]
+    pub const fn at(at: Instant) -> Self {
+        Self(Some(at))
+    }
+
+    /// No deadline
+    #[must_use]
+    pub const fn none() -> Self {
+        Self(None)
+    }
+
+    /// The instant the deadline expires, if there is one
+    #[must_use]
+    pub const fn instant(self) -> Option<Instant> {
+        self.0
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Deadline {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Self>().copied().unwrap_or_default())
+    }
+}
+
+/// Timeout for requests to `route`: its override, or the global timeout
+fn timeout_for(config: &Config, route: Option<&str>) -> Duration {
+    let secs = route
+        .and_then(|route| {
+            config
+                .route_timeouts
+                .iter()
+                .find(|(pattern, _)| pattern == route)
+        })
+        .map_or(config.request_timeout_secs, |&(_, secs)| secs);
+    Duration::from_secs(secs)
+}
+
+/// Middleware bounding each request by the configured timeout
+pub async fn enforce_request_timeout(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    let timeout = timeout_for(&state.config, route);
+    let at = Instant::now() + timeout;
+    request.extensions_mut().insert(Deadline::at(at));
+
+    if let Ok(response) = tokio::time::timeout_at(at, next.run(request)).await {
+        response
+    } else {
+        tracing::warn!(?timeout, "Request exceeded its deadline");
+        AppError::DeadlineExceeded.into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config, test_state, unreachable_pool};
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn slow() -> &'static str {
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+        "done"
+    }
+
+    #[test]
+    fn test_timeout_for_prefers_route_override() {
+        let config = Config {
+            request_timeout_secs: 30,
+            route_timeouts: vec![("/users/export".to_string(), 90)],
+            ..test_config()
+        };
+
+        assert_eq!(
+            timeout_for(&config, Some("/users/export")),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            timeout_for(&config, Some("/users")),
+            Duration::from_secs(30)
+        );
+        assert_eq!(timeout_for(&config, None), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_route_override_outlasts_global_timeout() {
+        let config = Config {
+            request_timeout_secs: 1,
+            route_timeouts: vec![("/users/export".to_string(), 3)],
+            ..test_config()
+        };
+        let state = test_state(unreachable_pool(), config);
+        let app: Router = Router::new()
+            .route("/users", get(slow))
+            .route("/users/export", get(slow))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                enforce_request_timeout,
+            ))
+            .with_state(state);
+        let status = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(uri).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+
+        let (export, users) = tokio::join!(status("/users/export"), status("/users"));
+
+        assert_eq!(export, StatusCode::OK);
+        assert_eq!(users, StatusCode::GATEWAY_TIMEOUT);
+    }
+}
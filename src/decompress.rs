@@ -0,0 +1,255 @@
+//! Compressed request bodies
+//!
+//! Clients uploading large payloads may gzip them and say so with
+//! `Content-Encoding: gzip`. The middleware here inflates such bodies before
+//! the handler sees them, stopping at `MAX_DECOMPRESSED_BYTES` so a small
+//! upload cannot expand into an unbounded one. Any other encoding but
+//! `identity` is refused with `415`.
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::header::{CONTENT_ENCODING, CONTENT_LENGTH},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, TINFLStatus};
+
+/// Middleware inflating `Content-Encoding: gzip` request bodies
+pub async fn decompress_request_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(encoding) = request.headers().get(CONTENT_ENCODING) else {
+        return next.run(request).await;
+    };
+    let encoding = encoding.to_str().unwrap_or_default().trim();
+    if encoding.eq_ignore_ascii_case("identity") {
+        return next.run(request).await;
+    }
+    if !encoding.eq_ignore_ascii_case("gzip") && !encoding.eq_ignore_ascii_case("x-gzip") {
+        return AppError::UnsupportedMediaType(format!(
+            "unsupported Content-Encoding: {encoding}; only gzip is accepted"
+        ))
+        .into_response();
+    }
+
+    let max = state.config.max_decompressed_bytes;
+    let (mut parts, body) = request.into_parts();
+    let Ok(compressed) = axum::body::to_bytes(body, max).await else {
+        return AppError::PayloadTooLarge.into_response();
+    };
+    let body = match gunzip(&compressed, max) {
+        Ok(body) => body,
+        Err(e) => {
+            if matches!(e, AppError::PayloadTooLarge) {
+                tracing::warn!(
+                    max,
+                    "Rejected gzip body inflating past MAX_DECOMPRESSED_BYTES"
+                );
+            }
+            return e.into_response();
+        }
+    };
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, body.len().into());
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Header flags of a gzip member, RFC 1952 section 2.3.1
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Inflate a single gzip member, producing at most `max` bytes
+///
+/// # Errors
+///
+/// Returns [`AppError::PayloadTooLarge`] if the content exceeds `max`, or
+/// [`AppError::BadRequest`] if `data` is not valid gzip
+fn gunzip(data: &[u8], max: usize) -> Result<Vec<u8>, AppError> {
+    let invalid = |reason: &str| AppError::BadRequest(format!("invalid gzip body: {reason}"));
+    if data.len() < 18 || data[..2] != [0x1f, 0x8b] {
+        return Err(invalid("missing gzip header"));
+    }
+    if data[2] != 8 {
+        return Err(invalid("unsupported compression method"));
+    }
+    let flags = data[3];
+    let (deflated, trailer) = data.split_at(data.len() - 8);
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = deflated
+            .get(pos..pos + 2)
+            .map(|len| usize::from(u16::from_le_bytes([len[0], len[1]])))
+            .ok_or_else(|| invalid("truncated header"))?;
+        pos += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = deflated
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or_else(|| invalid("truncated header"))?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let deflated = deflated
+        .get(pos..)
+        .ok_or_else(|| invalid("truncated header"))?;
+
+    let body = decompress_to_vec_with_limit(deflated, max).map_err(|e| match e.status {
+        TINFLStatus::HasMoreOutput => AppError::PayloadTooLarge,
+        _ => invalid("corrupt deflate stream"),
+    })?;
+
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    // ISIZE is the length modulo 2^32, so the truncation is intended
+    #[allow(clippy::cast_possible_truncation)]
+    let expected_size = body.len() as u32;
+    if crc32(&body) != crc || size != expected_size {
+        return Err(invalid("checksum mismatch"));
+    }
+    Ok(body)
+}
+
+/// CRC-32 (IEEE) lookup table for [`crc32`]
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ 0xEDB8_8320
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 of `data`, as stored in a gzip trailer
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[usize::from(crc.to_le_bytes()[0] ^ byte)] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        models::User,
+        test_utils::{admin, test_config, test_pool, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::to_bytes,
+        http::{header::CONTENT_TYPE, StatusCode},
+        Router,
+    };
+    use miniz_oxide::deflate::compress_to_vec;
+    use tower::ServiceExt;
+
+    /// `data` as a minimal gzip member
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        out.extend(compress_to_vec(data, 6));
+        out.extend(crc32(data).to_le_bytes());
+        out.extend(u32::try_from(data.len()).unwrap().to_le_bytes());
+        out
+    }
+
+    async fn post(app: Router, encoding: &str, body: Vec<u8>) -> (StatusCode, Vec<u8>) {
+        let request = Request::post("/users")
+            .extension(admin())
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, encoding)
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[test]
+    fn test_crc32_matches_reference() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_gunzip_round_trip_and_corruption() {
+        let data = b"{\"name\":\"Ann\"}".repeat(10);
+        assert_eq!(gunzip(&gzip(&data), 1024).unwrap(), data);
+
+        let mut corrupt = gzip(&data);
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 0xff;
+        assert!(matches!(
+            gunzip(&corrupt, 1024),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            gunzip(b"not gzip at all!!!", 1024),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gzipped_create_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = build_app(test_state(pool, test_config()));
+        let body = gzip(br#"{"name":"Zip","email":"zip@example.com"}"#);
+
+        let (status, body) = post(app, "gzip", body).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        let user: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.email, "zip@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_body_inflating_past_limit() {
+        let config = Config {
+            max_decompressed_bytes: 1024,
+            ..test_config()
+        };
+        let app = build_app(test_state(unreachable_pool(), config));
+        // Zeros compress to a few bytes, as a zip bomb's payload would
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 2048);
+
+        let (status, _) = post(app, "gzip", bomb).await;
+
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unsupported_encoding() {
+        let app = build_app(test_state(unreachable_pool(), test_config()));
+
+        let (status, body) = post(app, "br", b"{}".to_vec()).await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(String::from_utf8_lossy(&body).contains("Content-Encoding: br"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Operator-disabled routes
+//!
+//! Routes listed in the comma-separated `DISABLED_ROUTES` variable answer
+//! `404` as if they did not exist. An entry is a route pattern as registered
+//! in the router, optionally preceded by a method: `POST /users` disables
+//! only creation, `/admin/purge` disables every method. A disabled `GET`
+//! also disables `HEAD`.
+
+use crate::state::AppState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::fmt;
+
+/// Methods accepted in an entry
+const METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// One `DISABLED_ROUTES` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisabledRoute {
+    /// Method to disable; `None` disables all of them
+    pub method: Option<Method>,
+    /// Route pattern, e.g. `/users/:id`
+    pub path: String,
+}
+
+impl DisabledRoute {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let method = if method == Method::HEAD {
+            &Method::GET
+        } else {
+            method
+        };
+        self.path == path && self.method.as_ref().is_none_or(|m| m == method)
+    }
+}
+
+impl fmt::Display for DisabledRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{method} {}", self.path),
+            None => f.write_str(&self.path),
+        }
+    }
+}
+
+/// Parse a comma-separated list of `[METHOD] /pattern` entries
+///
+/// # Errors
+///
+/// Returns the first entry that is not a path, optionally preceded by a
+/// known method
+pub fn parse(value: &str) -> Result<Vec<DisabledRoute>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (method, path) = match entry.split_once(char::is_whitespace) {
+                Some((method, path)) => {
+                    let method = method.to_ascii_uppercase();
+                    if !METHODS.contains(&method.as_str()) {
+                        return Err(entry.to_string());
+                    }
+                    let method =
+                        Method::from_bytes(method.as_bytes()).map_err(|_| entry.to_string())?;
+                    (Some(method), path.trim())
+                }
+                None => (None, entry),
+            };
+            if !path.starts_with('/') {
+                return Err(entry.to_string());
+            }
+            Ok(DisabledRoute {
+                method,
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Middleware answering `404` for routes disabled in the configuration
+pub async fn reject_disabled(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let disabled = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|path| {
+            state
+                .config
+                .disabled_routes
+                .iter()
+                .any(|route| route.matches(request.method(), path.as_str()))
+        });
+    if disabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_parse_entries() {
+        let routes = parse(" post /users, /admin/purge ,").unwrap();
+        assert_eq!(
+            routes,
+            [
+                DisabledRoute {
+                    method: Some(Method::POST),
+                    path: "/users".to_string(),
+                },
+                DisabledRoute {
+                    method: None,
+                    path: "/admin/purge".to_string(),
+                },
+            ]
+        );
+        assert_eq!(routes[0].to_string(), "POST /users");
+
+        assert_eq!(parse("FETCH /users").unwrap_err(), "FETCH /users");
+        assert_eq!(parse("users").unwrap_err(), "users");
+    }
+
+    async fn status(config: Config, method: Method, uri: &str) -> StatusCode {
+        let app = build_app(test_state(unreachable_pool(), config));
+        app.oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn test_disabled_route_is_not_found() {
+        let config = Config {
+            disabled_routes: parse("POST /users, /health/startup").unwrap(),
+            ..test_config()
+        };
+
+        let created = status(config.clone(), Method::POST, "/users").await;
+        assert_eq!(created, StatusCode::NOT_FOUND);
+        let probed = status(config.clone(), Method::GET, "/health/startup").await;
+        assert_eq!(probed, StatusCode::NOT_FOUND);
+
+        let health = status(config.clone(), Method::GET, "/health").await;
+        assert_eq!(health, StatusCode::OK);
+        let metrics = status(config, Method::GET, "/metrics").await;
+        assert_eq!(metrics, StatusCode::OK);
+    }
+}
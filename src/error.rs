@@ -2,22 +2,90 @@
 //!
 //! This module provides custom error types using thiserror for better error handling.
 
+use crate::{
+    config::ErrorDetail,
+    repository::{QUERY_CANCELED, SERIALIZATION_FAILURE, TOO_MANY_CONNECTIONS, UNIQUE_VIOLATION},
+    state::AppState,
+};
 use axum::{
+    extract::{Request, State},
     http::StatusCode,
+    middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
+use sqlx::error::DatabaseError;
+use std::fmt;
 use thiserror::Error;
 
 /// Application-specific error types
-#[allow(dead_code)]
 #[derive(Error, Debug)]
 pub enum AppError {
     /// Database-related errors
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
+    /// Requested resource does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// The request clashes with an existing resource
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// Input rejected before reaching the database
+    #[error("Validation error: {0}")]
+    Validation(String),
+
+    /// The request itself is malformed
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
+    /// The client did not send the request body in time
+    #[error("Request timeout")]
+    RequestTimeout,
+
+    /// The request's deadline passed before it could be answered
+    #[error("Deadline exceeded")]
+    DeadlineExceeded,
+
+    /// The request body exceeds the accepted size
+    #[error("Payload too large")]
+    PayloadTooLarge,
+
+    /// The request target exceeds the accepted length
+    #[error("URI too long")]
+    UriTooLong,
+
+    /// The request body is in a format or encoding the service cannot read
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    /// Missing or invalid credentials
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    /// The caller may not perform this action in the current deployment
+    #[error("Forbidden")]
+    Forbidden,
+
+    /// The request was shed because the service is saturated
+    #[error("Service overloaded")]
+    Overloaded,
+
+    /// The `users.id` sequence has run past the range of its column
+    #[error("User ids exhausted; migrate users.id to bigint")]
+    IdsExhausted,
+
+    /// The request issued more queries than `MAX_QUERIES_PER_REQUEST`
+    /// allows; only raised outside production
+    #[error("Query budget of {budget} exceeded")]
+    QueryBudgetExceeded {
+        /// The configured budget
+        budget: usize,
+    },
+
     /// Configuration errors
     #[error("Configuration error: {0}")]
     Config(String),
@@ -27,34 +95,540 @@ pub enum AppError {
     Internal(String),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            Self::Database(ref e) => {
-                tracing::error!("Database error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+impl AppError {
+    /// Build a [`AppError::Validation`] from `(field, message)` pairs
+    ///
+    /// Each pair reads as a sentence, like the messages built by hand
+    /// elsewhere (`name must not be empty`); pairs are joined with `; ` in
+    /// the order given.
+    pub fn from_validation_errors<I, F, M>(errors: I) -> Self
+    where
+        I: IntoIterator<Item = (F, M)>,
+        F: fmt::Display,
+        M: fmt::Display,
+    {
+        let message = errors
+            .into_iter()
+            .map(|(field, message)| format!("{field} {message}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Self::Validation(message)
+    }
+
+    /// Short machine-readable code identifying the kind of error
+    ///
+    /// Database errors use the code assigned by [`classify_db_error`]; a
+    /// timed-out pool checkout is `database_busy`.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Database(sqlx::Error::PoolTimedOut) => "database_busy",
+            Self::Database(e) => classify_db_error(e).2,
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Validation(_) => "validation_error",
+            Self::BadRequest(_) => "bad_request",
+            Self::RequestTimeout => "request_timeout",
+            Self::DeadlineExceeded => "deadline_exceeded",
+            Self::PayloadTooLarge => "payload_too_large",
+            Self::UriTooLong => "uri_too_long",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::Unauthorized => "unauthorized",
+            Self::Forbidden => "forbidden",
+            Self::Overloaded => "overloaded",
+            Self::IdsExhausted => "ids_exhausted",
+            Self::QueryBudgetExceeded { .. } => "query_budget_exceeded",
+            Self::Config(_) => "config_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Status and client-facing message of the error's response
+    ///
+    /// Database and internal errors get generic text; their underlying
+    /// message is only logged.
+    #[must_use]
+    pub fn status_and_message(&self) -> (StatusCode, &str) {
+        match self {
+            Self::Database(sqlx::Error::PoolTimedOut) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Database busy")
+            }
+            Self::Database(e) => {
+                let (status, message, _) = classify_db_error(e);
+                (status, message)
             }
-            Self::Config(ref msg) => {
-                tracing::error!("Configuration error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Configuration error")
+            Self::NotFound(_) => (StatusCode::NOT_FOUND, "Resource not found"),
+            Self::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            Self::Validation(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+            Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            Self::RequestTimeout => (StatusCode::REQUEST_TIMEOUT, "Request timeout"),
+            Self::DeadlineExceeded => (StatusCode::GATEWAY_TIMEOUT, "Deadline exceeded"),
+            Self::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large"),
+            Self::UriTooLong => (StatusCode::URI_TOO_LONG, "URI too long"),
+            Self::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            Self::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            Self::Forbidden => (StatusCode::FORBIDDEN, "Forbidden"),
+            Self::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, "Service overloaded"),
+            Self::IdsExhausted => (StatusCode::INTERNAL_SERVER_ERROR, "User ids exhausted"),
+            Self::QueryBudgetExceeded { .. } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Request exceeded MAX_QUERIES_PER_REQUEST; look for queries issued in a loop",
+            ),
+            Self::Config(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Configuration error"),
+            Self::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+    }
+
+    /// Log the error as its response is built, at a level matching its
+    /// severity; client errors are not logged
+    pub(crate) fn log(&self) {
+        match self {
+            Self::Database(sqlx::Error::PoolTimedOut) => {
+                tracing::warn!("Timed out waiting for a database connection");
             }
-            Self::Internal(ref msg) => {
-                tracing::error!("Internal error: {}", msg);
-                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            Self::Database(e) => log_db_error(e, classify_db_error(e).2),
+            Self::IdsExhausted => tracing::error!(
+                "users.id is out of range; migrate the column and its sequence to bigint"
+            ),
+            Self::QueryBudgetExceeded { budget } => {
+                tracing::error!(budget, "Request exceeded MAX_QUERIES_PER_REQUEST");
             }
+            Self::Config(msg) => tracing::error!("Configuration error: {}", msg),
+            Self::Internal(msg) => tracing::error!("Internal error: {}", msg),
+            _ => {}
+        }
+    }
+}
+
+/// Code of the [`AppError`] a response was built from
+///
+/// Attached to every error response as an extension, so middleware such as
+/// the metrics layer can tell error kinds apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub &'static str);
+
+/// Underlying message of an error whose body shows only generic text
+///
+/// Attached to the response as an extension, for [`expose_error_detail`].
+#[derive(Debug, Clone)]
+struct Detail {
+    error: &'static str,
+    detail: String,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let code = ErrorCode(self.code());
+        let detail = match &self {
+            Self::Database(sqlx::Error::RowNotFound) => None,
+            Self::Database(e) => Some(("Database error", e.to_string())),
+            Self::Internal(msg) => Some(("Internal server error", msg.clone())),
+            _ => None,
         };
+        self.log();
+        let (status, error_message) = self.status_and_message();
 
         let body = Json(json!({
             "error": error_message,
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        response.extensions_mut().insert(code);
+        if let Some((error, detail)) = detail {
+            response.extensions_mut().insert(Detail { error, detail });
+        }
+        response
+    }
+}
+
+/// Map a database error to its response status, client message and a short
+/// machine-readable code
+///
+/// | SQLSTATE | Meaning | Status |
+/// |----------|---------|--------|
+/// | `23505` | unique violation | `409` |
+/// | `40001` | serialization failure | `409` |
+/// | `53300` | too many connections | `503` |
+/// | `57014` | query cancelled, e.g. `statement_timeout` | `503` |
+///
+/// [`sqlx::Error::RowNotFound`], from a `fetch_one` that matched nothing,
+/// means the resource is missing and is `404`. Anything else, including
+/// other errors without a SQLSTATE, is `500`.
+#[must_use]
+pub fn classify_db_error(err: &sqlx::Error) -> (StatusCode, &'static str, &'static str) {
+    if matches!(err, sqlx::Error::RowNotFound) {
+        return (StatusCode::NOT_FOUND, "Resource not found", "not_found");
+    }
+    let code = err.as_database_error().and_then(DatabaseError::code);
+    match code.as_deref() {
+        Some(UNIQUE_VIOLATION) => (
+            StatusCode::CONFLICT,
+            "Resource already exists",
+            "unique_violation",
+        ),
+        Some(SERIALIZATION_FAILURE) => (
+            StatusCode::CONFLICT,
+            "Conflicting concurrent update; retry the request",
+            "serialization_failure",
+        ),
+        Some(TOO_MANY_CONNECTIONS) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Database unavailable",
+            "too_many_connections",
+        ),
+        Some(QUERY_CANCELED) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Database timeout",
+            "statement_timeout",
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Database error",
+            "database_error",
+        ),
+    }
+}
+
+/// Log a database error classified as `code` by [`classify_db_error`]
+fn log_db_error(err: &sqlx::Error, code: &str) {
+    match code {
+        "too_many_connections" => tracing::error!(
+            "Database refused the connection: too many connections; raise the server's \
+             max_connections or lower the connections opened by clients"
+        ),
+        "database_error" => tracing::error!("Database error: {:?}", err),
+        "not_found" => tracing::debug!(error = %err, "Database row not found"),
+        _ => tracing::warn!(code, error = %err, "Database rejected the request"),
+    }
+}
+
+/// Middleware adding the underlying message of database and internal errors
+/// to the response body as `detail` when `ERROR_DETAIL` is `full`
+pub async fn expose_error_detail(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let Some(Detail { error, detail }) = response.extensions_mut().remove::<Detail>() else {
+        return response;
+    };
+    if state.config.error_detail == ErrorDetail::Minimal {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    let body = Json(json!({ "error": error, "detail": detail }));
+    (parts, body).into_response()
+}
+
+/// Check the status and body every [`AppError`] variant responds with
+///
+/// The expected contract is written out per variant in an exhaustive match,
+/// so adding a variant does not compile until its response is specified here.
+///
+/// # Panics
+///
+/// Panics if a variant's response deviates from the contract
+#[cfg(test)]
+pub async fn self_test() {
+    fn expected(err: &AppError) -> (StatusCode, &str) {
+        match err {
+            AppError::Database(sqlx::Error::PoolTimedOut) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Database busy")
+            }
+            // The per-SQLSTATE contract is pinned by the tests of classify_db_error
+            AppError::Database(e) => {
+                let (status, message, _) = classify_db_error(e);
+                (status, message)
+            }
+            AppError::NotFound(_) => (StatusCode::NOT_FOUND, "Resource not found"),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::Validation(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::RequestTimeout => (StatusCode::REQUEST_TIMEOUT, "Request timeout"),
+            AppError::DeadlineExceeded => (StatusCode::GATEWAY_TIMEOUT, "Deadline exceeded"),
+            AppError::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large"),
+            AppError::UriTooLong => (StatusCode::URI_TOO_LONG, "URI too long"),
+            AppError::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden"),
+            AppError::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, "Service overloaded"),
+            AppError::IdsExhausted => (StatusCode::INTERNAL_SERVER_ERROR, "User ids exhausted"),
+            AppError::QueryBudgetExceeded { .. } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Request exceeded MAX_QUERIES_PER_REQUEST; look for queries issued in a loop",
+            ),
+            AppError::Config(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Configuration error"),
+            AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+    }
+
+    let variants = [
+        AppError::Database(sqlx::Error::RowNotFound),
+        AppError::Database(sqlx::Error::PoolTimedOut),
+        AppError::NotFound("user 1".to_string()),
+        AppError::Conflict("email is already registered".to_string()),
+        AppError::Validation("name is too long".to_string()),
+        AppError::BadRequest("Digest header is not valid text".to_string()),
+        AppError::RequestTimeout,
+        AppError::DeadlineExceeded,
+        AppError::PayloadTooLarge,
+        AppError::UriTooLong,
+        AppError::UnsupportedMediaType("unsupported Content-Encoding: br".to_string()),
+        AppError::Unauthorized,
+        AppError::Forbidden,
+        AppError::Overloaded,
+        AppError::IdsExhausted,
+        AppError::QueryBudgetExceeded { budget: 3 },
+        AppError::Config("missing key".to_string()),
+        AppError::Internal("boom".to_string()),
+    ];
+
+    for err in variants {
+        let (status, message) = expected(&err);
+        let (status, message) = (status, message.to_string());
+        let label = format!("{err:?}");
+        let code = ErrorCode(err.code());
+
+        let response = err.into_response();
+        assert_eq!(response.status(), status, "{label}");
+        assert_eq!(response.extensions().get(), Some(&code), "{label}");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, json!({ "error": message }), "{label}");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        config::Config,
+        test_utils::{capture_logs, test_config, test_pool, test_state, unreachable_pool},
+    };
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    async fn internal_error_body(error_detail: ErrorDetail) -> serde_json::Value {
+        let config = Config {
+            error_detail,
+            ..test_config()
+        };
+        let state = test_state(unreachable_pool(), config);
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { AppError::Internal("cache poisoned".to_string()) }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                expose_error_detail,
+            ))
+            .with_state(state);
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_from_validation_errors_joins_fields_in_order() {
+        let err = AppError::from_validation_errors([
+            ("name", "must not be empty"),
+            ("email", "must contain '@'"),
+        ]);
+
+        match err {
+            AppError::Validation(msg) => {
+                assert_eq!(msg, "name must not be empty; email must contain '@'");
+            }
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_minimal_error_detail_hides_message() {
+        let body = internal_error_body(ErrorDetail::Minimal).await;
+        assert_eq!(body, json!({ "error": "Internal server error" }));
+    }
+
+    #[tokio::test]
+    async fn test_full_error_detail_includes_message() {
+        let body = internal_error_body(ErrorDetail::Full).await;
+        assert_eq!(
+            body,
+            json!({ "error": "Internal server error", "detail": "cache poisoned" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_every_variant_matches_response_contract() {
+        self_test().await;
+    }
+
+    #[tokio::test]
+    async fn test_too_many_connections_is_unavailable() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let err = sqlx::raw_sql(
+            "DO $$ BEGIN RAISE EXCEPTION 'sorry, too many clients already' \
+             USING ERRCODE = '53300'; END $$",
+        )
+        .execute(&pool)
+        .await
+        .unwrap_err();
+        let (logs, _guard) = capture_logs();
+
+        let response = AppError::Database(err).into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, json!({ "error": "Database unavailable" }));
+        assert!(logs
+            .contents()
+            .contains("Database refused the connection: too many connections"));
+    }
+
+    /// A database error carrying only a SQLSTATE
+    #[derive(Debug)]
+    struct SqlState(&'static str);
+
+    impl fmt::Display for SqlState {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "SQLSTATE {}", self.0)
+        }
+    }
+
+    impl std::error::Error for SqlState {}
+
+    impl DatabaseError for SqlState {
+        fn message(&self) -> &'static str {
+            "simulated"
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    fn classify(code: &'static str) -> (StatusCode, &'static str, &'static str) {
+        classify_db_error(&sqlx::Error::Database(Box::new(SqlState(code))))
+    }
+
+    #[test]
+    fn test_classify_unique_violation() {
+        assert_eq!(
+            classify("23505"),
+            (
+                StatusCode::CONFLICT,
+                "Resource already exists",
+                "unique_violation"
+            )
+        );
+    }
+
+    #[test]
+    fn test_classify_serialization_failure() {
+        assert_eq!(
+            classify("40001"),
+            (
+                StatusCode::CONFLICT,
+                "Conflicting concurrent update; retry the request",
+                "serialization_failure"
+            )
+        );
+    }
+
+    #[test]
+    fn test_classify_too_many_connections() {
+        assert_eq!(
+            classify("53300"),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Database unavailable",
+                "too_many_connections"
+            )
+        );
+    }
+
+    #[test]
+    fn test_classify_statement_timeout() {
+        assert_eq!(
+            classify("57014"),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Database timeout",
+                "statement_timeout"
+            )
+        );
+    }
+
+    #[test]
+    fn test_classify_defaults_to_internal_error() {
+        let expected = (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Database error",
+            "database_error",
+        );
+        assert_eq!(classify("42P01"), expected);
+        assert_eq!(
+            classify_db_error(&sqlx::Error::PoolClosed).0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_row_not_found_is_not_found() {
+        assert_eq!(
+            classify_db_error(&sqlx::Error::RowNotFound),
+            (StatusCode::NOT_FOUND, "Resource not found", "not_found")
+        );
+        let err = AppError::Database(sqlx::Error::RowNotFound);
+        assert_eq!(err.code(), "not_found");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.extensions().get::<ErrorCode>(),
+            Some(&ErrorCode("not_found"))
+        );
+    }
+
+    #[test]
+    fn test_database_error_response_uses_classification() {
+        let err = sqlx::Error::Database(Box::new(SqlState("57014")));
+        let response = AppError::Database(err).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
 
     #[test]
     fn test_error_display() {
@@ -62,6 +636,18 @@ mod tests {
         assert_eq!(err.to_string(), "Configuration error: missing key");
     }
 
+    #[test]
+    fn test_not_found_status() {
+        let response = AppError::NotFound("user 1".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_validation_status() {
+        let response = AppError::Validation("name is too long".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
     #[test]
     fn test_internal_error() {
         let err = AppError::Internal("test error".to_string());
@@ -0,0 +1,59 @@
+//! Feature flags
+//!
+//! Features named in the comma-separated `FEATURES` variable are enabled;
+//! everything else is off. Handlers for a disabled feature answer as if the
+//! route did not exist.
+
+use crate::state::AppState;
+use std::collections::BTreeSet;
+
+/// Bulk NDJSON import via `POST /users/import`
+pub const USER_IMPORT: &str = "user_import";
+
+/// Parse a comma-separated list of flag names, ignoring blanks
+#[must_use]
+pub fn parse(value: &str) -> BTreeSet<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `feature` is enabled in the running configuration
+#[must_use]
+pub fn is_enabled(state: &AppState, feature: &str) -> bool {
+    state.config.features.contains(feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+
+    #[test]
+    fn test_parse_ignores_whitespace_and_blanks() {
+        let flags = parse(" user_import, ,beta ,");
+        assert_eq!(
+            flags,
+            BTreeSet::from(["beta".to_string(), "user_import".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_enabled() {
+        let state = test_state(unreachable_pool(), test_config());
+        assert!(!is_enabled(&state, USER_IMPORT));
+
+        let config = Config {
+            features: parse(USER_IMPORT),
+            ..test_config()
+        };
+        let state = test_state(unreachable_pool(), config);
+        assert!(is_enabled(&state, USER_IMPORT));
+    }
+}
@@ -0,0 +1,144 @@
+//! JSON request body extractor with a nesting limit
+//!
+//! `serde_json` recurses once per level of nesting, so a body made of
+//! thousands of `[` can exhaust the stack. [`JsonBody`] scans the raw body
+//! first and rejects anything nested deeper than `JSON_MAX_DEPTH` with a
+//! validation error; otherwise it behaves exactly like [`axum::Json`].
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Request},
+    response::{IntoResponse, Response},
+};
+use serde::de::DeserializeOwned;
+
+/// JSON body extractor rejecting documents nested deeper than the configured
+/// `JSON_MAX_DEPTH`
+#[derive(Debug, Clone)]
+pub struct JsonBody<T>(pub T);
+
+#[axum::async_trait]
+impl<T: DeserializeOwned> FromRequest<AppState> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &AppState) -> Result<Self, Self::Rejection> {
+        let (parts, body) = request.into_parts();
+        let bytes = Bytes::from_request(Request::from_parts(parts.clone(), body), state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let max = state.config.json_max_depth;
+        if exceeds_depth(&bytes, max) {
+            return Err(AppError::Validation(format!(
+                "JSON body is nested deeper than {max} levels"
+            ))
+            .into_response());
+        }
+
+        let request = Request::from_parts(parts, Body::from(bytes));
+        let axum::Json(value) = axum::Json::<T>::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(value))
+    }
+}
+
+/// Whether arrays and objects in `json` nest deeper than `max`
+///
+/// Brackets inside strings are skipped; the input need not be valid JSON.
+fn exceeds_depth(json: &[u8], max: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::{http::StatusCode, routing::post, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn nested(depth: usize) -> String {
+        format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+    }
+
+    async fn post_json(body: String) -> (StatusCode, Value) {
+        let config = Config {
+            json_max_depth: 32,
+            ..test_config()
+        };
+        let state = test_state(unreachable_pool(), config);
+        let app = Router::new()
+            .route(
+                "/",
+                post(|JsonBody(value): JsonBody<Value>| async move { axum::Json(value) }),
+            )
+            .with_state(state);
+        let request = Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn test_exceeds_depth_ignores_brackets_in_strings() {
+        assert!(!exceeds_depth(br#"{"a": "[[[[\"{{{{"}"#, 1));
+        assert!(exceeds_depth(br#"{"a": [1]}"#, 1));
+        assert!(!exceeds_depth(br#"{"a": [1]}"#, 2));
+    }
+
+    #[tokio::test]
+    async fn test_nesting_at_limit_is_accepted() {
+        let (status, value) = post_json(nested(32)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(value.to_string(), nested(32));
+    }
+
+    #[tokio::test]
+    async fn test_nesting_past_limit_is_rejected() {
+        let (status, body) = post_json(nested(33)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"], "JSON body is nested deeper than 32 levels");
+
+        let (status, _) = post_json(nested(100_000)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,263 @@
+//! JWT bearer authentication
+//!
+//! With `JWT_SECRET` set, [`authenticate`] checks the HS256 signature,
+//! expiry and, with `JWT_ISSUER`, the issuer of an `Authorization: Bearer`
+//! token, then puts its [`Claims`] and the [`Principal`] they grant into the
+//! request extensions, where [`RequireRole`](crate::auth::RequireRole)
+//! finds it. Requests without a bearer token fall through to API key
+//! authentication; a token that fails validation gets `401`.
+
+use crate::{
+    auth::{Principal, Role},
+    error::AppError,
+    state::AppState,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+
+/// Role name in the `roles` claim that grants [`Role::Admin`]
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Claims of a validated bearer token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// Subject the token was issued to
+    pub sub: String,
+    /// Role names granted to the subject
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Claims {
+    /// The caller these claims describe: an admin with the
+    /// [`ADMIN_ROLE`] role, a reader otherwise
+    #[must_use]
+    pub fn principal(&self) -> Principal {
+        let role = if self.roles.iter().any(|role| role == ADMIN_ROLE) {
+            Role::Admin
+        } else {
+            Role::Reader
+        };
+        Principal { role }
+    }
+}
+
+/// Validate `token` against `secret` and, if given, `issuer`
+///
+/// The token must be signed with HS256 and carry `sub` and an unexpired
+/// `exp`.
+///
+/// # Errors
+///
+/// Returns the reason validation failed, e.g. a bad signature or expiry
+pub fn decode_claims(
+    token: &str,
+    secret: &str,
+    issuer: Option<&str>,
+) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_required_spec_claims(&["exp", "sub"]);
+    if let Some(issuer) = issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map(|data| data.claims)
+}
+
+/// The token of an `Authorization: Bearer` header, if there is one
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Middleware authenticating `Authorization: Bearer` tokens when
+/// `JWT_SECRET` is set
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(secret) = state.config.jwt_secret.as_deref() else {
+        return next.run(request).await;
+    };
+    let Some(token) = bearer_token(request.headers()) else {
+        return next.run(request).await;
+    };
+    match decode_claims(token, secret, state.config.jwt_issuer.as_deref()) {
+        Ok(claims) => {
+            request.extensions_mut().insert(claims.principal());
+            request.extensions_mut().insert(claims);
+            next.run(request).await
+        }
+        Err(e) => {
+            tracing::debug!(error = %e, "Rejected bearer token");
+            let mut response = AppError::Unauthorized.into_response();
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer error=\"invalid_token\""),
+            );
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{AdminRole, RequireRole},
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+        middleware,
+        routing::{get, post},
+        Extension, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    const SECRET: &str = "test-secret";
+
+    fn app(config: Config) -> Router {
+        let state = test_state(unreachable_pool(), config);
+        Router::new()
+            .route(
+                "/whoami",
+                get(|claims: Option<Extension<Claims>>| async move {
+                    claims.map_or_else(|| "anonymous".to_string(), |Extension(c)| c.sub)
+                }),
+            )
+            .route("/admin", post(|_: RequireRole<AdminRole>| async {}))
+            .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+            .with_state(state)
+    }
+
+    fn jwt_config() -> Config {
+        Config {
+            jwt_secret: Some(SECRET.to_string()),
+            jwt_issuer: Some("issuer".to_string()),
+            ..test_config()
+        }
+    }
+
+    fn token(secret: &str, claims: &Value) -> String {
+        encode(
+            &Header::default(),
+            claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn claims(exp_offset: i64) -> Value {
+        json!({
+            "sub": "alice",
+            "roles": ["admin"],
+            "iss": "issuer",
+            "exp": chrono::Utc::now().timestamp() + exp_offset,
+        })
+    }
+
+    async fn send(app: Router, request: Request) -> (StatusCode, String, Option<HeaderValue>) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let challenge = response.headers().get(header::WWW_AUTHENTICATE).cloned();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            String::from_utf8(bytes.to_vec()).unwrap(),
+            challenge,
+        )
+    }
+
+    fn bearer(method: &str, uri: &str, token: &str) -> Request {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_valid_token_sets_claims_and_role() {
+        let admin = token(SECRET, &claims(3600));
+
+        let (status, body, _) = send(app(jwt_config()), bearer("GET", "/whoami", &admin)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "alice");
+        let (status, _, _) = send(app(jwt_config()), bearer("POST", "/admin", &admin)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut reader = claims(3600);
+        reader["roles"] = json!(["viewer"]);
+        let reader = token(SECRET, &reader);
+        let (status, _, _) = send(app(jwt_config()), bearer("POST", "/admin", &reader)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_unauthorized() {
+        let expired = token(SECRET, &claims(-3600));
+
+        let (status, body, challenge) =
+            send(app(jwt_config()), bearer("GET", "/whoami", &expired)).await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({ "error": "Unauthorized" })
+        );
+        assert_eq!(
+            challenge.unwrap(),
+            HeaderValue::from_static("Bearer error=\"invalid_token\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bad_signature_or_issuer_is_unauthorized() {
+        let forged = token("other-secret", &claims(3600));
+        let (status, _, _) = send(app(jwt_config()), bearer("GET", "/whoami", &forged)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut foreign = claims(3600);
+        foreign["iss"] = json!("someone-else");
+        let foreign = token(SECRET, &foreign);
+        let (status, _, _) = send(app(jwt_config()), bearer("GET", "/whoami", &foreign)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _, _) = send(app(jwt_config()), bearer("GET", "/whoami", "garbage")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_without_secret() {
+        let admin = token(SECRET, &claims(3600));
+
+        let (status, body, _) = send(app(test_config()), bearer("GET", "/whoami", "garbage")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+        let (status, _, _) = send(app(test_config()), bearer("POST", "/admin", &admin)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let no_token = Request::get("/whoami").body(Body::empty()).unwrap();
+        let (status, body, _) = send(app(jwt_config()), no_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "anonymous");
+    }
+}
@@ -0,0 +1,523 @@
+//! Rust Basic API
+//!
+//! A production-ready REST API built with Axum framework.
+//!
+//! The binary is a thin wrapper around [`run`]; the modules are public so the
+//! service can be embedded and exercised from tests.
+
+pub mod access_log;
+pub mod auth;
+pub mod body_digest;
+pub mod body_timeout;
+pub mod cache;
+pub mod cache_control;
+pub mod change_listener;
+pub mod config;
+pub mod conn_limit;
+pub mod deadline;
+pub mod decompress;
+pub mod disabled_routes;
+pub mod error;
+pub mod features;
+pub mod json_body;
+pub mod jwt;
+pub mod load_shed;
+pub mod logging;
+pub mod metrics;
+pub mod models;
+pub mod pool_stats;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod query_budget;
+pub mod repository;
+pub mod request_id;
+pub mod response;
+pub mod response_time;
+pub mod routes;
+pub mod security_headers;
+pub mod server_config;
+pub mod shutdown;
+pub mod startup;
+pub mod state;
+pub mod tasks;
+#[cfg(test)]
+mod test_utils;
+pub mod trace_sample;
+pub mod trailing_slash;
+pub mod transaction;
+#[cfg(unix)]
+pub mod uds;
+pub mod uri_limit;
+pub mod watchdog;
+pub mod webhook;
+pub mod write_limit;
+
+use crate::{config::Config, startup::StartupError, state::AppState, tasks::TaskRegistry};
+use axum::{middleware, routing::get, Router};
+use futures_util::FutureExt;
+use std::time::Duration;
+use tokio::sync::watch;
+use tower_http::trace::TraceLayer;
+
+/// How long background tasks get to stop once the server has shut down
+const TASK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the service until the server stops
+///
+/// Binds the HTTP listener, or the Unix domain socket at `LISTEN_UDS`, and
+/// serves requests while the database is initialized in the background; a failed migration aborts the server.
+/// Returns once a shutdown signal has been received and in-flight requests
+/// have drained.
+///
+/// Embedders can stop the server without a signal by passing a `shutdown`
+/// receiver and sending `true` on it; dropping the sender does not stop it.
+///
+/// # Errors
+///
+/// Returns a [`StartupError`] classifying why the service could not start or
+/// keep running
+pub async fn run(
+    config: Config,
+    shutdown: Option<watch::Receiver<bool>>,
+) -> Result<(), StartupError> {
+    // Create the pool lazily; connectivity is established by the startup task
+    let pool = repository::create_pool(&config)?;
+
+    let state = AppState::new(pool, config.clone());
+    startup::log_feature_summary(&config);
+    startup::log_diagnostics(&config);
+
+    // Build application router
+    let app = build_app(state.clone());
+
+    // Signalled and joined once the server has shut down
+    let mut tasks = TaskRegistry::new();
+
+    if config.pool_stats_interval_secs > 0 {
+        pool_stats::spawn(
+            &mut tasks,
+            state.clone(),
+            Duration::from_secs(config.pool_stats_interval_secs),
+        );
+    }
+
+    let signal = shutdown::shutdown_signal(state.clone(), shutdown);
+    let server_config = server_config::ServerConfig::from_config(&config);
+    let mut server = match &config.listen_uds {
+        #[cfg(unix)]
+        Some(path) => {
+            let listener = startup::bind_unix(path)?;
+            tracing::info!("Listening on {}", path.display());
+            uds::serve(listener, app, server_config, signal).boxed()
+        }
+        _ => {
+            #[cfg(not(unix))]
+            if config.listen_uds.is_some() {
+                tracing::warn!("LISTEN_UDS is only supported on Unix; listening on TCP");
+            }
+            let addr = config.server_port.socket_addr([0, 0, 0, 0]);
+            let listener = startup::bind(addr).await?;
+            tracing::info!("Listening on {addr}");
+            conn_limit::serve(
+                listener,
+                app,
+                server_config,
+                conn_limit::ConnectionLimit::new(config.max_conn_per_ip),
+                signal,
+            )
+            .boxed()
+        }
+    };
+
+    // Database initialization only matters while the server is running; if a
+    // shutdown completes first, stop waiting for the database.
+    tokio::select! {
+        result = &mut server => {
+            state.in_flight.log_drained();
+            tasks.shutdown(TASK_SHUTDOWN_TIMEOUT).await;
+            return result.map_err(StartupError::Serve);
+        }
+        result = startup::initialize_database(&state) => result?,
+    }
+
+    if config.watchdog_interval_secs > 0 {
+        // Pings whichever pool is current, so a reconnect is picked up
+        let watched = state.clone();
+        watchdog::spawn(
+            &mut tasks,
+            state.db_ready.clone(),
+            Duration::from_secs(config.watchdog_interval_secs),
+            move || {
+                let pool = watched.pool();
+                let deep = watched.config.deep_health_check;
+                async move {
+                    if deep {
+                        repository::healthcheck_full(&pool).await
+                    } else {
+                        repository::ping(&pool).await
+                    }
+                }
+            },
+        );
+    }
+
+    if config.listen_user_changes {
+        // Notifications are informational; failing to subscribe is not fatal
+        if let Err(e) = change_listener::spawn(&state.pool(), &mut tasks).await {
+            tracing::warn!(error = %e, "Failed to listen for user changes");
+        }
+    }
+
+    let result = server.await;
+    state.in_flight.log_drained();
+    tasks.shutdown(TASK_SHUTDOWN_TIMEOUT).await;
+    result.map_err(StartupError::Serve)
+}
+
+/// Assemble the application router with all routes and middleware
+pub fn build_app(state: AppState) -> Router {
+    let router = Router::new()
+        .route(&state.config.health_path, get(health_check))
+        .merge(routes::health::router(&state.config.health_path))
+        .merge(routes::build_routes())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            query_budget::count_queries,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            webhook::dispatch,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            trailing_slash::redirect_trailing_slash,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            disabled_routes::reject_disabled,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            jwt::authenticate,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            error::expose_error_detail,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            decompress::decompress_request_body,
+        ))
+        .layer(middleware::from_fn(body_digest::verify_body_digest))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            body_timeout::limit_body_read_time,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            write_limit::limit_concurrent_writes,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            load_shed::shed_on_pool_saturation,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            deadline::enforce_request_timeout,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            uri_limit::reject_long_uri,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_metrics,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            trace_sample::sample_verbose_tracing,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_id::make_span)
+                .on_response(access_log::AccessLog::new(state.config.slow_request_ms)),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_id::propagate_request_id,
+        ))
+        .layer(middleware::from_fn(response_time::add_response_time))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            shutdown::track_in_flight,
+        ));
+    security_headers::apply(router, &state.config).with_state(state)
+}
+
+/// Health check endpoint handler
+///
+/// Returns a simple "OK" status to indicate the server is running. It never
+/// touches the database; axum answers `HEAD` from this route with the same
+/// status and no body, for cheap liveness polling. Served at `HEALTH_PATH`,
+/// `/health` by default.
+async fn health_check() -> &'static str {
+    "OK"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        admin, insert_user, mock_webhook, test_config, test_pool, test_state, unreachable_pool,
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    async fn scrape(app: Router) -> String {
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let response = health_check().await;
+        assert_eq!(response, "OK");
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_when_shutdown_is_triggered() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = Config {
+            database_url: "postgres://postgres@127.0.0.1:1/unreachable".to_string(),
+            server_port: config::Port::new(port).unwrap(),
+            pool_stats_interval_secs: 0,
+            ..test_config()
+        };
+        let (trigger, shutdown) = watch::channel(false);
+        let server = tokio::spawn(run(config, Some(shutdown)));
+
+        let mut connected = false;
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                connected = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(connected, "server never started listening");
+
+        trigger.send(true).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+        assert!(result.is_ok(), "{result:?}");
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_serves_health_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!(
+            "rust-basic-api-{}-{}.sock",
+            std::process::id(),
+            uuid::Uuid::new_v4()
+        ));
+        let config = Config {
+            database_url: "postgres://postgres@127.0.0.1:1/unreachable".to_string(),
+            listen_uds: Some(path.clone()),
+            pool_stats_interval_secs: 0,
+            ..test_config()
+        };
+        let (trigger, shutdown) = watch::channel(false);
+        let server = tokio::spawn(run(config, Some(shutdown)));
+
+        let mut stream = None;
+        for _ in 0..100 {
+            if let Ok(connected) = tokio::net::UnixStream::connect(&path).await {
+                stream = Some(connected);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut stream = stream.expect("server never started listening");
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("OK"), "{response}");
+
+        trigger.send(true).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+        assert!(result.is_ok(), "{result:?}");
+        assert!(!path.exists(), "socket file was left behind");
+    }
+
+    #[tokio::test]
+    async fn test_create_user_posts_webhook_after_commit() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (url, mut received) = mock_webhook(0).await;
+        let config = Config {
+            webhook_url: Some(url),
+            ..test_config()
+        };
+        let app = build_app(test_state(pool, config));
+        let create = || {
+            Request::post("/users")
+                .extension(admin())
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"Hook","email":"hook@example.com"}"#))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(create()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let user: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("webhook was not called")
+            .unwrap();
+        assert_eq!(event["event"], "user.created");
+        assert_eq!(event["user"], user);
+
+        // A rejected write announces nothing
+        let response = app.oneshot(create()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let extra = tokio::time::timeout(Duration::from_millis(200), received.recv()).await;
+        assert!(extra.is_err(), "{extra:?}");
+    }
+
+    #[tokio::test]
+    async fn test_custom_health_path() {
+        let config = Config {
+            health_path: "/_health".to_string(),
+            ..test_config()
+        };
+        let app = build_app(test_state(unreachable_pool(), config));
+        let status = |uri: &'static str| {
+            app.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(status("/_health").await.unwrap().status(), StatusCode::OK);
+        let startup = status("/_health/startup").await.unwrap();
+        assert_eq!(startup.status(), StatusCode::OK);
+        for default in ["/health", "/health/startup"] {
+            let response = status(default).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{default}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_health_is_pure_liveness() {
+        // Every query against this pool would fail, so a 200 shows none ran
+        let pool = unreachable_pool();
+        let app = build_app(test_state(pool.clone(), test_config()));
+
+        let response = app
+            .oneshot(Request::head("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_health_has_security_headers() {
+        let app = build_app(test_state(unreachable_pool(), test_config()));
+
+        let response = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers["referrer-policy"], "no-referrer");
+    }
+
+    #[tokio::test]
+    async fn test_security_headers_can_be_disabled() {
+        let config = Config {
+            security_headers: false,
+            ..test_config()
+        };
+        let app = build_app(test_state(unreachable_pool(), config));
+
+        let response = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert!(!response.headers().contains_key("x-frame-options"));
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_response_time() {
+        let app = build_app(test_state(unreachable_pool(), test_config()));
+
+        let response = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let value = response.headers()[&response_time::X_RESPONSE_TIME]
+            .to_str()
+            .unwrap();
+        let millis = value.strip_suffix("ms").expect("value should end in ms");
+        assert!(millis.parse::<f64>().is_ok(), "not a number: {value}");
+    }
+
+    #[tokio::test]
+    async fn test_response_size_histogram_for_users_list() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Metered", "metered@example.com").await;
+        let app = build_app(test_state(pool, test_config()));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/users").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let output = scrape(app).await;
+        assert!(output.contains("# TYPE http_response_size_bytes histogram"));
+        assert!(output.contains("http_response_size_bytes_count{route=\"/users\"} 1"));
+        assert!(output.contains("http_request_size_bytes_count{route=\"/users\"} 1"));
+    }
+}
@@ -0,0 +1,94 @@
+//! Load shedding on database pool saturation
+//!
+//! When every pooled connection is in use and the pool cannot grow, a new
+//! request would only queue for a connection and likely time out, adding to
+//! the pile-up. With `SHED_ON_POOL_SATURATION` set such requests are refused
+//! up front with `503`, so clients can back off and retry. Health and metrics
+//! endpoints are never shed.
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+
+/// Whether the pool is at its size limit with no idle connection
+#[must_use]
+pub fn is_saturated(pool: &PgPool) -> bool {
+    pool.num_idle() == 0 && pool.size() >= pool.options().get_max_connections()
+}
+
+/// Middleware answering `503` while the pool is saturated
+pub async fn shed_on_pool_saturation(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let health = &state.config.health_path;
+    let exempt = path == "/metrics"
+        || path
+            .strip_prefix(health.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+    if state.config.shed_on_pool_saturation && !exempt && is_saturated(&state.pool()) {
+        tracing::warn!("Shedding request, database pool saturated");
+        return AppError::Overloaded.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{test_config, test_pool, test_state},
+    };
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+
+    async fn status(app: &Router, uri: &str) -> StatusCode {
+        app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_saturated_pool_sheds_requests() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let single = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+        let config = Config {
+            shed_on_pool_saturation: true,
+            ..test_config()
+        };
+        let app = build_app(test_state(single.clone(), config));
+
+        let held = single.acquire().await.unwrap();
+        assert!(is_saturated(&single));
+        assert_eq!(
+            status(&app, "/users").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(&app, "/health/startup").await, StatusCode::OK);
+
+        // Closing frees the slot at once, unlike a drop returning it later
+        held.close().await.unwrap();
+        assert_eq!(status(&app, "/users").await, StatusCode::OK);
+    }
+}
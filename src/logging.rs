@@ -0,0 +1,168 @@
+//! Logging setup
+//!
+//! This module initializes the tracing subscriber and supports reloading the
+//! log filter at runtime, so operators can raise verbosity without a restart.
+//! With `LOG_FILE` set, logs are also written to that file, rotated daily.
+
+use std::path::Path;
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{InitError, RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+/// Filter used when neither `RUST_LOG` nor `LOG_LEVEL` is set
+const DEFAULT_FILTER: &str = "rust_basic_api=info,tower_http=debug";
+
+/// Handle used to swap the active log filter
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Install the global tracing subscriber
+///
+/// Logs go to stdout and, when `LOG_FILE` is set, to that file as well.
+/// Returns a handle through which the filter can later be reloaded, and the
+/// guard of the file writer, which flushes pending lines when dropped and so
+/// must be held until the process exits.
+#[must_use]
+pub fn init() -> (FilterHandle, Option<WorkerGuard>) {
+    let (filter, handle) = reload::Layer::new(env_filter());
+    let log_file = std::env::var("LOG_FILE").ok().filter(|v| !v.is_empty());
+    let file = log_file.as_deref().map(|path| file_writer(Path::new(path)));
+    let (writer, guard, error) = match file {
+        Some(Ok((writer, guard))) => (Some(writer), Some(guard), None),
+        Some(Err(e)) => (None, None, Some(e)),
+        None => (None, None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(writer.map(|writer| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer)
+        }))
+        .init();
+
+    if let Some(e) = error {
+        tracing::warn!(error = %e, log_file, "Failed to open LOG_FILE; logging to stdout only");
+    }
+    (handle, guard)
+}
+
+/// Writer appending to `path`, rotated daily, with writes done off-thread
+///
+/// Each day's file is named after `path` with the date appended, e.g.
+/// `api.log.2024-01-31`. Missing directories are created.
+///
+/// # Errors
+///
+/// Returns an error if the directory or file cannot be created
+pub fn file_writer(path: &Path) -> Result<(NonBlocking, WorkerGuard), InitError> {
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut appender = RollingFileAppender::builder().rotation(Rotation::DAILY);
+    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+        appender = appender.filename_prefix(name);
+    }
+    Ok(tracing_appender::non_blocking(appender.build(directory)?))
+}
+
+/// Build the log filter from the environment
+///
+/// `RUST_LOG` takes precedence over `LOG_LEVEL`; invalid or missing values
+/// fall back to the default filter.
+#[must_use]
+pub fn env_filter() -> EnvFilter {
+    ["RUST_LOG", "LOG_LEVEL"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .and_then(|directives| EnvFilter::try_new(directives).ok())
+        .unwrap_or_else(|| EnvFilter::new(DEFAULT_FILTER))
+}
+
+/// Re-read the log filter from the environment and apply it
+///
+/// # Errors
+///
+/// Returns an error if the subscriber holding the filter has been dropped
+pub fn reload_filter(handle: &FilterHandle) -> Result<(), reload::Error> {
+    let filter = env_filter();
+    let directives = filter.to_string();
+    handle.reload(filter)?;
+    tracing::info!(filter = %directives, "Log filter reloaded");
+    Ok(())
+}
+
+/// Reload the log filter whenever the process receives `SIGHUP`
+///
+/// # Errors
+///
+/// Returns an error if the signal handler cannot be registered
+#[cfg(unix)]
+pub fn spawn_sighup_reload(handle: FilterHandle) -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = reload_filter(&handle) {
+                tracing::error!("Failed to reload log filter: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_file_writer_receives_log_lines() {
+        let dir = env::temp_dir().join(format!("rust-basic-api-{}", uuid::Uuid::new_v4()));
+        let (writer, guard) = file_writer(&dir.join("api.log")).unwrap();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("written to the log file");
+        });
+        // Flushes the background writer
+        drop(guard);
+
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let contents = files
+            .first()
+            .map(|file| std::fs::read_to_string(file).unwrap())
+            .unwrap_or_default();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files.len(), 1, "{files:?}");
+        let name = files[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("api.log."), "{name}");
+        assert!(contents.contains("written to the log file"), "{contents}");
+    }
+
+    #[test]
+    fn test_reload_filter_applies_new_directives() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("warn"));
+        let _subscriber = tracing_subscriber::registry().with(filter);
+
+        env::set_var("RUST_LOG", "rust_basic_api=trace");
+        reload_filter(&handle).expect("Failed to reload filter");
+        env::remove_var("RUST_LOG");
+
+        let current = handle.with_current(ToString::to_string).unwrap();
+        assert_eq!(current, "rust_basic_api=trace");
+    }
+}
@@ -1,71 +1,53 @@
 //! Rust Basic API
 //!
-//! A production-ready REST API built with Axum framework.
+//! Binary entry point: sets up logging, loads configuration and runs the service.
 
-mod config;
-mod error;
-mod models;
-mod repository;
-mod routes;
+use rust_basic_api::{config::Config, logging, startup};
+use std::process::ExitCode;
 
-use crate::config::Config;
-use axum::{routing::get, Router};
-use std::net::SocketAddr;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize tracing subscriber for structured logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "rust_basic_api=info,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+fn main() -> ExitCode {
+    // Initialize tracing subscriber for structured logging; SIGHUP re-reads the filter
+    let (log_filter, _log_file) = logging::init();
 
     // Load configuration from environment
-    let config =
-        Config::from_env().map_err(|e| anyhow::anyhow!("Failed to load configuration: {e}"))?;
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Failed to load configuration: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
 
     tracing::info!(
         database_url_configured = !config.database_url.is_empty(),
-        port = config.server_port,
+        port = config.server_port.get(),
+        worker_threads = config.worker_threads,
         "Configuration loaded"
     );
 
-    // Build application router
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .merge(routes::build_routes())
-        .layer(TraceLayer::new_for_http());
-
-    // Create socket address
-    let addr = SocketAddr::from(([0, 0, 0, 0], config.server_port));
-    tracing::info!("Listening on {addr}");
-
-    // Start the server
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-
-    Ok(())
-}
-
-/// Health check endpoint handler
-///
-/// Returns a simple "OK" status to indicate the server is running.
-async fn health_check() -> &'static str {
-    "OK"
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    async fn test_health_check() {
-        let response = health_check().await;
-        assert_eq!(response, "OK");
-    }
+    // Build the runtime explicitly so WORKER_THREADS is honored
+    let runtime = match startup::build_runtime(&config) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!("Failed to start the async runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    runtime.block_on(async {
+        #[cfg(unix)]
+        if let Err(e) = logging::spawn_sighup_reload(log_filter) {
+            tracing::warn!("Failed to install SIGHUP handler, log filter reload disabled: {e}");
+        }
+        #[cfg(not(unix))]
+        drop(log_filter);
+
+        match rust_basic_api::run(config, None).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                tracing::error!("{e}");
+                e.exit_code()
+            }
+        }
+    })
 }
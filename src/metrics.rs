@@ -0,0 +1,333 @@
+//! Metrics collection
+//!
+//! This module keeps an in-process registry of request metrics and renders it
+//! in the Prometheus text exposition format for scraping via `GET /metrics`.
+
+use crate::{error::ErrorCode, state::AppState};
+use axum::{
+    body::HttpBody,
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, PoisonError},
+};
+
+/// Bucket upper bounds, in bytes, for body size histograms
+const SIZE_BUCKETS: &[f64] = &[
+    64.0,
+    256.0,
+    1024.0,
+    4096.0,
+    16_384.0,
+    65_536.0,
+    262_144.0,
+    1_048_576.0,
+];
+
+/// Route label used for requests that matched no route
+pub(crate) const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Registry of all metrics exported by the service
+#[derive(Debug)]
+pub struct Metrics {
+    request_size: HistogramVec,
+    response_size: HistogramVec,
+    errors: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            request_size: HistogramVec::new(
+                "http_request_size_bytes",
+                "Size of HTTP request bodies in bytes",
+                SIZE_BUCKETS,
+            ),
+            response_size: HistogramVec::new(
+                "http_response_size_bytes",
+                "Size of HTTP response bodies in bytes",
+                SIZE_BUCKETS,
+            ),
+            errors: CounterVec::new(
+                "app_errors_total",
+                "Error responses by application error code",
+                "code",
+            ),
+        }
+    }
+}
+
+impl Metrics {
+    /// Record the body size of a request to `route`
+    pub fn observe_request_size(&self, route: &str, bytes: u64) {
+        self.request_size.observe(route, bytes);
+    }
+
+    /// Record the body size of a response from `route`
+    pub fn observe_response_size(&self, route: &str, bytes: u64) {
+        self.response_size.observe(route, bytes);
+    }
+
+    /// Count an error response with the given [`AppError`](crate::error::AppError) code
+    pub fn record_error(&self, code: &str) {
+        self.errors.inc(code);
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.request_size.render(&mut out);
+        self.response_size.render(&mut out);
+        self.errors.render(&mut out);
+        out
+    }
+}
+
+/// A histogram partitioned by route
+#[derive(Debug)]
+struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    buckets: &'static [f64],
+    series: Mutex<BTreeMap<String, Histogram>>,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bucket_counts: Vec<u64>,
+    sum: u64,
+    count: u64,
+}
+
+impl HistogramVec {
+    fn new(name: &'static str, help: &'static str, buckets: &'static [f64]) -> Self {
+        Self {
+            name,
+            help,
+            buckets,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)] // byte counts stay far below 2^52
+    fn observe(&self, route: &str, value: u64) {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let histogram = series
+            .entry(route.to_string())
+            .or_insert_with(|| Histogram {
+                bucket_counts: vec![0; self.buckets.len()],
+                sum: 0,
+                count: 0,
+            });
+
+        for (bound, count) in self.buckets.iter().zip(&mut histogram.bucket_counts) {
+            if value as f64 <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum = histogram.sum.saturating_add(value);
+        histogram.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let name = self.name;
+        let _ = writeln!(out, "# HELP {name} {}", self.help);
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        let series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        for (route, histogram) in series.iter() {
+            let route = escape_label(route);
+            for (bound, count) in self.buckets.iter().zip(&histogram.bucket_counts) {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{route=\"{route}\",le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{route=\"{route}\",le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(out, "{name}_sum{{route=\"{route}\"}} {}", histogram.sum);
+            let _ = writeln!(out, "{name}_count{{route=\"{route}\"}} {}", histogram.count);
+        }
+    }
+}
+
+/// A counter partitioned by a single label
+#[derive(Debug)]
+struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    series: Mutex<BTreeMap<String, u64>>,
+}
+
+impl CounterVec {
+    fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            series: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn inc(&self, value: &str) {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        *series.entry(value.to_string()).or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let (name, label) = (self.name, self.label);
+        let _ = writeln!(out, "# HELP {name} {}", self.help);
+        let _ = writeln!(out, "# TYPE {name} counter");
+
+        let series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        for (value, count) in series.iter() {
+            let value = escape_label(value);
+            let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+        }
+    }
+}
+
+/// Escape a label value per the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Body size if it is known up front
+///
+/// Prefers the exact size hint of the body and falls back to `Content-Length`;
+/// streamed bodies of unknown length are not recorded.
+pub(crate) fn known_body_size<B: HttpBody>(body: &B, headers: &header::HeaderMap) -> Option<u64> {
+    body.size_hint().exact().or_else(|| {
+        headers
+            .get(header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    })
+}
+
+/// Middleware recording request and response body sizes per matched route,
+/// and counting error responses by their [`ErrorCode`]
+pub async fn track_metrics(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request.extensions().get::<MatchedPath>().map_or_else(
+        || UNMATCHED_ROUTE.to_string(),
+        |path| path.as_str().to_string(),
+    );
+
+    if let Some(bytes) = known_body_size(request.body(), request.headers()) {
+        state.metrics.observe_request_size(&route, bytes);
+    }
+
+    let response = next.run(request).await;
+
+    if let Some(ErrorCode(code)) = response.extensions().get() {
+        state.metrics.record_error(code);
+    }
+    if let Some(bytes) = known_body_size(response.body(), response.headers()) {
+        state.metrics.observe_response_size(&route, bytes);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        test_utils::{admin, test_config, test_pool, test_state},
+    };
+    use axum::{body::Body, http::StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_histogram_render() {
+        let metrics = Metrics::default();
+        metrics.observe_response_size("/users", 100);
+        metrics.observe_response_size("/users", 5000);
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE http_response_size_bytes histogram"));
+        assert!(output.contains("http_response_size_bytes_bucket{route=\"/users\",le=\"64\"} 0"));
+        assert!(output.contains("http_response_size_bytes_bucket{route=\"/users\",le=\"256\"} 1"));
+        assert!(output.contains("http_response_size_bytes_bucket{route=\"/users\",le=\"+Inf\"} 2"));
+        assert!(output.contains("http_response_size_bytes_sum{route=\"/users\"} 5100"));
+        assert!(output.contains("http_response_size_bytes_count{route=\"/users\"} 2"));
+    }
+
+    #[test]
+    fn test_counter_render() {
+        let metrics = Metrics::default();
+        metrics.record_error("not_found");
+        metrics.record_error("not_found");
+        metrics.record_error("validation_error");
+
+        let output = metrics.render();
+        assert!(output.contains("# TYPE app_errors_total counter"));
+        assert!(output.contains("app_errors_total{code=\"not_found\"} 2"));
+        assert!(output.contains("app_errors_total{code=\"validation_error\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_are_counted_by_code() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = build_app(test_state(pool, test_config()));
+        let send = |request: Request| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap() }
+        };
+
+        let missing = send(Request::get("/users/999999").body(Body::empty()).unwrap()).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let invalid = Request::post("/users")
+            .extension(admin())
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "name": "", "email": "a@example.com" }).to_string(),
+            ))
+            .unwrap();
+        assert_eq!(
+            send(invalid).await.status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        let scrape = send(Request::get("/metrics").body(Body::empty()).unwrap()).await;
+        let bytes = axum::body::to_bytes(scrape.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let output = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(
+            output.contains("app_errors_total{code=\"not_found\"} 1"),
+            "{output}"
+        );
+        assert!(
+            output.contains("app_errors_total{code=\"validation_error\"} 1"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        assert_eq!(escape_label("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
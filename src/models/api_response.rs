@@ -0,0 +1,126 @@
+//! Envelope giving successes and errors one response shape
+
+use crate::error::AppError;
+use axum::http::StatusCode;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/// Outcome of a request as one type
+///
+/// Serializes as `{"ok": true, "data": ...}` on success and as
+/// `{"ok": false, "error": {"code": ..., "message": ...}}` on failure, where
+/// `code` is [`AppError::code`] and `message` the text the plain error
+/// response would carry.
+#[derive(Debug)]
+pub enum ApiResponse<T> {
+    /// The request succeeded with `data`
+    Success(T),
+    /// The request failed
+    Failure(AppError),
+}
+
+impl<T> ApiResponse<T> {
+    /// A successful response carrying `data`
+    pub const fn success(data: T) -> Self {
+        Self::Success(data)
+    }
+
+    /// A failed response reporting `error`
+    pub fn failure(error: impl Into<AppError>) -> Self {
+        Self::Failure(error.into())
+    }
+
+    /// Status to send: `200` on success, the error's status otherwise
+    #[must_use]
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Success(_) => StatusCode::OK,
+            Self::Failure(error) => error.status_and_message().0,
+        }
+    }
+}
+
+impl<T> From<AppError> for ApiResponse<T> {
+    fn from(error: AppError) -> Self {
+        Self::Failure(error)
+    }
+}
+
+impl<T> From<Result<T, AppError>> for ApiResponse<T> {
+    fn from(result: Result<T, AppError>) -> Self {
+        result.map_or_else(Self::Failure, Self::Success)
+    }
+}
+
+/// The `error` object of a failed [`ApiResponse`]
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: &'a str,
+}
+
+impl<T: Serialize> Serialize for ApiResponse<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut envelope = serializer.serialize_struct("ApiResponse", 2)?;
+        match self {
+            Self::Success(data) => {
+                envelope.serialize_field("ok", &true)?;
+                envelope.serialize_field("data", data)?;
+            }
+            Self::Failure(error) => {
+                envelope.serialize_field("ok", &false)?;
+                envelope.serialize_field(
+                    "error",
+                    &ErrorBody {
+                        code: error.code(),
+                        message: error.status_and_message().1,
+                    },
+                )?;
+            }
+        }
+        envelope.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use serde_json::json;
+
+    #[test]
+    fn test_success_serializes_data() {
+        let user = User::builder()
+            .id(7)
+            .name("Ann")
+            .email("ann@example.com")
+            .build();
+        let response = ApiResponse::success(user.clone());
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({ "ok": true, "data": serde_json::to_value(&user).unwrap() })
+        );
+    }
+
+    #[test]
+    fn test_failure_serializes_code_and_message() {
+        let response: ApiResponse<User> = Err(AppError::NotFound("user 7".to_string())).into();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "ok": false,
+                "error": { "code": "not_found", "message": "Resource not found" },
+            })
+        );
+
+        let response = ApiResponse::<()>::failure(sqlx::Error::PoolTimedOut);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            serde_json::to_value(&response).unwrap()["error"],
+            json!({ "code": "database_busy", "message": "Database busy" })
+        );
+    }
+}
@@ -0,0 +1,14 @@
+//! Audit log entries
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// One recorded change to a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct AuditEntry {
+    /// `create`, `update` or `delete`
+    pub action: String,
+    /// When the change was committed
+    pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,118 @@
+//! Opaque cursor tokens for keyset pagination
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+/// Version prefix of the token payload, so the format can change later
+const PREFIX: &str = "u1:";
+
+/// Reason a cursor token was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid cursor")]
+pub struct InvalidCursor;
+
+/// Position after which the next page of users starts
+///
+/// Clients only see it as the token produced by [`encode_cursor`]; it
+/// serializes as that token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    last_id: i32,
+}
+
+impl Cursor {
+    /// A cursor continuing after the user with id `last_id`
+    #[must_use]
+    pub const fn after(last_id: i32) -> Self {
+        Self { last_id }
+    }
+
+    /// Id of the last user on the previous page
+    #[must_use]
+    pub const fn last_id(self) -> i32 {
+        self.last_id
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode_cursor(*self))
+    }
+}
+
+/// One page of a keyset-paginated listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CursorPage<T> {
+    /// Records on this page
+    pub items: Vec<T>,
+    /// Cursor of the following page; `None` on the last page
+    pub next: Option<Cursor>,
+}
+
+/// Encode `cursor` as a URL-safe token
+#[must_use]
+pub fn encode_cursor(cursor: Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{PREFIX}{}", cursor.last_id))
+}
+
+/// Decode a token produced by [`encode_cursor`]
+///
+/// # Errors
+///
+/// Returns [`InvalidCursor`] if the token is not one this service issued:
+/// not base64, an unknown version, or an id that is not a positive integer
+pub fn decode_cursor(token: &str) -> Result<Cursor, InvalidCursor> {
+    let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| InvalidCursor)?;
+    let payload = std::str::from_utf8(&bytes).map_err(|_| InvalidCursor)?;
+    let id = payload.strip_prefix(PREFIX).ok_or(InvalidCursor)?;
+    // Reject signs and leading zeros, which `parse` accepts, so a token has
+    // exactly one spelling
+    if id.starts_with(['+', '-', '0']) {
+        return Err(InvalidCursor);
+    }
+    let last_id = id.parse().map_err(|_| InvalidCursor)?;
+    Ok(Cursor { last_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        for id in [1, 42, i32::MAX] {
+            let token = encode_cursor(Cursor::after(id));
+            assert!(!token.contains(&id.to_string()), "{token}");
+            assert_eq!(decode_cursor(&token), Ok(Cursor::after(id)));
+        }
+    }
+
+    #[test]
+    fn test_cursor_serializes_as_token() {
+        let cursor = Cursor::after(7);
+        assert_eq!(
+            serde_json::to_value(cursor).unwrap(),
+            serde_json::Value::String(encode_cursor(cursor))
+        );
+    }
+
+    #[test]
+    fn test_decode_cursor_rejects_malformed_tokens() {
+        let forged = |payload: &str| URL_SAFE_NO_PAD.encode(payload);
+        for token in [
+            String::new(),
+            "not base64!".to_string(),
+            forged("7"),
+            forged("u2:7"),
+            forged("u1:"),
+            forged("u1:abc"),
+            forged("u1:-7"),
+            forged("u1:+7"),
+            forged("u1:007"),
+            forged("u1:99999999999"),
+        ] {
+            assert_eq!(decode_cursor(&token), Err(InvalidCursor), "{token:?}");
+        }
+    }
+}
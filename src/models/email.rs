@@ -0,0 +1,203 @@
+//! Validated email address type
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{fmt, ops::Deref};
+use thiserror::Error;
+
+/// Maximum length of an email address, matching the `users.email` column
+pub const MAX_EMAIL_LEN: usize = 255;
+
+/// Reason an email address was rejected
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid email address: {0}")]
+pub struct InvalidEmail(&'static str);
+
+/// An email address that has passed format validation
+///
+/// Validation happens on construction and during deserialization, so a value
+/// of this type is always well-formed. It dereferences to `str` for binding
+/// into queries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Email(String);
+
+impl Email {
+    /// Validate and wrap an email address
+    ///
+    /// # Errors
+    ///
+    /// Returns [`InvalidEmail`] describing the first rule the input breaks
+    pub fn parse(value: impl Into<String>) -> Result<Self, InvalidEmail> {
+        let value = value.into();
+        validate(&value)?;
+        Ok(Self(value))
+    }
+
+    /// The address as a string slice
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The address lowercased, the form in which it is stored
+    #[must_use]
+    pub fn normalized(&self) -> Self {
+        Self(self.0.to_lowercase())
+    }
+
+    /// Consume the wrapper, returning the address
+    #[must_use]
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// Check an email address without wrapping it, see [`Email::parse`]
+///
+/// # Errors
+///
+/// Returns a message naming the first rule the input breaks
+pub fn validate_email(value: &str) -> Result<(), String> {
+    validate(value).map_err(|e| e.to_string())
+}
+
+fn validate(value: &str) -> Result<(), InvalidEmail> {
+    if value.len() > MAX_EMAIL_LEN {
+        return Err(InvalidEmail("too long"));
+    }
+    if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(InvalidEmail("contains whitespace or control characters"));
+    }
+
+    let (local, domain) = value.split_once('@').ok_or(InvalidEmail("missing '@'"))?;
+    if local.is_empty() {
+        return Err(InvalidEmail("empty local part"));
+    }
+    if domain.contains('@') {
+        return Err(InvalidEmail("more than one '@'"));
+    }
+    if domain.starts_with('.') || domain.ends_with('.') || !domain.contains('.') {
+        return Err(InvalidEmail("domain must contain a dot between labels"));
+    }
+
+    Ok(())
+}
+
+impl Deref for Email {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Email {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A string in `email` format no longer than [`MAX_EMAIL_LEN`]
+impl JsonSchema for Email {
+    fn schema_name() -> String {
+        "Email".to_string()
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            format: Some("email".to_string()),
+            string: Some(Box::new(StringValidation {
+                max_length: u32::try_from(MAX_EMAIL_LEN).ok(),
+                ..StringValidation::default()
+            })),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl<'de> Deserialize<'de> for Email {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Self::parse(value).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalized_lowercases() {
+        let email = Email::parse("Jane.Doe@Example.COM").unwrap();
+        assert_eq!(email.normalized().as_str(), "jane.doe@example.com");
+    }
+
+    #[test]
+    fn test_validate_email() {
+        assert!(validate_email("jane@example.com").is_ok());
+
+        assert_eq!(
+            validate_email("").unwrap_err(),
+            "invalid email address: missing '@'"
+        );
+        let long = format!("{}@example.com", "x".repeat(MAX_EMAIL_LEN));
+        assert!(validate_email(&long).unwrap_err().contains("too long"));
+        assert!(validate_email("jane@localhost")
+            .unwrap_err()
+            .contains("dot"));
+    }
+
+    #[test]
+    fn test_parse_valid_email() {
+        let email = Email::parse("jane.doe@example.com").unwrap();
+        assert_eq!(&*email, "jane.doe@example.com");
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        for input in [
+            "",
+            "plainaddress",
+            "@example.com",
+            "jane@",
+            "jane@localhost",
+            "jane@example.com.",
+            "jane@@example.com",
+            "jane doe@example.com",
+        ] {
+            assert!(Email::parse(input).is_err(), "accepted {input:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_over_length() {
+        let input = format!("{}@example.com", "a".repeat(MAX_EMAIL_LEN));
+        assert_eq!(Email::parse(input), Err(InvalidEmail("too long")));
+    }
+
+    #[test]
+    fn test_serializes_as_plain_string() {
+        let email = Email::parse("jane@example.com").unwrap();
+        assert_eq!(
+            serde_json::to_string(&email).unwrap(),
+            "\"jane@example.com\""
+        );
+    }
+}
@@ -1,3 +1,21 @@
 //! Data models module
 //!
 //! This module contains all data structures and types used in the application.
+
+mod api_response;
+mod audit;
+mod cursor;
+mod email;
+mod page;
+mod user;
+
+pub use api_response::ApiResponse;
+pub use audit::AuditEntry;
+pub use cursor::{decode_cursor, encode_cursor, Cursor, CursorPage, InvalidCursor};
+pub use email::{validate_email, Email, InvalidEmail, MAX_EMAIL_LEN};
+pub use page::{FilteredPage, Page, PageParams};
+pub use user::{
+    full_name, validate_name, validate_name_part, validate_name_parts, AppliedFilters, Column,
+    DuplicateEmailGroup, EmailDomain, FieldChange, NewUser, RelatedRows, UpsertCounts, User,
+    UserFilter, UserSort, UserSummary, UserUpdate, UserView, MAX_NAME_LEN,
+};
@@ -0,0 +1,155 @@
+//! Paginated list envelope
+
+use serde::{Deserialize, Serialize};
+
+/// One page of a listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page<T> {
+    /// Records on this page
+    pub items: Vec<T>,
+    /// Number of matching records across all pages
+    pub total: i64,
+    /// Page size the records were fetched with
+    pub limit: i64,
+    /// Number of matching records skipped before this page
+    pub offset: i64,
+    /// Offset of the following page; `None` on the last page
+    pub next_offset: Option<i64>,
+    /// Offset of the preceding page; `None` on the first page
+    pub prev_offset: Option<i64>,
+    /// Number of pages of `limit` records needed for `total`
+    pub total_pages: i64,
+}
+
+impl<T> Page<T> {
+    /// A page of `items` fetched with `limit` and `offset` out of `total`
+    ///
+    /// Navigation is computed with checked arithmetic, so extreme offsets
+    /// clamp instead of overflowing: a next page whose offset would not fit
+    /// in `i64` is reported as absent. A negative `total`, as an estimate of
+    /// a never-analyzed table can be, counts as zero, and a `limit` below 1
+    /// as 1.
+    #[must_use]
+    pub fn new(items: Vec<T>, total: i64, limit: i64, offset: i64) -> Self {
+        let total = total.max(0);
+        let step = limit.max(1);
+        let offset = offset.max(0);
+        let next_offset = offset.checked_add(step).filter(|&next| next < total);
+        let prev_offset = (offset > 0).then(|| offset.saturating_sub(step).max(0));
+        let total_pages = total / step + i64::from(total % step != 0);
+        Self {
+            items,
+            total,
+            limit,
+            offset,
+            next_offset,
+            prev_offset,
+            total_pages,
+        }
+    }
+}
+
+/// A page of a filtered listing, echoing the filters it applied
+///
+/// Serializes as the [`Page`] envelope with an extra `filters` field, so
+/// clients can show the active filters without parsing the request URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FilteredPage<T, F> {
+    /// The page itself
+    #[serde(flatten)]
+    pub page: Page<T>,
+    /// Filters applied to the listing
+    pub filters: F,
+}
+
+/// Paging query parameters for listings without other criteria
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct PageParams {
+    /// Maximum number of rows to return
+    pub limit: Option<i64>,
+    /// Number of rows to skip
+    pub offset: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+
+    #[test]
+    fn test_page_serializes_envelope() {
+        let page = Page::new(
+            vec![User::builder().name("Ann").email("ann@example.com").build()],
+            41,
+            20,
+            20,
+        );
+
+        let json = serde_json::to_value(&page).unwrap();
+
+        let keys: Vec<_> = json.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            [
+                "items",
+                "limit",
+                "next_offset",
+                "offset",
+                "prev_offset",
+                "total",
+                "total_pages"
+            ]
+        );
+        assert_eq!(json["items"][0]["email"], "ann@example.com");
+        assert_eq!(json["total"], 41);
+        assert_eq!(json["limit"], 20);
+        assert_eq!(json["offset"], 20);
+        assert_eq!(json["next_offset"], 40);
+        assert_eq!(json["prev_offset"], 0);
+        assert_eq!(json["total_pages"], 3);
+    }
+
+    #[test]
+    fn test_page_navigation_at_the_edges() {
+        let first = Page::<()>::new(Vec::new(), 40, 20, 0);
+        assert_eq!((first.prev_offset, first.next_offset), (None, Some(20)));
+        let last = Page::<()>::new(Vec::new(), 40, 20, 20);
+        assert_eq!((last.prev_offset, last.next_offset), (Some(0), None));
+        assert_eq!(last.total_pages, 2);
+
+        let past_end = Page::<()>::new(Vec::new(), 5, 20, 7);
+        assert_eq!(
+            (past_end.prev_offset, past_end.next_offset),
+            (Some(0), None)
+        );
+
+        let empty = Page::<()>::new(Vec::new(), 0, 20, 0);
+        assert_eq!(empty.total_pages, 0);
+    }
+
+    #[test]
+    fn test_page_navigation_clamps_near_i64_max() {
+        let page = Page::<()>::new(Vec::new(), i64::MAX, 100, i64::MAX - 10);
+        assert_eq!(page.next_offset, None);
+        assert_eq!(page.prev_offset, Some(i64::MAX - 110));
+        assert_eq!(page.total_pages, i64::MAX / 100 + 1);
+
+        let page = Page::<()>::new(Vec::new(), i64::MAX, i64::MAX, i64::MAX);
+        assert_eq!(page.next_offset, None);
+        assert_eq!(page.prev_offset, Some(0));
+        assert_eq!(page.total_pages, 1);
+
+        let page = Page::<()>::new(Vec::new(), i64::MAX, 1, 0);
+        assert_eq!(page.next_offset, Some(1));
+        assert_eq!(page.total_pages, i64::MAX);
+    }
+
+    #[test]
+    fn test_page_treats_negative_inputs_as_empty() {
+        let page = Page::<()>::new(Vec::new(), -1, 0, i64::MIN);
+        assert_eq!(page.total, 0);
+        assert_eq!(page.offset, 0);
+        assert_eq!(page.total_pages, 0);
+        assert_eq!((page.prev_offset, page.next_offset), (None, None));
+    }
+}
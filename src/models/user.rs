@@ -0,0 +1,847 @@
+//! User domain model
+
+use super::Email;
+use crate::error::AppError;
+use chrono::{DateTime, Utc};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum length of a user's name, matching the column width
+pub const MAX_NAME_LEN: usize = 255;
+
+/// A persisted user record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, JsonSchema)]
+pub struct User {
+    /// Primary key
+    pub id: i32,
+    /// Display name; the [full name](full_name) when first or last name is set
+    pub name: String,
+    /// Given name, if provided separately
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub first_name: Option<String>,
+    /// Family name, if provided separately
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub last_name: Option<String>,
+    /// Unique email address
+    #[schemars(email)]
+    pub email: String,
+    /// Creation timestamp
+    pub created_at: DateTime<Utc>,
+    /// Last modification timestamp
+    pub updated_at: DateTime<Utc>,
+    /// When the user was soft-deleted; only admins see such users
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// Whether the email's domain is exactly `domain`, ignoring ASCII case
+    ///
+    /// Subdomains do not match: `a@mail.example.com` is not in `example.com`.
+    #[must_use]
+    pub fn is_internal_email(&self, domain: &str) -> bool {
+        self.email
+            .rsplit_once('@')
+            .is_some_and(|(_, own)| own.eq_ignore_ascii_case(domain))
+    }
+
+    /// Name to show for the user
+    ///
+    /// Falls back to the local part of the email if the name is blank, which
+    /// validation should already prevent.
+    #[must_use]
+    pub fn display_name(&self) -> &str {
+        if !self.name.trim().is_empty() {
+            return &self.name;
+        }
+        self.email
+            .rsplit_once('@')
+            .map_or(self.email.as_str(), |(local, _)| local)
+    }
+
+    /// Fields whose values differ in `other`, in declaration order
+    ///
+    /// For change events: each [`FieldChange`] carries the value in `self`
+    /// as `old` and the one in `other` as `new`. `updated_at` is ignored, as
+    /// every write moves it.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<FieldChange> {
+        fn change<T: PartialEq + Serialize>(
+            field: &'static str,
+            old: &T,
+            new: &T,
+        ) -> Option<FieldChange> {
+            (old != new).then(|| FieldChange {
+                field,
+                old: serde_json::to_value(old).unwrap_or_default(),
+                new: serde_json::to_value(new).unwrap_or_default(),
+            })
+        }
+
+        [
+            change("id", &self.id, &other.id),
+            change("name", &self.name, &other.name),
+            change("first_name", &self.first_name, &other.first_name),
+            change("last_name", &self.last_name, &other.last_name),
+            change("email", &self.email, &other.email),
+            change("created_at", &self.created_at, &other.created_at),
+            change("deleted_at", &self.deleted_at, &other.deleted_at),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// One field reported by [`User::diff`], with its values before and after
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    /// Field name as serialized
+    pub field: &'static str,
+    /// Value before the change; `null` for an unset optional field
+    pub old: serde_json::Value,
+    /// Value after the change
+    pub new: serde_json::Value,
+}
+
+#[cfg(test)]
+impl User {
+    /// Start building a user for a test, without a database
+    #[must_use]
+    pub fn builder() -> UserBuilder {
+        UserBuilder::default()
+    }
+}
+
+/// Builder of valid [`User`] values for tests
+///
+/// Defaults to user 1, `Test User` at `user@example.com`, created and last
+/// updated at the moment the builder was made.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct UserBuilder {
+    user: User,
+}
+
+#[cfg(test)]
+impl Default for UserBuilder {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            user: User {
+                id: 1,
+                name: "Test User".to_string(),
+                first_name: None,
+                last_name: None,
+                email: "user@example.com".to_string(),
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+impl UserBuilder {
+    /// Set the primary key
+    #[must_use]
+    pub fn id(mut self, id: i32) -> Self {
+        self.user.id = id;
+        self
+    }
+
+    /// Set the display name
+    #[must_use]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.user.name = name.into();
+        self
+    }
+
+    /// Set the first and last name, and the name combined from them
+    #[must_use]
+    pub fn full_name(mut self, first: Option<&str>, last: Option<&str>) -> Self {
+        self.user.first_name = first.map(str::to_string);
+        self.user.last_name = last.map(str::to_string);
+        self.user.name = full_name(first, last).unwrap_or_default();
+        self
+    }
+
+    /// Set the email address
+    #[must_use]
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.user.email = email.into();
+        self
+    }
+
+    /// Set the creation timestamp
+    #[must_use]
+    pub fn created_at(mut self, at: DateTime<Utc>) -> Self {
+        self.user.created_at = at;
+        self
+    }
+
+    /// Set the last modification timestamp
+    #[must_use]
+    pub fn updated_at(mut self, at: DateTime<Utc>) -> Self {
+        self.user.updated_at = at;
+        self
+    }
+
+    /// Mark the user soft-deleted at `at`
+    #[must_use]
+    pub fn deleted_at(mut self, at: DateTime<Utc>) -> Self {
+        self.user.deleted_at = Some(at);
+        self
+    }
+
+    /// The user as configured
+    #[must_use]
+    pub fn build(self) -> User {
+        self.user
+    }
+}
+
+/// Lightweight projection of a user for list views
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct UserSummary {
+    /// Primary key
+    pub id: i32,
+    /// Display name
+    pub name: String,
+    /// Unique email address
+    pub email: String,
+}
+
+/// Changes to a user; unset fields keep their current value
+///
+/// Setting `name` clears the first and last name; setting either of those
+/// recomputes `name` from them, so the two cannot be combined.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserUpdate {
+    /// New display name
+    pub name: Option<String>,
+    /// New given name
+    pub first_name: Option<String>,
+    /// New family name
+    pub last_name: Option<String>,
+    /// New email address, validated during deserialization
+    pub email: Option<Email>,
+}
+
+/// Outcome of a bulk upsert
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UpsertCounts {
+    /// Rows created
+    pub inserted: u64,
+    /// Existing rows updated
+    pub updated: u64,
+}
+
+/// Rows referring to a user, as counted when it was deleted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, FromRow)]
+pub struct RelatedRows {
+    /// Entries of the user's change history, which are kept
+    pub audit: i64,
+}
+
+/// Users whose emails differ only in case or surrounding whitespace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct DuplicateEmailGroup {
+    /// The email lowercased and trimmed
+    pub normalized_email: String,
+    /// Number of users sharing it
+    pub count: i64,
+    /// Their ids, ascending
+    pub user_ids: Vec<i32>,
+}
+
+/// An email domain and the number of users registered under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow)]
+pub struct EmailDomain {
+    /// The part after `@`, lowercased
+    pub domain: String,
+    /// Number of users whose email has it
+    pub count: i64,
+}
+
+/// Shape of the records returned by a user listing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserView {
+    /// Full [`User`] records
+    #[default]
+    Full,
+    /// [`UserSummary`] records without timestamps
+    Summary,
+}
+
+/// A `users` column that can be selected on its own, for sparse fieldsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Column {
+    /// Primary key
+    Id,
+    /// Display name
+    Name,
+    /// Email address
+    Email,
+    /// Creation timestamp
+    CreatedAt,
+    /// Last modification timestamp
+    UpdatedAt,
+}
+
+impl Column {
+    /// Every selectable column, in table order
+    pub const ALL: [Self; 5] = [
+        Self::Id,
+        Self::Name,
+        Self::Email,
+        Self::CreatedAt,
+        Self::UpdatedAt,
+    ];
+
+    /// Column name, which is also the field name in JSON
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Name => "name",
+            Self::Email => "email",
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+/// Sort order for user listings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserSort {
+    /// Ascending by id (insertion order)
+    #[default]
+    #[serde(rename = "id")]
+    Id,
+    /// Ascending by name
+    #[serde(rename = "name")]
+    Name,
+    /// Descending by name
+    #[serde(rename = "-name")]
+    NameDesc,
+    /// Oldest first
+    #[serde(rename = "created_at")]
+    CreatedAt,
+    /// Newest first
+    #[serde(rename = "-created_at")]
+    CreatedAtDesc,
+}
+
+/// Criteria for listing and searching users
+///
+/// Every field is optional; unset fields do not constrain the result.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserFilter {
+    /// Case-insensitive substring of the name
+    pub name_contains: Option<String>,
+    /// Exact (case-insensitive) domain part of the email
+    pub email_domain: Option<String>,
+    /// Only users created at or after this instant
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only users created strictly before this instant
+    pub created_before: Option<DateTime<Utc>>,
+    /// `true` for only users who never logged in (`last_login_at` is null),
+    /// `false` for only those who have
+    pub never_logged_in: Option<bool>,
+    /// Maximum number of rows to return
+    pub limit: Option<i64>,
+    /// Number of rows to skip
+    pub offset: Option<i64>,
+    /// Result ordering
+    #[serde(default)]
+    pub sort: UserSort,
+    /// Record shape
+    #[serde(default)]
+    pub view: UserView,
+    /// Also list soft-deleted users; only honoured for admins
+    #[serde(default)]
+    pub include_deleted: bool,
+    /// Largest `limit` honoured for this caller, instead of the default cap;
+    /// set from the caller's API key tier, never from the query
+    #[serde(skip)]
+    pub max_limit: Option<i64>,
+}
+
+impl UserFilter {
+    /// Whether any criterion narrows the result set
+    ///
+    /// Paging, sorting and the view do not count as criteria; including
+    /// soft-deleted users does, as it changes the total.
+    #[must_use]
+    pub const fn has_conditions(&self) -> bool {
+        self.include_deleted
+            || self.name_contains.is_some()
+            || self.email_domain.is_some()
+            || self.created_after.is_some()
+            || self.created_before.is_some()
+            || self.never_logged_in.is_some()
+    }
+
+    /// The criteria and ordering applied, to echo back with the listing
+    #[must_use]
+    pub fn applied(&self) -> AppliedFilters {
+        AppliedFilters {
+            name_contains: self.name_contains.clone(),
+            email_domain: self.email_domain.clone(),
+            created_after: self.created_after,
+            created_before: self.created_before,
+            never_logged_in: self.never_logged_in,
+            sort: self.sort,
+            include_deleted: self.include_deleted,
+        }
+    }
+}
+
+/// The [`UserFilter`] criteria a listing applied, as echoed to clients
+///
+/// Criteria that were not given are omitted; `sort` is always present, as
+/// some order always applies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppliedFilters {
+    /// Case-insensitive substring of the name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_contains: Option<String>,
+    /// Domain part of the email
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_domain: Option<String>,
+    /// Lower bound of the creation time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<DateTime<Utc>>,
+    /// Upper bound of the creation time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<DateTime<Utc>>,
+    /// Whether only users who never (or only who did) log in are listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub never_logged_in: Option<bool>,
+    /// Result ordering
+    pub sort: UserSort,
+    /// Whether soft-deleted users are listed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub include_deleted: bool,
+}
+
+/// Payload for creating a user
+///
+/// Either `name` or at least one of `first_name` and `last_name` is required.
+/// With the parts, `name` is their [combination](full_name) and may be
+/// omitted; a `name` that differs from it is rejected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "NewUserFields")]
+pub struct NewUser {
+    /// Display name
+    pub name: String,
+    /// Given name
+    pub first_name: Option<String>,
+    /// Family name
+    pub last_name: Option<String>,
+    /// Email address, validated during deserialization
+    pub email: Email,
+}
+
+/// [`NewUser`] as sent, before `name` is reconciled with its parts
+#[derive(Deserialize, JsonSchema)]
+struct NewUserFields {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    first_name: Option<String>,
+    #[serde(default)]
+    last_name: Option<String>,
+    email: Email,
+}
+
+/// The schema of [`NewUserFields`], which is what clients send
+impl JsonSchema for NewUser {
+    fn schema_name() -> String {
+        "NewUser".to_string()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        NewUserFields::json_schema(generator)
+    }
+}
+
+impl TryFrom<NewUserFields> for NewUser {
+    type Error = String;
+
+    fn try_from(fields: NewUserFields) -> Result<Self, Self::Error> {
+        let combined = full_name(fields.first_name.as_deref(), fields.last_name.as_deref());
+        let name = match (fields.name, combined) {
+            (Some(name), Some(combined)) if name != combined => {
+                return Err(format!(
+                    "name must be \"{combined}\", the combined first_name and last_name"
+                ));
+            }
+            (_, Some(name)) | (Some(name), None) => name,
+            (None, None) => return Err("missing field `name`".to_string()),
+        };
+        Ok(Self {
+            name,
+            first_name: fields.first_name,
+            last_name: fields.last_name,
+            email: fields.email,
+        })
+    }
+}
+
+impl NewUser {
+    /// Check the fields that deserialization does not, see [`validate_name`]
+    /// and [`validate_name_part`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AppError::Validation`] describing the first problem found
+    pub fn validate(&self) -> Result<(), AppError> {
+        validate_name_parts(self.first_name.as_deref(), self.last_name.as_deref())
+            .and_then(|()| validate_name(&self.name))
+            .map_err(AppError::Validation)
+    }
+}
+
+/// The name formed by `first` and `last`, separated by a space
+///
+/// Blank parts are left out; `None` if neither part has any text.
+#[must_use]
+pub fn full_name(first: Option<&str>, last: Option<&str>) -> Option<String> {
+    let parts: Vec<&str> = [first, last]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Check a user's name
+///
+/// The name must not be blank. It is measured in grapheme clusters, so an
+/// emoji or an accented letter built from combining marks counts as one
+/// character, as a user would count it. Control characters (including NUL,
+/// tabs and newlines) are rejected.
+///
+/// # Errors
+///
+/// Returns a message describing the first problem found
+pub fn validate_name(name: &str) -> Result<(), String> {
+    validate_name_part("name", name)
+}
+
+/// Check one part of a user's name, such as `first_name`, by the rules of
+/// [`validate_name`]; messages start with `field`
+///
+/// # Errors
+///
+/// Returns a message describing the first problem found
+pub fn validate_name_part(field: &str, part: &str) -> Result<(), String> {
+    if part.trim().is_empty() {
+        return Err(format!("{field} must not be empty"));
+    }
+    if part.graphemes(true).count() > MAX_NAME_LEN {
+        return Err(format!("{field} must be at most {MAX_NAME_LEN} characters"));
+    }
+    if part.chars().any(char::is_control) {
+        return Err(format!("{field} must not contain control characters"));
+    }
+    Ok(())
+}
+
+/// Check whichever of `first_name` and `last_name` are given
+///
+/// # Errors
+///
+/// Returns a message describing the first problem found
+pub fn validate_name_parts(first: Option<&str>, last: Option<&str>) -> Result<(), String> {
+    if let Some(first) = first {
+        validate_name_part("first_name", first)?;
+    }
+    if let Some(last) = last {
+        validate_name_part("last_name", last)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_changed_fields() {
+        let before = User::builder().name("Ann").email("ann@example.com").build();
+        let after = User {
+            name: "Anne".to_string(),
+            email: "anne@example.com".to_string(),
+            updated_at: before.updated_at + Duration::seconds(5),
+            ..before.clone()
+        };
+
+        assert_eq!(
+            before.diff(&after),
+            [
+                FieldChange {
+                    field: "name",
+                    old: json!("Ann"),
+                    new: json!("Anne"),
+                },
+                FieldChange {
+                    field: "email",
+                    old: json!("ann@example.com"),
+                    new: json!("anne@example.com"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_ignores_updated_at_and_reports_unset_as_null() {
+        let before = User::builder().build();
+        let touched = User {
+            updated_at: before.updated_at + Duration::seconds(5),
+            ..before.clone()
+        };
+        assert!(before.diff(&touched).is_empty());
+
+        let named = User {
+            first_name: Some("Ann".to_string()),
+            ..before.clone()
+        };
+        let changes = before.diff(&named);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "first_name");
+        assert_eq!(changes[0].old, serde_json::Value::Null);
+        assert_eq!(
+            serde_json::to_value(&changes[0]).unwrap(),
+            json!({ "field": "first_name", "old": null, "new": "Ann" })
+        );
+    }
+
+    #[test]
+    fn test_applied_filters_omit_absent_criteria() {
+        let filter: UserFilter =
+            serde_json::from_str(r#"{"email_domain":"example.com","limit":5}"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(filter.applied()).unwrap(),
+            serde_json::json!({ "email_domain": "example.com", "sort": "id" })
+        );
+
+        let filter = UserFilter {
+            name_contains: Some("an".to_string()),
+            sort: UserSort::CreatedAtDesc,
+            include_deleted: true,
+            ..UserFilter::default()
+        };
+        assert_eq!(
+            serde_json::to_value(filter.applied()).unwrap(),
+            serde_json::json!({
+                "name_contains": "an",
+                "sort": "-created_at",
+                "include_deleted": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_new_user_with_valid_email() {
+        let user: NewUser =
+            serde_json::from_str(r#"{"name":"Jane","email":"jane@example.com"}"#).unwrap();
+        assert_eq!(user.name, "Jane");
+        assert_eq!(&*user.email, "jane@example.com");
+    }
+
+    #[test]
+    fn test_new_user_with_bad_email_fails() {
+        let err = serde_json::from_str::<NewUser>(r#"{"name":"Jane","email":"not-an-email"}"#)
+            .unwrap_err();
+        assert!(err.is_data());
+        assert!(err.to_string().contains("invalid email address"));
+    }
+
+    fn user_with_email(email: &str) -> User {
+        User::builder().name("Jane").email(email).build()
+    }
+
+    #[test]
+    fn test_user_builder_defaults() {
+        let before = Utc::now();
+        let user = User::builder().build();
+
+        assert_eq!(user.id, 1);
+        assert_eq!(user.name, "Test User");
+        assert_eq!(user.email, "user@example.com");
+        assert!(user.created_at >= before);
+        assert_eq!(user.updated_at, user.created_at);
+        assert_eq!(user.deleted_at, None);
+        assert!(validate_name(&user.name).is_ok());
+        assert!(Email::parse(user.email).is_ok());
+    }
+
+    #[test]
+    fn test_user_builder_setters() {
+        let created = Utc::now() - chrono::Duration::days(1);
+        let user = User::builder()
+            .id(7)
+            .name("Ann")
+            .email("ann@example.com")
+            .created_at(created)
+            .updated_at(created)
+            .build();
+
+        assert_eq!(user.id, 7);
+        assert_eq!(user.name, "Ann");
+        assert_eq!(user.email, "ann@example.com");
+        assert_eq!(user.created_at, created);
+        assert_eq!(user.updated_at, created);
+    }
+
+    #[test]
+    fn test_is_internal_email() {
+        let user = user_with_email("Jane@Example.COM");
+        assert!(user.is_internal_email("example.com"));
+        assert!(user.is_internal_email("EXAMPLE.com"));
+        assert!(!user.is_internal_email("example.org"));
+        assert!(!user.is_internal_email("ample.com"));
+
+        let sub = user_with_email("jane@mail.example.com");
+        assert!(!sub.is_internal_email("example.com"));
+        assert!(sub.is_internal_email("mail.example.com"));
+    }
+
+    #[test]
+    fn test_display_name() {
+        let user = user_with_email("jane.doe@example.com");
+        assert_eq!(user.display_name(), "Jane");
+
+        for blank in ["", "  "] {
+            let nameless = User {
+                name: blank.to_string(),
+                ..user.clone()
+            };
+            assert_eq!(nameless.display_name(), "jane.doe");
+        }
+    }
+
+    fn new_user(name: &str) -> NewUser {
+        NewUser {
+            name: name.to_string(),
+            first_name: None,
+            last_name: None,
+            email: Email::parse("jane@example.com").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_full_name() {
+        assert_eq!(full_name(Some("Jane"), Some("Doe")).unwrap(), "Jane Doe");
+        assert_eq!(full_name(Some(" Jane "), None).unwrap(), "Jane");
+        assert_eq!(full_name(Some(""), Some("Doe")).unwrap(), "Doe");
+        assert_eq!(full_name(None, None), None);
+        assert_eq!(full_name(Some(" "), None), None);
+    }
+
+    #[test]
+    fn test_new_user_name_from_parts() {
+        let user: NewUser = serde_json::from_str(
+            r#"{"first_name":"Jane","last_name":"Doe","email":"jane@example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(user.name, "Jane Doe");
+        assert_eq!(user.first_name.as_deref(), Some("Jane"));
+        assert_eq!(user.last_name.as_deref(), Some("Doe"));
+        assert!(user.validate().is_ok());
+
+        let user: NewUser = serde_json::from_str(
+            r#"{"name":"Jane Doe","first_name":"Jane","last_name":"Doe","email":"j@example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(user.name, "Jane Doe");
+    }
+
+    #[test]
+    fn test_new_user_name_must_match_parts() {
+        let err = serde_json::from_str::<NewUser>(
+            r#"{"name":"Janet","first_name":"Jane","last_name":"Doe","email":"j@example.com"}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("\"Jane Doe\""), "{err}");
+
+        let err = serde_json::from_str::<NewUser>(r#"{"email":"j@example.com"}"#).unwrap_err();
+        assert!(err.to_string().contains("missing field `name`"), "{err}");
+    }
+
+    #[test]
+    fn test_validate_checks_each_name_part() {
+        let mut user = new_user("Jane Doe");
+        user.first_name = Some("Jane\u{7}".to_string());
+        let err = user.validate().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("first_name must not contain control characters"),
+            "{err}"
+        );
+
+        user.first_name = Some("Jane".to_string());
+        user.last_name = Some("x".repeat(MAX_NAME_LEN + 1));
+        let err = user.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("last_name must be at most"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_validate_counts_graphemes() {
+        // A family emoji is five code points joined into one grapheme
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(family.chars().count(), 5);
+
+        assert!(new_user(&family.repeat(MAX_NAME_LEN)).validate().is_ok());
+        let err = new_user(&family.repeat(MAX_NAME_LEN + 1))
+            .validate()
+            .unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)));
+
+        // "e" followed by a combining acute accent is a single character
+        assert!(new_user(&"e\u{301}".repeat(MAX_NAME_LEN))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_control_characters() {
+        for name in ["Jane\0Doe", "Jane\u{7}", "Jane\nDoe"] {
+            let err = new_user(name).validate().unwrap_err();
+            assert!(err.to_string().contains("control characters"), "{name:?}");
+        }
+        assert!(new_user("Zoë 👩\u{200D}💻").validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("Jane Doe").is_ok());
+        assert!(validate_name(&"x".repeat(MAX_NAME_LEN)).is_ok());
+
+        assert_eq!(validate_name("").unwrap_err(), "name must not be empty");
+        assert_eq!(validate_name("   ").unwrap_err(), "name must not be empty");
+        assert!(validate_name(&"x".repeat(MAX_NAME_LEN + 1))
+            .unwrap_err()
+            .contains("at most"));
+        assert!(validate_name("Jane\tDoe")
+            .unwrap_err()
+            .contains("control characters"));
+    }
+}
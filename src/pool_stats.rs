@@ -0,0 +1,104 @@
+//! Periodic connection pool statistics
+//!
+//! Every `POOL_STATS_INTERVAL_SECS` the pool size, idle and in-use counts are
+//! logged at info level, so a connection leak shows up as `in_use` creeping
+//! towards the pool maximum over time.
+
+use crate::{state::AppState, tasks::TaskRegistry};
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Log the current size, idle and in-use counts of `pool`
+pub fn log(pool: &PgPool) {
+    let size = pool.size();
+    let idle = u32::try_from(pool.num_idle()).unwrap_or(u32::MAX);
+    tracing::info!(
+        size,
+        idle,
+        in_use = size.saturating_sub(idle),
+        "Database pool stats"
+    );
+}
+
+/// Log statistics of the current pool of `state` every `interval`
+///
+/// The first line is logged one interval after starting. The pool is looked
+/// up on every tick, so one replaced at runtime is reported from then on.
+/// Registered with `tasks`, so it stops on shutdown.
+///
+/// # Panics
+///
+/// Panics if `interval` is zero
+pub fn spawn(tasks: &mut TaskRegistry, state: AppState, interval: Duration) {
+    let start = tokio::time::Instant::now() + interval;
+    let mut ticks = tokio::time::interval_at(start, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    tasks.spawn("pool stats", move |mut shutdown| async move {
+        loop {
+            tokio::select! {
+                () = shutdown.requested() => return,
+                _ = ticks.tick() => log(&state.pool()),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{capture_logs, test_config, test_pool, test_state, unreachable_pool};
+
+    #[tokio::test]
+    async fn test_log_reports_pool_counts() {
+        let (logs, _guard) = capture_logs();
+
+        log(&unreachable_pool());
+
+        let logs = logs.contents();
+        assert!(logs.contains("Database pool stats"), "{logs}");
+        assert!(logs.contains("size=0 idle=0 in_use=0"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn test_task_stops_on_shutdown() {
+        let (logs, _guard) = capture_logs();
+        let lines = || logs.contents().matches("Database pool stats").count();
+        let mut tasks = TaskRegistry::new();
+
+        let state = test_state(unreachable_pool(), test_config());
+        spawn(&mut tasks, state, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tasks.shutdown(Duration::from_secs(1)).await.is_empty());
+        let logged = lines();
+        assert!(logged >= 1);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(lines(), logged);
+    }
+
+    #[tokio::test]
+    async fn test_task_reports_replaced_pool() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (logs, _guard) = capture_logs();
+        let mut tasks = TaskRegistry::new();
+        let held = pool.acquire().await.unwrap();
+        let state = test_state(pool, test_config());
+
+        spawn(&mut tasks, state.clone(), Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(logs.contents().contains("in_use=1"), "{}", logs.contents());
+        let previous = state.replace_pool(unreachable_pool());
+        let before = logs.contents().len();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let after = &logs.contents()[before..];
+        assert!(after.contains("size=0 idle=0 in_use=0"), "{after}");
+        assert!(!after.contains("in_use=1"), "{after}");
+        tasks.shutdown(Duration::from_secs(1)).await;
+        drop(held);
+        previous.close().await;
+    }
+}
@@ -0,0 +1,163 @@
+//! On-demand CPU profiling
+//!
+//! Compiled only with the `profiling` cargo feature, which production builds
+//! must leave off; even then the endpoint refuses to run when `APP_ENV=prod`.
+//! `GET /debug/profile?seconds=N` samples every thread of the process for
+//! `N` seconds and answers with a flamegraph SVG.
+
+use crate::{config::AppEnv, error::AppError, state::AppState};
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Capture length when `seconds` is not given
+const DEFAULT_SECONDS: u64 = 5;
+
+/// Longest capture, well inside the default `REQUEST_TIMEOUT_SECS`
+const MAX_SECONDS: u64 = 20;
+
+/// Samples per second; not a multiple of common timer rates, to avoid
+/// sampling in lockstep with periodic work
+const FREQUENCY: i32 = 99;
+
+/// Query of `GET /debug/profile`
+#[derive(Debug, Deserialize)]
+pub struct ProfileQuery {
+    /// How long to sample for
+    seconds: Option<u64>,
+}
+
+/// `GET /debug/profile?seconds=` - CPU profile of the process as a flamegraph
+///
+/// Only one profile can be captured at a time; a concurrent request gets
+/// `409`. An idle process yields no samples, answered with `204`.
+///
+/// # Errors
+///
+/// Returns [`AppError::Forbidden`] in production, [`AppError::BadRequest`]
+/// for an out-of-range `seconds`, and [`AppError::Conflict`] while another
+/// profile is being captured
+pub async fn profile(
+    State(state): State<AppState>,
+    Query(query): Query<ProfileQuery>,
+) -> Result<Response, AppError> {
+    if state.config.app_env == AppEnv::Prod {
+        tracing::warn!("Refused to profile in production");
+        return Err(AppError::Forbidden);
+    }
+    let seconds = query.seconds.unwrap_or(DEFAULT_SECONDS);
+    if !(1..=MAX_SECONDS).contains(&seconds) {
+        return Err(AppError::BadRequest(format!(
+            "seconds must be between 1 and {MAX_SECONDS}"
+        )));
+    }
+
+    tracing::info!(seconds, "Capturing CPU profile");
+    let svg = tokio::task::spawn_blocking(move || capture(Duration::from_secs(seconds)))
+        .await
+        .map_err(|e| AppError::Internal(format!("profiler task failed: {e}")))??;
+    match svg {
+        Some(svg) => Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+/// Sample the process for `duration` and render the samples as an SVG
+///
+/// Returns `Ok(None)` if the process used no CPU meanwhile.
+fn capture(duration: Duration) -> Result<Option<Vec<u8>>, AppError> {
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|e| AppError::Conflict(format!("profiler unavailable: {e}")))?;
+    std::thread::sleep(duration);
+    let report = guard
+        .report()
+        .build()
+        .map_err(|e| AppError::Internal(format!("building profile failed: {e}")))?;
+
+    if report.data.is_empty() {
+        return Ok(None);
+    }
+
+    let mut svg = Vec::new();
+    report
+        .flamegraph(&mut svg)
+        .map_err(|e| AppError::Internal(format!("rendering flamegraph failed: {e}")))?;
+    Ok(Some(svg))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        build_app,
+        config::{AppEnv, Config},
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+        response::Response,
+    };
+    use std::{
+        hint::black_box,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+    use tower::ServiceExt;
+
+    async fn get(config: Config, uri: &str) -> Response {
+        let app = build_app(test_state(unreachable_pool(), config));
+        app.oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_profile_returns_flamegraph() {
+        // Give the profiler something to sample until the capture is over
+        let done = Arc::new(AtomicBool::new(false));
+        let busy = std::thread::spawn({
+            let done = done.clone();
+            move || {
+                let mut n = 0u64;
+                while !done.load(Ordering::Relaxed) {
+                    n = black_box(n.wrapping_mul(31).wrapping_add(7));
+                }
+                n
+            }
+        });
+
+        let response = get(test_config(), "/debug/profile?seconds=1").await;
+        done.store(true, Ordering::Relaxed);
+        busy.join().unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(!body.is_empty());
+        assert!(String::from_utf8_lossy(&body).contains("<svg"));
+    }
+
+    #[tokio::test]
+    async fn test_profile_rejects_bad_duration_and_prod() {
+        for uri in ["/debug/profile?seconds=0", "/debug/profile?seconds=21"] {
+            let response = get(test_config(), uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        let config = Config {
+            app_env: AppEnv::Prod,
+            ..test_config()
+        };
+        let response = get(config, "/debug/profile?seconds=1").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,212 @@
+//! Per-request query budget
+//!
+//! The [`count_queries`] middleware counts the database queries each request
+//! issues and logs the total, so N+1 patterns show up as handlers grow. The
+//! [`repository`](crate::repository) functions call [`record`] before each
+//! statement they send, so reads served from a cache are not counted. With
+//! `MAX_QUERIES_PER_REQUEST` set, a query past the budget fails with `500`
+//! outside production; in production the request is only logged.
+//!
+//! Work that outlives the handler, such as a streamed response body, keeps
+//! counting against the request through [`RequestBudget::scope`]; the total
+//! is logged once that work is done too.
+
+use crate::{config::AppEnv, error::AppError, state::AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Queries issued so far by a request, and its budget
+struct Budget {
+    queries: AtomicUsize,
+    max: usize,
+    enforced: bool,
+}
+
+impl Drop for Budget {
+    fn drop(&mut self) {
+        let queries = *self.queries.get_mut();
+        tracing::debug!(queries, "Database queries issued by the request");
+        if self.max > 0 && queries > self.max {
+            tracing::warn!(
+                queries,
+                max = self.max,
+                "Request exceeded MAX_QUERIES_PER_REQUEST"
+            );
+        }
+    }
+}
+
+tokio::task_local! {
+    static BUDGET: Arc<Budget>;
+}
+
+/// Handle on the budget of the request being handled, if any
+///
+/// Taken in a handler and moved into work that runs after it returns, so
+/// that work is counted against the same request.
+#[derive(Clone, Default)]
+pub struct RequestBudget(Option<Arc<Budget>>);
+
+impl RequestBudget {
+    /// The budget of the current request; empty outside a request
+    #[must_use]
+    pub fn current() -> Self {
+        Self(BUDGET.try_with(Arc::clone).ok())
+    }
+
+    /// Run `future` with its queries counted against this budget
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        match self.0 {
+            Some(budget) => BUDGET.scope(budget, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Count a query against the current request's budget
+///
+/// Outside a request, such as in background tasks, nothing is counted.
+///
+/// # Errors
+///
+/// Returns [`AppError::QueryBudgetExceeded`] if the query would exceed an
+/// enforced budget; the query must then not be issued
+pub fn record() -> Result<(), AppError> {
+    BUDGET
+        .try_with(|budget| {
+            let queries = budget.queries.fetch_add(1, Ordering::Relaxed) + 1;
+            if budget.enforced && queries > budget.max {
+                return Err(AppError::QueryBudgetExceeded { budget: budget.max });
+            }
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
+
+/// Middleware counting the queries each request issues
+pub async fn count_queries(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let max = state.config.max_queries_per_request;
+    let budget = Budget {
+        queries: AtomicUsize::new(0),
+        max,
+        enforced: max > 0 && state.config.app_env != AppEnv::Prod,
+    };
+    BUDGET.scope(Arc::new(budget), next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        repository,
+        test_utils::{capture_logs, get_body, test_config, test_pool, test_state},
+    };
+    use axum::{http::StatusCode, middleware, routing::get, Router};
+
+    /// A handler fetching in a loop, as an N+1 pattern would
+    async fn five_queries(State(state): State<AppState>) -> Result<StatusCode, AppError> {
+        for _ in 0..5 {
+            repository::get_user_by_id(&state.pool(), 1).await?;
+        }
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    fn app(state: AppState) -> Router {
+        Router::new()
+            .route("/loop", get(five_queries))
+            .layer(middleware::from_fn_with_state(state.clone(), count_queries))
+            .with_state(state)
+    }
+
+    fn budget_config(app_env: AppEnv, max: usize) -> Config {
+        Config {
+            app_env,
+            max_queries_per_request: max,
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_counts_only_inside_a_request() {
+        record().unwrap();
+
+        let budget = Budget {
+            queries: AtomicUsize::new(0),
+            max: 2,
+            enforced: true,
+        };
+        let (results, carried) = BUDGET
+            .scope(Arc::new(budget), async {
+                ([record(), record()], RequestBudget::current())
+            })
+            .await;
+        assert!(results.iter().all(Result::is_ok));
+
+        // Counted against the same request after its scope has ended
+        let late = carried.scope(async { record() }).await;
+        assert!(matches!(
+            late,
+            Err(AppError::QueryBudgetExceeded { budget: 2 })
+        ));
+        RequestBudget::current()
+            .scope(async { record() })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_budget_enforced_outside_prod() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = app(test_state(pool, budget_config(AppEnv::Dev, 2)));
+
+        let (status, body) = get_body(app, "/loop").await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("MAX_QUERIES_PER_REQUEST"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_budget_only_logged_in_prod() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (logs, _guard) = capture_logs();
+
+        let (status, _) = get_body(
+            app(test_state(pool.clone(), budget_config(AppEnv::Prod, 2))),
+            "/loop",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let output = logs.contents();
+        assert!(
+            output.contains("Request exceeded MAX_QUERIES_PER_REQUEST"),
+            "{output}"
+        );
+        assert!(output.contains("queries=5"), "{output}");
+
+        let (status, _) = get_body(
+            app(test_state(pool, budget_config(AppEnv::Dev, 5))),
+            "/loop",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,86 @@
+//! Queries against the `audit_log` table
+
+use crate::{
+    error::AppError,
+    models::{AuditEntry, PageParams},
+    query_budget,
+};
+use sqlx::PgExecutor;
+
+use super::users::clamp_page;
+
+/// Fetch a page of a user's audit entries, oldest first
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn find_audit_entries<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    page: &PageParams,
+) -> Result<Vec<AuditEntry>, AppError> {
+    let (limit, offset) = clamp_page(page.limit, page.offset);
+    query_budget::record()?;
+    sqlx::query_as::<_, AuditEntry>(
+        "SELECT action, created_at FROM audit_log WHERE user_id = $1 \
+         ORDER BY id LIMIT $2 OFFSET $3",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Count all audit entries recorded for a user
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_audit_entries<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool};
+
+    #[tokio::test]
+    async fn test_audit_entries_follow_writes_in_order() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Ann", "ann@example.com").await;
+        sqlx::query("UPDATE users SET name = 'Anne' WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let entries = find_audit_entries(&pool, user.id, &PageParams::default())
+            .await
+            .unwrap();
+        let actions: Vec<_> = entries.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, ["create", "update"]);
+        assert!(entries[0].created_at <= entries[1].created_at);
+        assert_eq!(count_audit_entries(&pool, user.id).await.unwrap(), 2);
+
+        let page = PageParams {
+            limit: Some(1),
+            offset: Some(1),
+        };
+        let entries = find_audit_entries(&pool, user.id, &page).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "update");
+    }
+}
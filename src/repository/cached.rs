@@ -0,0 +1,365 @@
+//! Cached reads of single users
+//!
+//! [`CachedUserRepo`] serves `get_user_by_id` from a bounded, expiring
+//! [`LruCache`] and coalesces concurrent misses for the same id into one
+//! query. Writes made through this service invalidate the affected entry;
+//! writes made elsewhere, such as by another instance, show up once the
+//! entry expires.
+//!
+//! The bulk writes of [`repository`](super) are run through the cache too,
+//! with [`CachedUserRepo::batch_update_emails`],
+//! [`CachedUserRepo::swap_emails`] and [`CachedUserRepo::bulk_upsert_users`],
+//! which evict the users they changed once the write has committed. Imports
+//! only insert new users, and misses are never cached, so they are found
+//! right away.
+
+use super::get_user_by_id;
+use crate::{
+    cache::{LruCache, SingleFlight},
+    error::AppError,
+    models::{NewUser, UpsertCounts, User},
+};
+use arc_swap::ArcSwap;
+use sqlx::PgPool;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+/// Where a [`CachedUserRepo`] loads users it does not hold
+pub trait UserSource: Send + Sync {
+    /// The live user with `id`, if any
+    fn get_user_by_id(
+        &self,
+        id: i32,
+    ) -> impl Future<Output = Result<Option<User>, AppError>> + Send;
+}
+
+/// Users read from the database through the service's current pool
+///
+/// Shares the pool handle of [`AppState`](crate::state::AppState), so a pool
+/// replaced at runtime is used from the next load on.
+#[derive(Debug, Clone)]
+pub struct PgUsers {
+    pool: Arc<ArcSwap<PgPool>>,
+}
+
+impl PgUsers {
+    /// Load users through whichever pool `pool` holds at the time
+    #[must_use]
+    pub const fn new(pool: Arc<ArcSwap<PgPool>>) -> Self {
+        Self { pool }
+    }
+}
+
+impl UserSource for PgUsers {
+    async fn get_user_by_id(&self, id: i32) -> Result<Option<User>, AppError> {
+        let pool = PgPool::clone(&self.pool.load());
+        get_user_by_id(&pool, id).await
+    }
+}
+
+/// Cached entries and the number of invalidations so far
+///
+/// A load only fills the cache if no invalidation happened while it ran, so
+/// a write racing a read cannot leave the pre-write user cached.
+#[derive(Debug)]
+struct Entries {
+    users: LruCache<i32, User>,
+    generation: u64,
+}
+
+/// Read-through cache of users by id, in front of a [`UserSource`]
+///
+/// Only existing users are cached: a miss for an unknown id is not
+/// remembered, so a user created afterwards is found right away.
+#[derive(Debug)]
+pub struct CachedUserRepo<R> {
+    source: R,
+    entries: Mutex<Entries>,
+    loads: SingleFlight<(i32, u64), Option<User>>,
+}
+
+impl<R: UserSource> CachedUserRepo<R> {
+    /// Cache at most `capacity` users from `source`, each for `ttl`
+    #[must_use]
+    pub fn new(source: R, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            source,
+            entries: Mutex::new(Entries {
+                users: LruCache::new(capacity, ttl),
+                generation: 0,
+            }),
+            loads: SingleFlight::default(),
+        }
+    }
+
+    /// The live user with `id`, from the cache or else from the source
+    ///
+    /// Concurrent misses for the same id share one load from the source.
+    ///
+    /// # Errors
+    ///
+    /// Returns the source's error; nothing is cached in that case
+    pub async fn get_user_by_id(&self, id: i32) -> Result<Option<User>, AppError> {
+        let generation = {
+            let mut entries = self.lock();
+            if let Some(user) = entries.users.get(&id) {
+                return Ok(Some(user));
+            }
+            entries.generation
+        };
+
+        // Keyed by generation too, so a read after a write never joins a
+        // load that started before it
+        let user = self
+            .loads
+            .run((id, generation), || self.source.get_user_by_id(id))
+            .await?;
+        if let Some(user) = &user {
+            let mut entries = self.lock();
+            if entries.generation == generation {
+                entries.users.insert(id, user.clone());
+            }
+        }
+        Ok(user)
+    }
+
+    /// Forget the user with `id`, after it was changed or deleted
+    pub fn invalidate(&self, id: i32) {
+        let mut entries = self.lock();
+        entries.generation += 1;
+        entries.users.remove(&id);
+    }
+
+    /// Forget every user, after a bulk change
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.generation += 1;
+        entries.users.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Bulk writes that keep the cache in step
+///
+/// Each runs on the current pool, so it has committed by the time the
+/// affected users are evicted and a read cannot cache them again from before
+/// the write.
+impl CachedUserRepo<PgUsers> {
+    /// [`batch_update_emails`](super::batch_update_emails), then forget every
+    /// user, as any of them may have been renamed
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the update; the cache is left alone then
+    pub async fn batch_update_emails(
+        &self,
+        old_domain: &str,
+        new_domain: &str,
+    ) -> Result<u64, AppError> {
+        let pool = PgPool::clone(&self.source.pool.load());
+        let updated = super::batch_update_emails(&pool, old_domain, new_domain).await?;
+        if updated > 0 {
+            self.clear();
+        }
+        Ok(updated)
+    }
+
+    /// [`swap_emails`](super::swap_emails), then forget both users
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the swap; the cache is left alone then
+    pub async fn swap_emails(&self, id_a: i32, id_b: i32) -> Result<(User, User), AppError> {
+        let pool = PgPool::clone(&self.source.pool.load());
+        let swapped = super::swap_emails(&pool, id_a, id_b).await?;
+        self.invalidate(id_a);
+        self.invalidate(id_b);
+        Ok(swapped)
+    }
+
+    /// [`bulk_upsert_users`](super::bulk_upsert_users), then forget every
+    /// user, as any of them may have been overwritten
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the upsert; the cache is left alone then
+    pub async fn bulk_upsert_users(&self, users: &[NewUser]) -> Result<UpsertCounts, AppError> {
+        let pool = PgPool::clone(&self.source.pool.load());
+        let counts = super::bulk_upsert_users(&pool, users).await?;
+        if counts.updated > 0 {
+            self.clear();
+        }
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::Email,
+        test_utils::{insert_user, test_pool},
+    };
+    use chrono::Utc;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// In-memory users, counting how often they are loaded
+    #[derive(Default)]
+    struct CountingUsers {
+        users: Mutex<HashMap<i32, User>>,
+        loads: AtomicUsize,
+    }
+
+    impl CountingUsers {
+        fn with(users: &[(i32, &str)]) -> Self {
+            let source = Self::default();
+            for &(id, name) in users {
+                source.put(id, name);
+            }
+            source
+        }
+
+        fn put(&self, id: i32, name: &str) {
+            let now = Utc::now();
+            let user = User {
+                id,
+                name: name.to_string(),
+                first_name: None,
+                last_name: None,
+                email: format!("{id}@example.com"),
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+            };
+            self.users.lock().unwrap().insert(id, user);
+        }
+
+        fn loads(&self) -> usize {
+            self.loads.load(Ordering::SeqCst)
+        }
+    }
+
+    impl UserSource for CountingUsers {
+        async fn get_user_by_id(&self, id: i32) -> Result<Option<User>, AppError> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(self.users.lock().unwrap().get(&id).cloned())
+        }
+    }
+
+    fn repo(users: &[(i32, &str)]) -> CachedUserRepo<CountingUsers> {
+        CachedUserRepo::new(CountingUsers::with(users), 10, Duration::from_secs(30))
+    }
+
+    async fn name(repo: &CachedUserRepo<CountingUsers>, id: i32) -> Option<String> {
+        repo.get_user_by_id(id).await.unwrap().map(|user| user.name)
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_skips_the_source() {
+        let repo = repo(&[(1, "Ann")]);
+
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("Ann"));
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("Ann"));
+
+        assert_eq!(repo.source.loads(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_load() {
+        let repo = repo(&[(1, "Ann")]);
+
+        let names = futures_util::future::join_all((0..10).map(|_| name(&repo, 1))).await;
+
+        assert!(names.iter().all(|name| name.as_deref() == Some("Ann")));
+        assert_eq!(repo.source.loads(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_after_update_reloads() {
+        let repo = repo(&[(1, "Ann"), (2, "Bob")]);
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("Ann"));
+        assert_eq!(name(&repo, 2).await.as_deref(), Some("Bob"));
+
+        repo.source.put(1, "Anna");
+        repo.invalidate(1);
+
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("Anna"));
+        assert_eq!(name(&repo, 2).await.as_deref(), Some("Bob"));
+        assert_eq!(repo.source.loads(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_missing_users_are_not_cached() {
+        let repo = repo(&[]);
+        assert_eq!(name(&repo, 1).await, None);
+
+        repo.source.put(1, "New");
+
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("New"));
+        assert_eq!(repo.source.loads(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_load_racing_an_invalidation_is_not_cached() {
+        let repo = repo(&[(1, "Ann")]);
+
+        let (stale, ()) = tokio::join!(name(&repo, 1), async {
+            tokio::task::yield_now().await;
+            repo.source.put(1, "Anna");
+            repo.invalidate(1);
+        });
+
+        // The load may have read either version, but must not keep it
+        assert!(stale.is_some());
+        assert_eq!(name(&repo, 1).await.as_deref(), Some("Anna"));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_writes_evict_changed_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let ann = insert_user(&pool, "Ann", "ann@old.example").await;
+        let ben = insert_user(&pool, "Ben", "ben@other.example").await;
+        let repo = CachedUserRepo::new(
+            PgUsers::new(Arc::new(ArcSwap::from_pointee(pool))),
+            10,
+            Duration::from_secs(30),
+        );
+        let email = |id| {
+            let repo = &repo;
+            async move { repo.get_user_by_id(id).await.unwrap().unwrap().email }
+        };
+        assert_eq!(email(ann.id).await, "ann@old.example");
+        assert_eq!(email(ben.id).await, "ben@other.example");
+
+        repo.batch_update_emails("old.example", "new.example")
+            .await
+            .unwrap();
+        assert_eq!(email(ann.id).await, "ann@new.example");
+
+        repo.swap_emails(ann.id, ben.id).await.unwrap();
+        assert_eq!(email(ann.id).await, "ben@other.example");
+        assert_eq!(email(ben.id).await, "ann@new.example");
+
+        let renamed = NewUser {
+            name: "Benjamin".to_string(),
+            first_name: None,
+            last_name: None,
+            email: Email::parse("ann@new.example").unwrap(),
+        };
+        repo.bulk_upsert_users(&[renamed]).await.unwrap();
+        let ben = repo.get_user_by_id(ben.id).await.unwrap().unwrap();
+        assert_eq!(ben.name, "Benjamin");
+    }
+}
@@ -0,0 +1,124 @@
+//! Queries against the `idempotency_keys` table
+
+use crate::{error::AppError, query_budget};
+use sqlx::{PgConnection, PgExecutor};
+use std::time::Duration;
+
+/// How long a stored response is replayed for retries of its key
+pub const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_hours(24);
+
+/// Longest accepted idempotency key, matching the column width
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Response recorded for an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct StoredResponse {
+    /// HTTP status code
+    pub status: i16,
+    /// Response body
+    pub body: String,
+}
+
+/// Lock `key` for the rest of the transaction and fetch its stored response
+///
+/// Concurrent requests with the same key wait here until the first one
+/// commits or rolls back, so only one of them performs the work. Expired
+/// responses are ignored. Must run inside a transaction on `conn`, which
+/// should then store the response with [`store_idempotent_response`].
+///
+/// # Errors
+///
+/// Returns an error if a query fails
+pub async fn lock_idempotency_key(
+    conn: &mut PgConnection,
+    key: &str,
+) -> Result<Option<StoredResponse>, AppError> {
+    query_budget::record()?;
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    query_budget::record()?;
+    sqlx::query_as::<_, StoredResponse>(
+        "SELECT status, body FROM idempotency_keys WHERE key = $1 AND expires_at > NOW()",
+    )
+    .bind(key)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Record the response for `key`, replacing an expired one
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn store_idempotent_response<'e>(
+    executor: impl PgExecutor<'e>,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<(), AppError> {
+    query_budget::record()?;
+    sqlx::query(
+        "INSERT INTO idempotency_keys (key, status, body, expires_at) \
+         VALUES ($1, $2, $3, NOW() + make_interval(secs => $4)) \
+         ON CONFLICT (key) DO UPDATE \
+         SET status = EXCLUDED.status, body = EXCLUDED.body, expires_at = EXCLUDED.expires_at",
+    )
+    .bind(key)
+    .bind(response.status)
+    .bind(&response.body)
+    .bind(IDEMPOTENCY_KEY_TTL.as_secs_f64())
+    .execute(executor)
+    .await
+    .map(|_| ())
+    .map_err(AppError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    #[tokio::test]
+    async fn test_stored_response_is_found_and_expired_one_replaced() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let stored = StoredResponse {
+            status: 201,
+            body: r#"{"id":1}"#.to_string(),
+        };
+
+        let mut tx = pool.begin().await.unwrap();
+        assert_eq!(lock_idempotency_key(&mut tx, "k1").await.unwrap(), None);
+        store_idempotent_response(&mut *tx, "k1", &stored)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(
+            lock_idempotency_key(&mut conn, "k1").await.unwrap(),
+            Some(stored.clone())
+        );
+
+        sqlx::query("UPDATE idempotency_keys SET expires_at = NOW() - INTERVAL '1 second'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(lock_idempotency_key(&mut conn, "k1").await.unwrap(), None);
+
+        let replaced = StoredResponse {
+            status: 201,
+            body: r#"{"id":2}"#.to_string(),
+        };
+        store_idempotent_response(&pool, "k1", &replaced)
+            .await
+            .unwrap();
+        assert_eq!(
+            lock_idempotency_key(&mut conn, "k1").await.unwrap(),
+            Some(replaced)
+        );
+    }
+}
@@ -1,3 +1,1024 @@
 //! Database repository module
 //!
 //! This module contains all database interaction logic and queries.
+
+mod audit;
+mod cached;
+mod idempotency;
+mod schema;
+mod users;
+
+pub use audit::{count_audit_entries, find_audit_entries};
+pub use cached::{CachedUserRepo, PgUsers, UserSource};
+pub use idempotency::{
+    lock_idempotency_key, store_idempotent_response, StoredResponse, IDEMPOTENCY_KEY_TTL,
+    MAX_IDEMPOTENCY_KEY_LEN,
+};
+pub use schema::{ensure_schema, SchemaError};
+pub use users::{
+    analyze_users, batch_update_emails, bulk_upsert_users, clamp_page, count_active_since,
+    count_email_domains, count_users, count_users_by_created_month, count_users_created_today,
+    create_user, delete_user, email_exists_case_insensitive, estimate_user_count,
+    find_duplicate_emails, find_user_summaries, find_users, get_or_create_user, get_user_by_email,
+    get_user_by_id, get_user_changes, get_user_columns, get_user_page, insert_user_with_id,
+    list_email_domains, list_user_summaries, page_bounds, purge_all, reset_user_id_sequence,
+    search_users, soft_delete_user, stream_search, swap_emails, touch_logins, touch_updated_at,
+    update_user_returning_prev,
+};
+
+use crate::{
+    config::{Config, RetryJitter, SslMode},
+    deadline::Deadline,
+    error::AppError,
+};
+use sqlx::{
+    error::DatabaseError,
+    migrate::{MigrateError, Migrator},
+    pool::PoolConnection,
+    postgres::{PgConnectOptions, PgConnection, PgListener, PgPool, PgPoolOptions, PgSslMode},
+    Connection, Postgres, Transaction,
+};
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// SQLSTATE raised when a serializable transaction cannot be committed
+pub const SERIALIZATION_FAILURE: &str = "40001";
+
+/// SQLSTATE raised when the server has no connection slots left
+pub const TOO_MANY_CONNECTIONS: &str = "53300";
+
+/// SQLSTATE raised when a statement is cancelled, e.g. by `statement_timeout`
+pub const QUERY_CANCELED: &str = "57014";
+
+/// SQLSTATE raised when an insert or update breaks a unique constraint
+pub const UNIQUE_VIOLATION: &str = "23505";
+
+/// SQLSTATE raised when a value does not fit its integer column
+pub const NUMERIC_VALUE_OUT_OF_RANGE: &str = "22003";
+
+/// SQLSTATE raised when a sequence reaches its maximum value
+pub const SEQUENCE_LIMIT_EXCEEDED: &str = "2200H";
+
+/// Create the `PostgreSQL` connection pool
+///
+/// The pool is created lazily: no connection is attempted until the first
+/// query, so the server can start (and report itself unready) while the
+/// database is still coming up.
+///
+/// # Errors
+///
+/// Returns an error if the connection URL cannot be parsed
+pub fn create_pool(config: &Config) -> Result<PgPool, sqlx::Error> {
+    Ok(pool_options(config).connect_lazy_with(connect_options(config)?))
+}
+
+/// Build a fresh pool to replace one stuck in a bad state, e.g. after failover
+///
+/// Unlike [`create_pool`] this connects eagerly, so a database that is still
+/// unreachable is reported instead of swapped in.
+///
+/// # Errors
+///
+/// Returns an error if the connection URL cannot be parsed or the first
+/// connection fails
+pub async fn reconnect(config: &Config) -> Result<PgPool, sqlx::Error> {
+    pool_options(config)
+        .connect_with(connect_options(config)?)
+        .await
+}
+
+/// Pool settings derived from the configuration
+///
+/// Instead of pinging every connection it hands out, the pool pings only
+/// those idle for `DB_CONN_MAX_IDLE_PING_SECS` or longer, the ones a
+/// database or firewall idle timeout may have closed, and replaces a
+/// connection whose ping fails.
+///
+/// `DB_FAIR_ACQUIRE` is not applied: sqlx has no documented switch for the
+/// acquisition order, and its pools always hand out connections first come,
+/// first served. The setting is only logged.
+fn pool_options(config: &Config) -> PgPoolOptions {
+    tracing::debug!(
+        fair_acquire = config.db_fair_acquire,
+        "Ignoring DB_FAIR_ACQUIRE; pooled connections are always issued in request order"
+    );
+    let max_idle = Duration::from_secs(config.db_conn_max_idle_ping_secs);
+    PgPoolOptions::new()
+        .max_connections(10)
+        .acquire_timeout(Duration::from_secs(3))
+        .test_before_acquire(false)
+        .before_acquire(move |conn, meta| {
+            Box::pin(async move {
+                if meta.idle_for < max_idle {
+                    return Ok(true);
+                }
+                let alive = sqlx::Connection::ping(conn).await.is_ok();
+                if !alive {
+                    tracing::debug!(
+                        idle_ms = meta.idle_for.as_millis(),
+                        "Discarding stale pooled connection"
+                    );
+                }
+                Ok(alive)
+            })
+        })
+}
+
+/// Connection options derived from the database URL and overrides
+fn connect_options(config: &Config) -> Result<PgConnectOptions, sqlx::Error> {
+    let mut options = PgConnectOptions::from_str(&config.database_url)?;
+    if let Some(mode) = config.db_ssl_mode {
+        options = options.ssl_mode(pg_ssl_mode(mode));
+    }
+    for (key, value) in &config.db_extra_params {
+        options = if key == "application_name" {
+            options.application_name(value)
+        } else {
+            options.options([(key, value)])
+        };
+    }
+    Ok(options)
+}
+
+const fn pg_ssl_mode(mode: SslMode) -> PgSslMode {
+    match mode {
+        SslMode::Disable => PgSslMode::Disable,
+        SslMode::Allow => PgSslMode::Allow,
+        SslMode::Prefer => PgSslMode::Prefer,
+        SslMode::Require => PgSslMode::Require,
+        SslMode::VerifyCa => PgSslMode::VerifyCa,
+        SslMode::VerifyFull => PgSslMode::VerifyFull,
+    }
+}
+
+/// Base delay before retrying a timed-out acquire; doubles per attempt
+const ACQUIRE_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Acquire a pooled connection, retrying up to `retries` times on timeout
+///
+/// Each retry waits a random fraction of an exponentially growing delay, so
+/// a burst of waiters does not retry in lockstep. Only
+/// [`sqlx::Error::PoolTimedOut`] is retried.
+///
+/// # Errors
+///
+/// Returns the last acquire error once the retries are used up
+pub async fn acquire(pool: &PgPool, retries: u32) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+    let mut attempt = 0;
+    loop {
+        match pool.acquire().await {
+            Err(sqlx::Error::PoolTimedOut) if attempt < retries => {
+                let delay = next_backoff(attempt, ACQUIRE_BACKOFF, RetryJitter::Full);
+                tracing::debug!(attempt, ?delay, "Pool acquire timed out, retrying");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Longest delay [`next_backoff`] grows to
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Delay before retry `attempt`, counting from 0
+///
+/// The delay doubles from `base` with every attempt up to [`MAX_BACKOFF`],
+/// then `jitter` randomizes it: [`RetryJitter::Full`] picks anything below
+/// it, [`RetryJitter::Equal`] at least half of it, and
+/// [`RetryJitter::None`] uses it as is.
+#[must_use]
+pub fn next_backoff(attempt: u32, base: Duration, jitter: RetryJitter) -> Duration {
+    let ceiling = base
+        .saturating_mul(2u32.saturating_pow(attempt.min(16)))
+        .min(MAX_BACKOFF);
+    match jitter {
+        RetryJitter::Full => random_below(ceiling),
+        RetryJitter::Equal => {
+            let half = ceiling / 2;
+            half + random_below(ceiling.saturating_sub(half))
+        }
+        RetryJitter::None => ceiling,
+    }
+}
+
+/// A random duration below `ceiling`, or zero if `ceiling` is zero
+fn random_below(ceiling: Duration) -> Duration {
+    let ceiling_nanos = u64::try_from(ceiling.as_nanos()).unwrap_or(u64::MAX);
+    if ceiling_nanos == 0 {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % ceiling_nanos)
+}
+
+/// Run a query, abandoning it if `deadline` passes first
+///
+/// Dropping the query future cancels it; the connection it was using is
+/// closed rather than returned to the pool.
+///
+/// # Errors
+///
+/// Returns [`AppError::DeadlineExceeded`] if the deadline passes, or the
+/// error of `query`
+pub async fn with_deadline<T, E, F>(deadline: Deadline, query: F) -> Result<T, AppError>
+where
+    F: Future<Output = Result<T, E>>,
+    AppError: From<E>,
+{
+    match deadline.instant() {
+        Some(at) => tokio::time::timeout_at(at, query)
+            .await
+            .map_err(|_| AppError::DeadlineExceeded)?
+            .map_err(AppError::from),
+        None => query.await.map_err(AppError::from),
+    }
+}
+
+/// Verify connectivity with a trivial round trip
+///
+/// # Errors
+///
+/// Returns an error if a connection cannot be acquired or the query fails
+pub async fn ping(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT 1").execute(pool).await.map(|_| ())
+}
+
+/// The version of the `PostgreSQL` server, e.g. `16.2`
+///
+/// # Errors
+///
+/// Returns an error if a connection cannot be acquired or the query fails
+pub async fn server_version(pool: &PgPool) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar("SHOW server_version")
+        .fetch_one(pool)
+        .await
+}
+
+/// Verify the database accepts writes
+///
+/// Inserts into `health_probe` inside a transaction that is rolled back, so
+/// a server that answers reads but is in read-only mode (for example a
+/// replica promoted incorrectly) fails the check.
+///
+/// # Errors
+///
+/// Returns an error if a connection cannot be acquired or the write fails
+pub async fn healthcheck_full(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO health_probe DEFAULT VALUES")
+        .execute(&mut *tx)
+        .await?;
+    tx.rollback().await
+}
+
+/// Outcome of a [`run_readonly_check`]
+#[derive(Debug)]
+pub struct CheckResult {
+    /// Name the check was run under
+    pub name: &'static str,
+    /// How long the check took, including acquiring a connection
+    pub elapsed: Duration,
+    /// Why the check failed; `None` if it passed
+    pub error: Option<sqlx::Error>,
+}
+
+impl CheckResult {
+    /// Whether the query ran without error
+    #[must_use]
+    pub const fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Run the health check `query` in a read-only transaction and time it
+///
+/// Only queries compiled into the binary can be passed, and the transaction
+/// is `READ ONLY` and always rolled back, so a check that tries to modify
+/// data fails with SQLSTATE `25006` instead of writing. Failures are logged
+/// and reported in the result rather than returned as an error.
+pub async fn run_readonly_check(
+    pool: &PgPool,
+    name: &'static str,
+    query: &'static str,
+) -> CheckResult {
+    let started = Instant::now();
+    let outcome = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SET TRANSACTION READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(query).execute(&mut *tx).await?;
+        tx.rollback().await
+    }
+    .await;
+    let elapsed = started.elapsed();
+    if let Err(e) = &outcome {
+        tracing::warn!(
+            check = name,
+            elapsed_ms = elapsed.as_millis(),
+            error = %e,
+            "Health check failed"
+        );
+    }
+    CheckResult {
+        name,
+        elapsed,
+        error: outcome.err(),
+    }
+}
+
+/// Channel notified by a trigger whenever a row of `users` changes
+pub const USERS_CHANGED_CHANNEL: &str = "users_changed";
+
+/// Open a listener subscribed to [`USERS_CHANGED_CHANNEL`]
+///
+/// Each notification's payload is a JSON object `{"op": ..., "id": ...}`
+/// where `op` is `INSERT`, `UPDATE` or `DELETE`.
+///
+/// # Errors
+///
+/// Returns an error if connecting or subscribing fails
+pub async fn listen_user_changes(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(USERS_CHANGED_CHANNEL).await?;
+    Ok(listener)
+}
+
+/// Default key of the advisory lock held while migrating, the ASCII bytes of
+/// `rust_api`
+///
+/// Overridden by `MIGRATION_LOCK_KEY` when other applications sharing the
+/// database would otherwise contend for the same lock.
+pub const DEFAULT_MIGRATION_LOCK_KEY: i64 = 0x7275_7374_5f61_7069;
+
+/// Apply all pending migrations embedded from the `migrations/` directory
+///
+/// Migrations run under the advisory lock `lock_key`, shared by every
+/// instance, so when several instances boot at once one of them migrates
+/// while the others wait, then find nothing left to apply.
+///
+/// # Errors
+///
+/// Returns an error if the lock cannot be taken or a migration fails to apply
+pub async fn run_migrations(pool: &PgPool, lock_key: i64) -> Result<(), MigrateError> {
+    migrate_locked(pool, sqlx::migrate!(), lock_key).await
+}
+
+/// Apply pending migrations up to and including `version`
+///
+/// For staged deploys, where a schema change ships ahead of the code that
+/// relies on it. Later migrations stay pending, and migrations already
+/// applied past `version` are left in place. Takes the advisory lock
+/// `lock_key` like [`run_migrations`].
+///
+/// # Errors
+///
+/// Returns [`MigrateError::VersionNotPresent`] if no embedded migration has
+/// that version, or an error if the lock cannot be taken or a migration
+/// fails to apply
+pub async fn run_migration_up_to(
+    pool: &PgPool,
+    version: i64,
+    lock_key: i64,
+) -> Result<(), MigrateError> {
+    let mut migrator = sqlx::migrate!();
+    if !migrator.version_exists(version) {
+        return Err(MigrateError::VersionNotPresent(version));
+    }
+    migrator.migrations = migrator
+        .iter()
+        .filter(|migration| migration.version <= version)
+        .cloned()
+        .collect::<Vec<_>>()
+        .into();
+    migrator.set_ignore_missing(true);
+    migrate_locked(pool, migrator, lock_key).await
+}
+
+/// Version of the latest successfully applied migration
+///
+/// Returns `Ok(None)` on a database that has never been migrated.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn current_migration_version(pool: &PgPool) -> Result<Option<i64>, sqlx::Error> {
+    let tracked: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+    if !tracked {
+        return Ok(None);
+    }
+    sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await
+}
+
+/// Run `migrator` while holding the advisory lock `lock_key`
+///
+/// The lock is held by a session of its own, outside the pool, so migrating
+/// does not wait on the pool when it has a single connection. It replaces the
+/// lock [`Migrator::run`] would take, which is keyed by database name only.
+async fn migrate_locked(
+    pool: &PgPool,
+    mut migrator: Migrator,
+    lock_key: i64,
+) -> Result<(), MigrateError> {
+    let mut conn = PgConnection::connect_with(&pool.connect_options()).await?;
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(lock_key)
+        .fetch_one(&mut conn)
+        .await?;
+    if !locked {
+        tracing::info!("Waiting for another instance to finish migrating");
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(lock_key)
+            .execute(&mut conn)
+            .await?;
+    }
+
+    migrator.set_locking(false);
+    let result = migrator.run(pool).await;
+
+    // Ending the session releases the lock
+    if let Err(e) = conn.close().await {
+        tracing::warn!(error = %e, "Failed to close the migration lock session");
+    }
+    result
+}
+
+/// Begin a transaction running at `SERIALIZABLE` isolation
+///
+/// Under this level `PostgreSQL` aborts any transaction whose outcome could
+/// differ from some serial ordering, reporting SQLSTATE `40001` on a query or
+/// on commit. Such failures are transient: callers should roll back and rerun
+/// the whole transaction (usually with a small bounded number of attempts),
+/// using [`is_serialization_failure`] to tell them apart from real errors.
+///
+/// # Errors
+///
+/// Returns an error if a connection cannot be acquired or the isolation level
+/// cannot be set
+pub async fn begin_serializable(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+/// Whether `err` is a serialization failure that warrants a retry
+pub fn is_serialization_failure(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(DatabaseError::code)
+        .is_some_and(|code| code == SERIALIZATION_FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{capture_logs, insert_user, test_config, test_pool};
+
+    async fn count_then_insert(tx: &mut Transaction<'static, Postgres>, email: &str) {
+        let _: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&mut **tx)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO users (name, email) VALUES ('Writer', $1)")
+            .bind(email)
+            .execute(&mut **tx)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_begin_serializable_sets_isolation_level() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut tx = begin_serializable(&pool).await.unwrap();
+        let level: String = sqlx::query_scalar("SHOW transaction_isolation")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(level, "serializable");
+    }
+
+    #[tokio::test]
+    async fn test_serialization_conflict_is_surfaced() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut first = begin_serializable(&pool).await.unwrap();
+        let mut second = begin_serializable(&pool).await.unwrap();
+
+        // Classic write skew: both read the table, then both write to it
+        count_then_insert(&mut first, "first@example.com").await;
+        count_then_insert(&mut second, "second@example.com").await;
+
+        first.commit().await.unwrap();
+        let err = second.commit().await.unwrap_err();
+
+        assert!(is_serialization_failure(&err), "unexpected error: {err}");
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_full_on_writable_database() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        healthcheck_full(&pool).await.unwrap();
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM health_probe")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_full_fails_when_read_only() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let options = (*pool.connect_options())
+            .clone()
+            .options([("default_transaction_read_only", "on")]);
+        let read_only = PgPoolOptions::new().connect_lazy_with(options);
+
+        ping(&read_only).await.unwrap();
+        let err = healthcheck_full(&read_only).await.unwrap_err();
+
+        let code = err.as_database_error().and_then(DatabaseError::code);
+        assert_eq!(code.as_deref(), Some("25006"));
+    }
+
+    #[tokio::test]
+    async fn test_run_readonly_check_passes_select() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let result = run_readonly_check(&pool, "users", "SELECT COUNT(*) FROM users").await;
+
+        assert!(result.passed(), "{:?}", result.error);
+        assert_eq!(result.name, "users");
+    }
+
+    #[tokio::test]
+    async fn test_run_readonly_check_rejects_writes() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let result = run_readonly_check(
+            &pool,
+            "probe_write",
+            "INSERT INTO health_probe DEFAULT VALUES",
+        )
+        .await;
+
+        assert!(!result.passed());
+        let code = result
+            .error
+            .as_ref()
+            .and_then(sqlx::Error::as_database_error)
+            .and_then(DatabaseError::code);
+        assert_eq!(code.as_deref(), Some("25006"));
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM health_probe")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_retries_until_connection_frees() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let contended = PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(50))
+            .connect_with((*pool.connect_options()).clone())
+            .await
+            .unwrap();
+        let held = contended.acquire().await.unwrap();
+
+        let err = acquire(&contended, 0).await.unwrap_err();
+        assert!(matches!(err, sqlx::Error::PoolTimedOut), "{err:?}");
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(80)).await;
+            drop(held);
+        });
+        acquire(&contended, 5).await.unwrap();
+    }
+
+    #[test]
+    fn test_next_backoff_bounds_per_jitter() {
+        let base = Duration::from_millis(100);
+        for attempt in 0..5 {
+            let ceiling = base * 2u32.pow(attempt);
+            assert_eq!(next_backoff(attempt, base, RetryJitter::None), ceiling);
+            for _ in 0..50 {
+                let full = next_backoff(attempt, base, RetryJitter::Full);
+                assert!(full < ceiling, "{attempt}: {full:?}");
+                let equal = next_backoff(attempt, base, RetryJitter::Equal);
+                assert!(
+                    equal >= ceiling / 2 && equal < ceiling,
+                    "{attempt}: {equal:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_next_backoff_is_capped() {
+        let base = Duration::from_secs(1);
+        assert_eq!(next_backoff(5, base, RetryJitter::None), MAX_BACKOFF);
+        assert_eq!(next_backoff(u32::MAX, base, RetryJitter::None), MAX_BACKOFF);
+        assert!(next_backoff(40, base, RetryJitter::Full) < MAX_BACKOFF);
+        assert!(next_backoff(40, base, RetryJitter::Equal) >= MAX_BACKOFF / 2);
+        assert_eq!(
+            next_backoff(3, Duration::ZERO, RetryJitter::Full),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline_aborts_query_near_expiry() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let deadline = Deadline::at(tokio::time::Instant::now() + Duration::from_millis(50));
+
+        let query = sqlx::query("SELECT pg_sleep(5)").execute(&pool);
+        let err = with_deadline(deadline, query).await.unwrap_err();
+
+        assert!(matches!(err, AppError::DeadlineExceeded), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline_passes_through_results() {
+        let far = Deadline::at(tokio::time::Instant::now() + Duration::from_secs(30));
+        assert_eq!(
+            with_deadline(far, async { Ok::<_, AppError>(7) })
+                .await
+                .unwrap(),
+            7
+        );
+
+        let err = with_deadline(Deadline::none(), async {
+            Err::<(), _>(sqlx::Error::RowNotFound)
+        })
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AppError::Database(sqlx::Error::RowNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_notifies_users_changed() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut listener = listen_user_changes(&pool).await.unwrap();
+
+        let user = insert_user(&pool, "Notified", "notified@example.com").await;
+
+        let notification = tokio::time::timeout(Duration::from_secs(5), listener.recv())
+            .await
+            .expect("no notification received")
+            .unwrap();
+        assert_eq!(notification.channel(), USERS_CHANGED_CHANNEL);
+        let payload: serde_json::Value = serde_json::from_str(notification.payload()).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({ "op": "INSERT", "id": user.id })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_migrations_both_succeed() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .execute(&pool)
+            .await
+            .unwrap();
+        // A second pool stands in for another instance booting at once
+        let other = PgPool::connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+
+        let (first, second) = tokio::join!(
+            run_migrations(&pool, DEFAULT_MIGRATION_LOCK_KEY),
+            run_migrations(&other, DEFAULT_MIGRATION_LOCK_KEY)
+        );
+
+        first.unwrap();
+        second.unwrap();
+        ensure_schema(&pool).await.unwrap();
+        let held: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pg_locks l JOIN pg_database d ON d.oid = l.database \
+             WHERE l.locktype = 'advisory' AND d.datname = current_database()",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(held, 0);
+    }
+
+    #[tokio::test]
+    async fn test_migration_lock_keys_are_independent() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        // Another application sharing the server holds its own key
+        let (held, other) = (4242, 4243);
+        let mut holder = pool.acquire().await.unwrap();
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(held)
+            .execute(&mut *holder)
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), run_migrations(&pool, other))
+            .await
+            .expect("a different key must not wait")
+            .unwrap();
+
+        let same = tokio::spawn({
+            let pool = pool.clone();
+            async move { run_migrations(&pool, held).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!same.is_finished(), "the same key must wait for the holder");
+
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(held)
+            .execute(&mut *holder)
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), same)
+            .await
+            .expect("migrating must proceed once the lock is released")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migrations_run_on_a_single_connection_pool() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let single = PgPoolOptions::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+
+        run_migrations(&single, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap();
+
+        ensure_schema(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_migration_up_to_stops_at_target() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let latest = sqlx::migrate!().iter().map(|m| m.version).max();
+        assert_eq!(current_migration_version(&pool).await.unwrap(), latest);
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(current_migration_version(&pool).await.unwrap(), None);
+
+        run_migration_up_to(&pool, 4, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap();
+
+        assert_eq!(current_migration_version(&pool).await.unwrap(), Some(4));
+        let audit: bool = sqlx::query_scalar("SELECT to_regclass('audit_log') IS NOT NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(audit);
+        let last_login: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM information_schema.columns \
+             WHERE table_name = 'users' AND column_name = 'last_login_at')",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(!last_login);
+
+        assert!(matches!(
+            run_migration_up_to(&pool, 99, DEFAULT_MIGRATION_LOCK_KEY).await,
+            Err(MigrateError::VersionNotPresent(99))
+        ));
+        run_migrations(&pool, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap();
+        run_migration_up_to(&pool, 2, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap();
+        assert_eq!(current_migration_version(&pool).await.unwrap(), latest);
+    }
+
+    #[tokio::test]
+    async fn test_lower_email_index_rejects_case_variants() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let insert = |email| {
+            sqlx::query("INSERT INTO users (name, email) VALUES ('Case', $1)")
+                .bind(email)
+                .execute(&pool)
+        };
+
+        insert("A@x.com").await.unwrap();
+        let err = insert("a@x.com").await.unwrap_err();
+
+        let db_err = err.as_database_error().unwrap();
+        assert_eq!(db_err.code().as_deref(), Some(UNIQUE_VIOLATION));
+        assert_eq!(db_err.constraint(), Some("users_lower_email_key"));
+    }
+
+    #[tokio::test]
+    async fn test_lower_email_migration_fails_on_case_variants() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .execute(&pool)
+            .await
+            .unwrap();
+        run_migration_up_to(&pool, 10, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO users (name, email) VALUES ('Upper', 'A@x.com'), ('Lower', 'a@x.com')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let err = run_migrations(&pool, DEFAULT_MIGRATION_LOCK_KEY)
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string().contains("case-variant duplicates: a@x.com"),
+            "{err}"
+        );
+        assert_eq!(current_migration_version(&pool).await.unwrap(), Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_server_version() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let version = server_version(&pool).await.unwrap();
+        assert!(!version.trim().is_empty());
+        assert!(
+            version.starts_with(|c: char| c.is_ascii_digit()),
+            "{version}"
+        );
+    }
+
+    #[test]
+    fn test_connect_options_apply_ssl_mode() {
+        let config = Config {
+            database_url: "postgres://user@localhost/db?sslmode=disable".to_string(),
+            ..test_config()
+        };
+        let options = connect_options(&config).unwrap();
+        assert!(matches!(options.get_ssl_mode(), PgSslMode::Disable));
+
+        let config = Config {
+            db_ssl_mode: Some(SslMode::VerifyFull),
+            ..config
+        };
+        let options = connect_options(&config).unwrap();
+        assert!(matches!(options.get_ssl_mode(), PgSslMode::VerifyFull));
+    }
+
+    #[test]
+    fn test_connect_options_apply_extra_params() {
+        let config = Config {
+            database_url: "postgres://user@localhost/db".to_string(),
+            db_extra_params: vec![
+                ("application_name".to_string(), "api".to_string()),
+                ("statement_timeout".to_string(), "5s".to_string()),
+            ],
+            ..test_config()
+        };
+        let options = connect_options(&config).unwrap();
+        assert_eq!(options.get_application_name(), Some("api"));
+        assert_eq!(options.get_options(), Some("-c statement_timeout=5s"));
+    }
+
+    #[tokio::test]
+    async fn test_extra_params_reach_the_server() {
+        let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+            return;
+        };
+        let config = Config {
+            database_url,
+            db_extra_params: vec![("lock_timeout".to_string(), "1234ms".to_string())],
+            ..test_config()
+        };
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(connect_options(&config).unwrap())
+            .await
+            .unwrap();
+
+        let timeout: String = sqlx::query_scalar("SHOW lock_timeout")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(timeout, "1234ms");
+    }
+
+    #[test]
+    fn test_pool_options_only_log_fair_acquire() {
+        let (logs, _guard) = capture_logs();
+        let config = Config {
+            db_fair_acquire: false,
+            ..test_config()
+        };
+
+        let _ = pool_options(&config);
+
+        assert!(logs.contents().contains("fair_acquire=false"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_connection_is_replaced_on_acquire() {
+        let Some(admin) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            db_conn_max_idle_ping_secs: 1,
+            ..test_config()
+        };
+        let pool = pool_options(&config)
+            .max_connections(1)
+            .connect_lazy_with(admin.connect_options().as_ref().clone());
+        let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        // As a firewall or idle timeout would, close the pooled connection
+        // behind the pool's back
+        sqlx::query("SELECT pg_terminate_backend($1)")
+            .bind(pid)
+            .execute(&admin)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        let new_pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_ne!(new_pid, pid);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_reports_unreachable_database() {
+        let config = Config {
+            database_url: "postgres://postgres@127.0.0.1:1/none".to_string(),
+            ..test_config()
+        };
+        assert!(reconnect(&config).await.is_err());
+    }
+
+    #[test]
+    fn test_other_errors_are_not_serialization_failures() {
+        assert!(!is_serialization_failure(&sqlx::Error::RowNotFound));
+    }
+}
@@ -0,0 +1,125 @@
+//! Verification that the schema matches what the code expects
+
+use sqlx::PgPool;
+use thiserror::Error;
+
+/// Columns of `users` the queries rely on
+const USER_COLUMNS: &[&str] = &[
+    "id",
+    "name",
+    "email",
+    "created_at",
+    "updated_at",
+    "last_login_at",
+    "changes",
+    "deleted_at",
+    "first_name",
+    "last_name",
+];
+
+/// Indexes on `users` the queries rely on for performance and uniqueness
+const USER_INDEXES: &[&str] = &[
+    "users_pkey",
+    "users_email_key",
+    "idx_users_email",
+    "idx_users_created_at",
+    "idx_users_last_login_at",
+    "users_lower_email_key",
+];
+
+/// Why the schema check failed
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    /// Expected objects are absent, e.g. after a partially applied migration
+    #[error("Database schema incomplete, missing: {}", .0.join(", "))]
+    Missing(Vec<String>),
+
+    /// The catalog could not be queried
+    #[error("Failed to inspect database schema: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Verify the `users` table, its columns and its indexes exist
+///
+/// Checks the current schema only, and is safe to run any number of times.
+///
+/// # Errors
+///
+/// Returns [`SchemaError::Missing`] naming every absent object, or
+/// [`SchemaError::Database`] if the catalog query fails
+pub async fn ensure_schema(pool: &PgPool) -> Result<(), SchemaError> {
+    let columns: Vec<String> = sqlx::query_scalar(
+        "SELECT column_name::text FROM information_schema.columns \
+         WHERE table_schema = current_schema() AND table_name = 'users'",
+    )
+    .fetch_all(pool)
+    .await?;
+    let indexes: Vec<String> = sqlx::query_scalar(
+        "SELECT indexname::text FROM pg_indexes \
+         WHERE schemaname = current_schema() AND tablename = 'users'",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut missing = Vec::new();
+    if columns.is_empty() {
+        missing.push("table users".to_string());
+    } else {
+        missing.extend(
+            USER_COLUMNS
+                .iter()
+                .filter(|c| !columns.iter().any(|found| found == *c))
+                .map(|c| format!("column users.{c}")),
+        );
+    }
+    missing.extend(
+        USER_INDEXES
+            .iter()
+            .filter(|i| !indexes.iter().any(|found| found == *i))
+            .map(|i| format!("index {i}")),
+    );
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError::Missing(missing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    #[tokio::test]
+    async fn test_fresh_database_passes() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        ensure_schema(&pool).await.unwrap();
+        ensure_schema(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_index_is_reported() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        sqlx::query("DROP INDEX idx_users_created_at")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let err = ensure_schema(&pool).await.unwrap_err();
+
+        assert!(
+            matches!(&err, SchemaError::Missing(missing) if missing == &["index idx_users_created_at"]),
+            "{err:?}"
+        );
+        assert_eq!(
+            err.to_string(),
+            "Database schema incomplete, missing: index idx_users_created_at"
+        );
+    }
+}
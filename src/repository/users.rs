@@ -0,0 +1,2613 @@
+//! Queries against the `users` table
+
+use super::{NUMERIC_VALUE_OUT_OF_RANGE, SEQUENCE_LIMIT_EXCEEDED, UNIQUE_VIOLATION};
+use crate::{
+    error::AppError,
+    models::{
+        validate_name_parts, Column, Cursor, CursorPage, DuplicateEmailGroup, Email, EmailDomain,
+        NewUser, PageParams, RelatedRows, UpsertCounts, User, UserFilter, UserSort, UserSummary,
+        UserUpdate, MAX_EMAIL_LEN, MAX_NAME_LEN,
+    },
+    query_budget,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{stream, Stream, StreamExt};
+use sqlx::{
+    error::DatabaseError, postgres::PgRow, PgExecutor, PgPool, Postgres, QueryBuilder, Row,
+};
+use tokio::sync::mpsc;
+
+/// Page size used when a listing does not specify one
+pub const DEFAULT_LIMIT: i64 = 20;
+
+/// Largest page size a listing may request
+pub const MAX_LIMIT: i64 = 100;
+
+/// Rows [`stream_search`] fetches ahead of its consumer
+const STREAM_BUFFER: usize = 64;
+
+const USER_COLUMNS: &str =
+    "id, name, first_name, last_name, email, created_at, updated_at, deleted_at";
+
+const SUMMARY_COLUMNS: &str = "id, name, email";
+
+/// Fetch a single user by primary key
+///
+/// Returns `Ok(None)` when no user with the given id exists or it has been
+/// soft-deleted. Runs on `executor`, so it can read inside a caller's
+/// transaction.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn get_user_by_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+) -> Result<Option<User>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE id = $1 AND deleted_at IS NULL"
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Fetch a user by email address
+///
+/// `email` is [normalized](Email::normalized) first and compared against the
+/// lowercased column, so lookups ignore case and are served by the
+/// unique `lower(email)` index. Returns `Ok(None)` when no live user has the address.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn get_user_by_email<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &Email,
+) -> Result<Option<User>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {USER_COLUMNS} FROM users \
+         WHERE lower(email) = $1 AND deleted_at IS NULL \
+         ORDER BY id LIMIT 1"
+    ))
+    .bind(email.normalized().as_str())
+    .fetch_optional(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Fetch only `columns` of a user, as a JSON object keyed by column name
+///
+/// Only the requested columns are selected, in the order given; repeats are
+/// ignored. Values are serialized as in [`User`]. Returns `Ok(None)` when no
+/// user with the given id exists or it has been soft-deleted.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn get_user_columns<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+    columns: &[Column],
+) -> Result<Option<serde_json::Value>, AppError> {
+    let mut selected: Vec<Column> = Vec::with_capacity(columns.len());
+    for &column in columns {
+        if !selected.contains(&column) {
+            selected.push(column);
+        }
+    }
+    query_budget::record()?;
+    let Some(row) = sqlx::query(&select_columns_sql(&selected))
+        .bind(id)
+        .fetch_optional(executor)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut object = serde_json::Map::with_capacity(selected.len());
+    for column in selected {
+        let name = column.name();
+        let value = match column {
+            Column::Id => serde_json::Value::from(row.try_get::<i32, _>(name)?),
+            Column::Name | Column::Email => {
+                serde_json::Value::from(row.try_get::<String, _>(name)?)
+            }
+            Column::CreatedAt | Column::UpdatedAt => {
+                serde_json::json!(row.try_get::<DateTime<Utc>, _>(name)?)
+            }
+        };
+        object.insert(name.to_string(), value);
+    }
+    Ok(Some(serde_json::Value::Object(object)))
+}
+
+/// Query selecting `columns` of the live user with id `$1`
+///
+/// Only [`Column`] names reach the SQL, never client input. Without columns
+/// it selects a constant, which still reports whether the user exists.
+fn select_columns_sql(columns: &[Column]) -> String {
+    let list = if columns.is_empty() {
+        "1".to_string()
+    } else {
+        columns
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!("SELECT {list} FROM users WHERE id = $1 AND deleted_at IS NULL")
+}
+
+/// Soft-delete a user, hiding it from regular reads
+///
+/// Returns whether a user was deleted by this call; `false` if none with the
+/// given id exists or it was already deleted.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn soft_delete_user<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+) -> Result<bool, AppError> {
+    query_budget::record()?;
+    let result =
+        sqlx::query("UPDATE users SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .execute(executor)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Soft-delete a user, counting the rows that refer to it
+///
+/// The user is hidden as by [`soft_delete_user`], and the related rows are
+/// counted in the same statement. They are left in place: the audit history
+/// outlives the user. Returns `Ok(None)` if no user with the given id exists
+/// or it was already deleted.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn delete_user<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+) -> Result<Option<RelatedRows>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as::<_, RelatedRows>(
+        "WITH deleted AS ( \
+             UPDATE users SET deleted_at = NOW() \
+             WHERE id = $1 AND deleted_at IS NULL RETURNING id \
+         ) \
+         SELECT (SELECT COUNT(*) FROM audit_log a WHERE a.user_id = d.id) AS audit \
+         FROM deleted d",
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Set a user's `updated_at` to now, leaving every other column alone
+///
+/// Returns the refreshed user, or `Ok(None)` when no user with the given id
+/// exists or it is soft-deleted. For cache testing and data fixes.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn touch_updated_at<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+) -> Result<Option<User>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as::<_, User>(&format!(
+        "UPDATE users SET updated_at = NOW() WHERE id = $1 AND deleted_at IS NULL \
+         RETURNING {USER_COLUMNS}"
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Insert a new user and return the stored record
+///
+/// Runs on `executor`, so it can take part in a caller's transaction.
+///
+/// Lengths are checked again here, independently of request validation, so
+/// an over-long value is reported as a validation error instead of surfacing
+/// as a `22001` (string data right truncation) database error.
+///
+/// The email is stored [normalized](Email::normalized). The unique
+/// `lower(email)` index rejects an address registered in any case, however
+/// many inserts race for it.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if a field exceeds its column width,
+/// [`AppError::Conflict`] if the email is already registered,
+/// [`AppError::IdsExhausted`] if no `integer` id is left, or
+/// [`AppError::Database`] if the insert fails
+pub async fn create_user<'e>(
+    executor: impl PgExecutor<'e>,
+    new_user: &NewUser,
+) -> Result<User, AppError> {
+    let email = new_user.email.normalized();
+    check_length("name", &new_user.name, MAX_NAME_LEN)?;
+    check_name_parts(
+        new_user.first_name.as_deref(),
+        new_user.last_name.as_deref(),
+    )?;
+    check_length("email", &email, MAX_EMAIL_LEN)?;
+
+    query_budget::record()?;
+    let inserted = sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (name, email, first_name, last_name) VALUES ($1, $2, $3, $4) \
+         RETURNING {USER_COLUMNS}"
+    ))
+    .bind(&new_user.name)
+    .bind(email.as_str())
+    .bind(new_user.first_name.as_deref())
+    .bind(new_user.last_name.as_deref())
+    .fetch_one(executor)
+    .await
+    .map_err(|err| {
+        if is_id_exhausted(&err) {
+            AppError::IdsExhausted
+        } else if is_unique_violation(&err) {
+            email_taken()
+        } else {
+            AppError::Database(err)
+        }
+    })?;
+
+    Ok(inserted)
+}
+
+/// Insert a user under an explicit `id`, for imports that preserve ids
+///
+/// Names and emails are checked and the email [normalized](Email::normalized)
+/// as by [`create_user`]. The `users.id` sequence is not advanced, so once a
+/// batch is in, call [`reset_user_id_sequence`] before ids are generated
+/// again.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if a field exceeds its column width,
+/// [`AppError::Conflict`] if the id or the email is taken, or
+/// [`AppError::Database`] if the insert fails
+pub async fn insert_user_with_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+    name: &str,
+    email: &Email,
+) -> Result<User, AppError> {
+    let email = email.normalized();
+    check_length("name", name, MAX_NAME_LEN)?;
+    check_length("email", &email, MAX_EMAIL_LEN)?;
+
+    query_budget::record()?;
+    sqlx::query_as::<_, User>(&format!(
+        "INSERT INTO users (id, name, email) VALUES ($1, $2, $3) RETURNING {USER_COLUMNS}"
+    ))
+    .bind(id)
+    .bind(name)
+    .bind(email.as_str())
+    .fetch_one(executor)
+    .await
+    .map_err(|err| {
+        let constraint = err.as_database_error().and_then(DatabaseError::constraint);
+        match constraint {
+            Some("users_pkey") => AppError::Conflict(format!("user id {id} is already taken")),
+            _ if is_unique_violation(&err) => email_taken(),
+            _ => AppError::Database(err),
+        }
+    })
+}
+
+/// Move the `users.id` sequence past the highest id in use
+///
+/// Needed after [`insert_user_with_id`], whose ids the sequence does not
+/// know about. Returns the id the next generated user will get.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn reset_user_id_sequence<'e>(executor: impl PgExecutor<'e>) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar(
+        "SELECT setval(pg_get_serial_sequence('users', 'id'), COALESCE(MAX(id), 0) + 1, false) \
+         FROM users",
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+fn email_taken() -> AppError {
+    AppError::Conflict("email is already registered".to_string())
+}
+
+/// Whether `err` is a unique constraint violation, e.g. from an email that
+/// matches an existing one ignoring case
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(DatabaseError::code)
+        .is_some_and(|code| code == UNIQUE_VIOLATION)
+}
+
+/// Whether `err` means the next `users.id` no longer fits
+///
+/// A `SERIAL` sequence stops at the `integer` maximum; a sequence widened
+/// without its column overflows the column instead.
+fn is_id_exhausted(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(DatabaseError::code)
+        .is_some_and(|code| code == NUMERIC_VALUE_OUT_OF_RANGE || code == SEQUENCE_LIMIT_EXCEEDED)
+}
+
+/// Whether a user's email equals `email`, ignoring case
+///
+/// Served by the unique `lower(email)` index.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn email_exists_case_insensitive<'e>(
+    executor: impl PgExecutor<'e>,
+    email: &str,
+) -> Result<bool, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower($1))")
+        .bind(email)
+        .fetch_one(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Apply `update` to a user, returning the user before and after
+///
+/// The previous row is captured by a CTE in the same statement, so the pair
+/// describes exactly this change even under concurrent writes. Fields whose
+/// value actually changes are appended to the row's `changes` log, see
+/// [`get_user_changes`]. Returns `Ok(None)` when no user with the given id
+/// exists or it is soft-deleted.
+///
+/// A new `name` clears the first and last name. A new first or last name is
+/// combined with the other part, new or kept, into the
+/// [full name](crate::models::full_name)
+/// stored as `name`. A new email is stored [normalized](Email::normalized).
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if a field is invalid, exceeds its column
+/// width, or `name` is set together with a name part,
+/// [`AppError::Conflict`] if the new email is already registered, or
+/// [`AppError::Database`] if the update fails
+pub async fn update_user_returning_prev<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+    update: &UserUpdate,
+) -> Result<Option<(User, User)>, AppError> {
+    if update.name.is_some() && (update.first_name.is_some() || update.last_name.is_some()) {
+        return Err(AppError::Validation(
+            "name cannot be set together with first_name or last_name".to_string(),
+        ));
+    }
+    if let Some(name) = &update.name {
+        check_length("name", name, MAX_NAME_LEN)?;
+    }
+    check_name_parts(update.first_name.as_deref(), update.last_name.as_deref())?;
+    let email = update.email.as_ref().map(Email::normalized);
+    if let Some(email) = &email {
+        check_length("email", email, MAX_EMAIL_LEN)?;
+    }
+
+    // A new name ($2) clears the parts; a new part ($4, $5) keeps the other
+    // stored part and recombines the name from both
+    query_budget::record()?;
+    let row = sqlx::query(
+        "WITH prev AS ( \
+             SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE \
+         ), \
+         parts AS ( \
+             SELECT id, \
+                 CASE WHEN $2::text IS NULL THEN COALESCE($4, first_name) END AS first_name, \
+                 CASE WHEN $2::text IS NULL THEN COALESCE($5, last_name) END AS last_name \
+             FROM prev \
+         ), \
+         target AS ( \
+             SELECT parts.*, COALESCE($3, prev.email) AS email, \
+                 CASE WHEN $4::text IS NULL AND $5::text IS NULL THEN COALESCE($2, prev.name) \
+                      ELSE concat_ws(' ', NULLIF(btrim(parts.first_name), ''), \
+                                          NULLIF(btrim(parts.last_name), '')) END AS name \
+             FROM parts JOIN prev USING (id) \
+         ), \
+         changed AS ( \
+             SELECT target.*, jsonb_strip_nulls(jsonb_build_object( \
+                 'name', NULLIF(target.name, prev.name), \
+                 'first_name', NULLIF(target.first_name, prev.first_name), \
+                 'last_name', NULLIF(target.last_name, prev.last_name), \
+                 'email', NULLIF(target.email, prev.email) \
+             )) AS fields \
+             FROM target JOIN prev USING (id) \
+         ) \
+         UPDATE users u \
+         SET name = changed.name, first_name = changed.first_name, \
+             last_name = changed.last_name, email = changed.email, updated_at = NOW(), \
+             changes = CASE WHEN changed.fields = '{}' THEN u.changes ELSE u.changes || \
+                 jsonb_build_array(jsonb_build_object('at', NOW(), 'fields', changed.fields)) END \
+         FROM prev JOIN changed USING (id) WHERE u.id = prev.id \
+         RETURNING prev.id, prev.name, prev.first_name, prev.last_name, prev.email, \
+                   prev.created_at, prev.updated_at, prev.deleted_at, \
+                   u.name AS new_name, u.first_name AS new_first_name, \
+                   u.last_name AS new_last_name, u.email AS new_email, \
+                   u.updated_at AS new_updated_at",
+    )
+    .bind(id)
+    .bind(update.name.as_deref())
+    .bind(email.as_ref().map(Email::as_str))
+    .bind(update.first_name.as_deref())
+    .bind(update.last_name.as_deref())
+    .fetch_optional(executor)
+    .await
+    .map_err(|err| {
+        if is_unique_violation(&err) {
+            email_taken()
+        } else {
+            AppError::Database(err)
+        }
+    })?;
+
+    row.map(|row| split_update_row(&row))
+        .transpose()
+        .map_err(AppError::from)
+}
+
+/// The change log of a user, oldest first
+///
+/// Each entry is `{"at": <timestamp>, "fields": {<field>: <new value>}}`.
+/// Returns `Ok(None)` when no user with the given id exists.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn get_user_changes<'e>(
+    executor: impl PgExecutor<'e>,
+    id: i32,
+) -> Result<Option<serde_json::Value>, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar("SELECT changes FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// The previous and updated user from a row of [`update_user_returning_prev`]
+fn split_update_row(row: &PgRow) -> Result<(User, User), sqlx::Error> {
+    let previous = User {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        first_name: row.try_get("first_name")?,
+        last_name: row.try_get("last_name")?,
+        email: row.try_get("email")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        deleted_at: row.try_get("deleted_at")?,
+    };
+    let current = User {
+        name: row.try_get("new_name")?,
+        first_name: row.try_get("new_first_name")?,
+        last_name: row.try_get("new_last_name")?,
+        email: row.try_get("new_email")?,
+        updated_at: row.try_get("new_updated_at")?,
+        ..previous.clone()
+    };
+    Ok((previous, current))
+}
+
+/// Fetch the user with `email`, creating it with `name` if absent
+///
+/// Returns the user and whether it was created by this call. An existing
+/// user is returned unchanged, even if its name differs from `name`.
+/// A new user's email is stored [normalized](Email::normalized) as by
+/// [`create_user`]. Emails are matched ignoring case, and concurrent calls for the same email
+/// are resolved by `ON CONFLICT`, so exactly one of them reports the user as
+/// created. A soft-deleted user is never returned.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if `name` exceeds its column width,
+/// [`AppError::Conflict`] if the email belongs to a soft-deleted user, or
+/// [`AppError::Database`] if a query fails
+pub async fn get_or_create_user(
+    pool: &PgPool,
+    name: &str,
+    email: &Email,
+) -> Result<(User, bool), AppError> {
+    let email = email.normalized();
+    check_length("name", name, MAX_NAME_LEN)?;
+    check_length("email", &email, MAX_EMAIL_LEN)?;
+
+    loop {
+        query_budget::record()?;
+        let inserted = sqlx::query_as::<_, User>(&format!(
+            "INSERT INTO users (name, email) VALUES ($1, $2) \
+             ON CONFLICT (lower(email)) DO NOTHING RETURNING {USER_COLUMNS}"
+        ))
+        .bind(name)
+        .bind(email.as_str())
+        .fetch_optional(pool)
+        .await?;
+        if let Some(user) = inserted {
+            return Ok((user, true));
+        }
+
+        query_budget::record()?;
+        let existing = sqlx::query_as::<_, User>(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE lower(email) = $1"
+        ))
+        .bind(email.as_str())
+        .fetch_optional(pool)
+        .await?;
+        match existing {
+            // A soft-deleted user keeps its email in the unique index
+            Some(user) if user.deleted_at.is_some() => return Err(email_taken()),
+            Some(user) => return Ok((user, false)),
+            // The conflicting row can be deleted before we read it; try again
+            None => {}
+        }
+    }
+}
+
+/// List users matching `filter`
+///
+/// All user-supplied values are bound as parameters; only the fixed
+/// `ORDER BY` expression of the chosen [`UserSort`] is
+/// interpolated. Paging follows [`page_bounds`].
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn find_users<'e>(
+    executor: impl PgExecutor<'e>,
+    filter: &UserFilter,
+) -> Result<Vec<User>, AppError> {
+    query_budget::record()?;
+    select_users(USER_COLUMNS, filter)
+        .build_query_as::<User>()
+        .fetch_all(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Fetch the page of users after `cursor`, in id order
+///
+/// Starts from the first user when `cursor` is `None`. `limit` is clamped
+/// like a listing's. Paging by id rather than offset keeps every page equally
+/// cheap, and rows inserted meanwhile neither shift nor repeat entries.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn get_user_page<'e>(
+    executor: impl PgExecutor<'e>,
+    cursor: Option<Cursor>,
+    limit: Option<i64>,
+) -> Result<CursorPage<User>, AppError> {
+    let (limit, _) = clamp_page(limit, None);
+    // One extra row tells whether another page follows
+    query_budget::record()?;
+    let mut items = sqlx::query_as::<_, User>(&format!(
+        "SELECT {USER_COLUMNS} FROM users WHERE id > $1 AND deleted_at IS NULL \
+         ORDER BY id LIMIT $2"
+    ))
+    .bind(cursor.map_or(0, Cursor::last_id))
+    .bind(limit + 1)
+    .fetch_all(executor)
+    .await?;
+
+    let next = if items.len() > usize::try_from(limit).unwrap_or(usize::MAX) {
+        items.pop();
+        items.last().map(|user| Cursor::after(user.id))
+    } else {
+        None
+    };
+    Ok(CursorPage { items, next })
+}
+
+/// Stream every user matching `filter`, for exports
+///
+/// Applies the same criteria and sort as [`find_users`] but ignores paging,
+/// so the whole result set is read through one cursor. Rows are fetched by a
+/// background task at most [`STREAM_BUFFER`] ahead of the consumer; dropping
+/// the stream cancels the query.
+///
+/// # Errors
+///
+/// Returns [`AppError::QueryBudgetExceeded`] without starting the query if
+/// the request has used up its [query budget](crate::query_budget)
+pub fn stream_search(
+    pool: &PgPool,
+    filter: &UserFilter,
+) -> Result<impl Stream<Item = Result<User, sqlx::Error>> + Send + 'static, AppError> {
+    query_budget::record()?;
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    let pool = pool.clone();
+    let filter = filter.clone();
+    tokio::spawn(async move {
+        let mut query = QueryBuilder::<Postgres>::new(format!("SELECT {USER_COLUMNS} FROM users"));
+        push_filter_conditions(&mut query, &filter);
+        query.push(" ORDER BY ").push(order_by(filter.sort));
+
+        let mut rows = query.build_query_as::<User>().fetch(&pool);
+        while let Some(row) = rows.next().await {
+            let failed = row.is_err();
+            if tx.send(row).await.is_err() {
+                tracing::debug!("Stream consumer went away; cancelling the query");
+                break;
+            }
+            if failed {
+                break;
+            }
+        }
+    });
+    Ok(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    }))
+}
+
+/// Search users whose name contains `query`, most relevant first
+///
+/// Names starting with `query` rank above names containing it further in;
+/// within each group users are ordered by name, then id. Matching is
+/// case-insensitive and wildcards in `query` are taken literally.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn search_users<'e>(
+    executor: impl PgExecutor<'e>,
+    query: &str,
+    page: &PageParams,
+) -> Result<Vec<User>, AppError> {
+    let (limit, offset) = clamp_page(page.limit, page.offset);
+    let escaped = escape_like(query);
+    query_budget::record()?;
+    sqlx::query_as::<_, User>(&format!(
+        "SELECT {USER_COLUMNS} FROM users \
+         WHERE name ILIKE '%' || $1 || '%' AND deleted_at IS NULL \
+         ORDER BY CASE WHEN name ILIKE $1 || '%' THEN 0 ELSE 1 END, name, id \
+         LIMIT $2 OFFSET $3"
+    ))
+    .bind(escaped)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Insert or update `users` by email in a single statement, for sync jobs
+///
+/// Emails are [normalized](Email::normalized) as by [`create_user`]. A new
+/// email inserts a user; an existing one has its name, first and last name
+/// replaced. A soft-deleted user with the email is restored, as it still
+/// holds the address, and counted as updated. When the batch repeats an
+/// email, its last entry wins.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if a field exceeds its column width, or
+/// [`AppError::Database`] if the statement fails
+pub async fn bulk_upsert_users<'e>(
+    executor: impl PgExecutor<'e>,
+    users: &[NewUser],
+) -> Result<UpsertCounts, AppError> {
+    let mut latest = std::collections::HashMap::new();
+    for user in users {
+        check_length("name", &user.name, MAX_NAME_LEN)?;
+        check_name_parts(user.first_name.as_deref(), user.last_name.as_deref())?;
+        let email = user.email.normalized().into_inner();
+        check_length("email", &email, MAX_EMAIL_LEN)?;
+        latest.insert(email, user);
+    }
+    if latest.is_empty() {
+        return Ok(UpsertCounts::default());
+    }
+    let mut emails = Vec::with_capacity(latest.len());
+    let mut names = Vec::with_capacity(latest.len());
+    let mut first_names = Vec::with_capacity(latest.len());
+    let mut last_names = Vec::with_capacity(latest.len());
+    for (email, user) in latest {
+        emails.push(email);
+        names.push(user.name.as_str());
+        first_names.push(user.first_name.as_deref());
+        last_names.push(user.last_name.as_deref());
+    }
+
+    query_budget::record()?;
+    let inserted: Vec<bool> = sqlx::query_scalar(
+        "INSERT INTO users (name, email, first_name, last_name) \
+         SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[]) \
+         ON CONFLICT (lower(email)) DO UPDATE SET name = EXCLUDED.name, \
+             first_name = EXCLUDED.first_name, last_name = EXCLUDED.last_name, \
+             deleted_at = NULL, updated_at = NOW() \
+         RETURNING xmax = 0",
+    )
+    .bind(&names)
+    .bind(&emails)
+    .bind(&first_names)
+    .bind(&last_names)
+    .fetch_all(executor)
+    .await?;
+
+    let created = inserted.iter().filter(|&&new| new).count() as u64;
+    Ok(UpsertCounts {
+        inserted: created,
+        updated: inserted.len() as u64 - created,
+    })
+}
+
+/// Move every user on `old_domain` to `new_domain` in a single `UPDATE`
+///
+/// The domain is matched case-insensitively, stored lowercased, and the local
+/// part is kept, so a domain differing only in case is lowercased in place.
+/// Users whose new address is taken by another user in any case, would be
+/// produced twice, or would exceed [`MAX_EMAIL_LEN`] are left unchanged
+/// rather than failing the batch. Returns the number of users updated.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if `new_domain` is not a valid email
+/// domain, or [`AppError::Database`] if the update fails
+pub async fn batch_update_emails<'e>(
+    executor: impl PgExecutor<'e>,
+    old_domain: &str,
+    new_domain: &str,
+) -> Result<u64, AppError> {
+    if Email::parse(format!("user@{new_domain}")).is_err() {
+        return Err(AppError::Validation(format!(
+            "invalid email domain: {new_domain}"
+        )));
+    }
+
+    query_budget::record()?;
+    let result = sqlx::query(
+        "WITH renamed AS ( \
+             SELECT id, split_part(email, '@', 1) || '@' || lower($2) AS new_email \
+             FROM users \
+             WHERE lower(split_part(email, '@', 2)) = lower($1) \
+         ), \
+         candidates AS ( \
+             SELECT DISTINCT ON (lower(new_email)) id, new_email \
+             FROM renamed \
+             WHERE char_length(new_email) <= $3 \
+             ORDER BY lower(new_email), id \
+         ) \
+         UPDATE users u SET email = c.new_email, updated_at = NOW() \
+         FROM candidates c \
+         WHERE u.id = c.id \
+           AND NOT EXISTS ( \
+               SELECT 1 FROM users o WHERE lower(o.email) = lower(c.new_email) AND o.id <> c.id \
+           )",
+    )
+    .bind(old_domain)
+    .bind(new_domain)
+    .bind(i32::try_from(MAX_EMAIL_LEN).unwrap_or(i32::MAX))
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Exchange the emails of two users in one transaction
+///
+/// Emails are unique and the constraint is checked row by row, so the first
+/// user is parked on a throwaway address while the second takes its email,
+/// then given the second's. Both rows are locked up front, in id order so
+/// concurrent swaps cannot deadlock. Returns the two updated users, in
+/// argument order.
+///
+/// # Errors
+///
+/// Returns [`AppError::Validation`] if both ids are the same,
+/// [`AppError::NotFound`] if either user does not exist or is deleted, or
+/// [`AppError::Database`] if a statement fails
+pub async fn swap_emails(pool: &PgPool, id_a: i32, id_b: i32) -> Result<(User, User), AppError> {
+    if id_a == id_b {
+        return Err(AppError::Validation(format!(
+            "cannot swap the email of user {id_a} with itself"
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+    query_budget::record()?;
+    let locked: Vec<(i32, String)> = sqlx::query_as(
+        "SELECT id, email FROM users \
+         WHERE id IN ($1, $2) AND deleted_at IS NULL \
+         ORDER BY id FOR UPDATE",
+    )
+    .bind(id_a)
+    .bind(id_b)
+    .fetch_all(&mut *tx)
+    .await?;
+    let email_of = |id: i32| {
+        locked
+            .iter()
+            .find(|(locked_id, _)| *locked_id == id)
+            .map(|(_, email)| email.clone())
+            .ok_or_else(|| AppError::NotFound(format!("user {id}")))
+    };
+    let (email_a, email_b) = (email_of(id_a)?, email_of(id_b)?);
+
+    let parked = format!("swap-{}@swap.invalid", uuid::Uuid::new_v4().simple());
+    let set_email = format!(
+        "UPDATE users SET email = $2, updated_at = NOW() WHERE id = $1 RETURNING {USER_COLUMNS}"
+    );
+    query_budget::record()?;
+    sqlx::query(&set_email)
+        .bind(id_a)
+        .bind(&parked)
+        .execute(&mut *tx)
+        .await?;
+    query_budget::record()?;
+    let user_b = sqlx::query_as::<_, User>(&set_email)
+        .bind(id_b)
+        .bind(&email_a)
+        .fetch_one(&mut *tx)
+        .await?;
+    query_budget::record()?;
+    let user_a = sqlx::query_as::<_, User>(&set_email)
+        .bind(id_a)
+        .bind(&email_b)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok((user_a, user_b))
+}
+
+/// Count the users matching `filter`, ignoring its paging and sort
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_users<'e>(
+    executor: impl PgExecutor<'e>,
+    filter: &UserFilter,
+) -> Result<i64, AppError> {
+    let mut query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users");
+    push_filter_conditions(&mut query, filter);
+    query_budget::record()?;
+    query
+        .build_query_scalar()
+        .fetch_one(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Approximate number of users, from the planner's statistics
+///
+/// Reads `reltuples` from `pg_class`, which costs nothing regardless of table
+/// size but is only as current as the last `ANALYZE` or vacuum. Soft-deleted
+/// users are left out by scaling it with the share of rows whose
+/// `deleted_at` is null, from the column's statistics. A table that has never
+/// been analyzed reports `0`.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn estimate_user_count<'e>(executor: impl PgExecutor<'e>) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar(
+        "SELECT round(GREATEST(c.reltuples, 0) * COALESCE(s.null_frac, 1))::bigint \
+         FROM pg_class c \
+         JOIN pg_namespace n ON n.oid = c.relnamespace \
+         LEFT JOIN pg_stats s \
+             ON s.schemaname = n.nspname AND s.tablename = c.relname \
+             AND s.attname = 'deleted_at' \
+         WHERE c.oid = 'users'::regclass",
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Count the users whose last login is strictly after `since`
+///
+/// Users who never logged in are not counted.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_active_since<'e>(
+    executor: impl PgExecutor<'e>,
+    since: DateTime<Utc>,
+) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE last_login_at > $1 AND deleted_at IS NULL")
+        .bind(since)
+        .fetch_one(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Set `last_login_at` to now for every user in `ids` in one statement
+///
+/// For login bursts, where touching users one at a time is chatty. Unknown
+/// and soft-deleted ids are skipped; returns the number of users updated.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn touch_logins<'e>(executor: impl PgExecutor<'e>, ids: &[i32]) -> Result<u64, AppError> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    query_budget::record()?;
+    let result = sqlx::query(
+        "UPDATE users SET last_login_at = NOW() WHERE id = ANY($1) AND deleted_at IS NULL",
+    )
+    .bind(ids)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Count the users created on the database's current date
+///
+/// The date boundary follows the session time zone of the database.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_users_created_today<'e>(executor: impl PgExecutor<'e>) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users \
+         WHERE created_at::date = current_date AND deleted_at IS NULL",
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Count signups per calendar month over the last `months` months
+///
+/// Returns one `(first day of month, count)` pair per month, oldest first and
+/// ending with the current month. Months without signups are included with a
+/// count of zero, so a chart drawn from the series has no gaps. Months are
+/// UTC calendar months, and soft-deleted users still count as signups.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_users_by_created_month<'e>(
+    executor: impl PgExecutor<'e>,
+    months: i32,
+) -> Result<Vec<(NaiveDate, i64)>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as(
+        "WITH bounds AS (SELECT date_trunc('month', now() AT TIME ZONE 'UTC') AS current) \
+         SELECT m.month::date, COUNT(u.id) \
+         FROM bounds, generate_series( \
+             bounds.current - make_interval(months => $1 - 1), \
+             bounds.current, \
+             interval '1 month' \
+         ) AS m(month) \
+         LEFT JOIN users u \
+             ON u.created_at >= m.month AT TIME ZONE 'UTC' \
+             AND u.created_at < (m.month + interval '1 month') AT TIME ZONE 'UTC' \
+         GROUP BY m.month \
+         ORDER BY m.month",
+    )
+    .bind(months)
+    .fetch_all(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// List users matching `filter` as [`UserSummary`] records
+///
+/// Same semantics as [`find_users`], selecting only the summary columns.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn find_user_summaries<'e>(
+    executor: impl PgExecutor<'e>,
+    filter: &UserFilter,
+) -> Result<Vec<UserSummary>, AppError> {
+    query_budget::record()?;
+    select_users(SUMMARY_COLUMNS, filter)
+        .build_query_as::<UserSummary>()
+        .fetch_all(executor)
+        .await
+        .map_err(AppError::from)
+}
+
+/// List a page of users as [`UserSummary`] records, ordered by id
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn list_user_summaries<'e>(
+    executor: impl PgExecutor<'e>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<UserSummary>, AppError> {
+    let filter = UserFilter {
+        limit: Some(limit),
+        offset: Some(offset),
+        ..UserFilter::default()
+    };
+    find_user_summaries(executor, &filter).await
+}
+
+/// Find groups of users whose emails match after lowercasing and trimming
+///
+/// Emails are unique ignoring case but not surrounding whitespace, so
+/// `ann@example.com` and `ann@example.com ` can both be stored. Groups are ordered by normalized
+/// email.
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn find_duplicate_emails<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<Vec<DuplicateEmailGroup>, AppError> {
+    query_budget::record()?;
+    sqlx::query_as::<_, DuplicateEmailGroup>(
+        "SELECT lower(trim(email)) AS normalized_email, COUNT(*) AS count, \
+                array_agg(id ORDER BY id) AS user_ids \
+         FROM users \
+         GROUP BY lower(trim(email)) \
+         HAVING COUNT(*) > 1 \
+         ORDER BY normalized_email",
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Fetch a page of distinct email domains with their user counts
+///
+/// Domains are compared ignoring case and ordered by count, most users
+/// first, then by name, so pages are stable. `limit` and `offset` are
+/// clamped as by [`clamp_page`].
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn list_email_domains<'e>(
+    executor: impl PgExecutor<'e>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<EmailDomain>, AppError> {
+    let (limit, offset) = clamp_page(Some(limit), Some(offset));
+    query_budget::record()?;
+    sqlx::query_as::<_, EmailDomain>(
+        "SELECT lower(split_part(trim(email), '@', 2)) AS domain, COUNT(*) AS count \
+         FROM users \
+         WHERE deleted_at IS NULL \
+         GROUP BY 1 \
+         ORDER BY count DESC, domain \
+         LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Count the distinct email domains [`list_email_domains`] pages through
+///
+/// # Errors
+///
+/// Returns an error if the query fails
+pub async fn count_email_domains<'e>(executor: impl PgExecutor<'e>) -> Result<i64, AppError> {
+    query_budget::record()?;
+    sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT lower(split_part(trim(email), '@', 2))) FROM users \
+         WHERE deleted_at IS NULL",
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(AppError::from)
+}
+
+/// Refresh the planner statistics of the `users` table
+///
+/// Useful after bulk imports, when autovacuum has not caught up yet.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn analyze_users(pool: &PgPool) -> Result<(), AppError> {
+    query_budget::record()?;
+    sqlx::query("ANALYZE users")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(AppError::from)
+}
+
+/// Delete every user, their audit history and recorded idempotent
+/// responses, restarting id sequences
+///
+/// For resetting test and staging databases; callers must gate it.
+///
+/// # Errors
+///
+/// Returns an error if the statement fails
+pub async fn purge_all(pool: &PgPool) -> Result<(), AppError> {
+    query_budget::record()?;
+    sqlx::query("TRUNCATE users, audit_log, idempotency_keys RESTART IDENTITY")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(AppError::from)
+}
+
+/// Check name parts by the rules requests are validated with
+fn check_name_parts(first: Option<&str>, last: Option<&str>) -> Result<(), AppError> {
+    validate_name_parts(first, last).map_err(AppError::Validation)
+}
+
+/// Reject `value` if it is longer than `max` characters
+fn check_length(field: &str, value: &str, max: usize) -> Result<(), AppError> {
+    if value.chars().count() > max {
+        return Err(AppError::Validation(format!(
+            "{field} must be at most {max} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// The `(limit, offset)` a listing with `filter` is fetched with
+///
+/// The limit defaults to [`DEFAULT_LIMIT`] and is clamped to `1..=MAX_LIMIT`,
+/// or to the filter's `max_limit` when set; a missing or negative offset is
+/// treated as zero.
+#[must_use]
+pub fn page_bounds(filter: &UserFilter) -> (i64, i64) {
+    let (limit, offset) = clamp_page(None, filter.offset);
+    let max = filter.max_limit.unwrap_or(MAX_LIMIT);
+    let limit = filter.limit.map_or(limit, |limit| limit.clamp(1, max));
+    (limit, offset)
+}
+
+/// Apply the default and maximum page size, and a non-negative offset
+#[must_use]
+pub fn clamp_page(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+    (limit, offset)
+}
+
+/// Build the listing query for `filter`, selecting `columns`
+fn select_users<'a>(columns: &str, filter: &'a UserFilter) -> QueryBuilder<'a, Postgres> {
+    let mut query = QueryBuilder::<Postgres>::new(format!("SELECT {columns} FROM users"));
+    push_filter_conditions(&mut query, filter);
+
+    let (limit, offset) = page_bounds(filter);
+    query
+        .push(" ORDER BY ")
+        .push(order_by(filter.sort))
+        .push(" LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    query
+}
+
+/// Append a `WHERE` clause for every criterion set on `filter`
+fn push_filter_conditions(query: &mut QueryBuilder<'_, Postgres>, filter: &UserFilter) {
+    let mut keyword = " WHERE ";
+
+    if !filter.include_deleted {
+        query.push(keyword).push("deleted_at IS NULL");
+        keyword = " AND ";
+    }
+
+    if let Some(name) = &filter.name_contains {
+        query
+            .push(keyword)
+            .push("name ILIKE ")
+            .push_bind(format!("%{}%", escape_like(name)));
+        keyword = " AND ";
+    }
+    if let Some(domain) = &filter.email_domain {
+        query
+            .push(keyword)
+            .push("lower(split_part(email, '@', 2)) = lower(")
+            .push_bind(domain.clone())
+            .push(")");
+        keyword = " AND ";
+    }
+    if let Some(after) = filter.created_after {
+        query.push(keyword).push("created_at >= ").push_bind(after);
+        keyword = " AND ";
+    }
+    if let Some(before) = filter.created_before {
+        query.push(keyword).push("created_at < ").push_bind(before);
+        keyword = " AND ";
+    }
+    if let Some(never) = filter.never_logged_in {
+        query.push(keyword).push(if never {
+            "last_login_at IS NULL"
+        } else {
+            "last_login_at IS NOT NULL"
+        });
+    }
+}
+
+/// SQL `ORDER BY` expression for a sort
+///
+/// Non-unique columns are followed by `id`, so rows with equal values keep
+/// the same order from one page to the next.
+const fn order_by(sort: UserSort) -> &'static str {
+    match sort {
+        UserSort::Id => "id ASC",
+        UserSort::Name => "name ASC, id ASC",
+        UserSort::NameDesc => "name DESC, id DESC",
+        UserSort::CreatedAt => "created_at ASC, id ASC",
+        UserSort::CreatedAtDesc => "created_at DESC, id DESC",
+    }
+}
+
+/// Escape `LIKE` wildcards so user input only matches literally
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{insert_user, test_pool, unreachable_pool};
+    use axum::{http::StatusCode, response::IntoResponse};
+    use chrono::{Datelike, Months, TimeZone};
+    use futures_util::TryStreamExt;
+
+    async fn insert_user_created_at(pool: &PgPool, name: &str, email: &str, at: DateTime<Utc>) {
+        sqlx::query("INSERT INTO users (name, email, created_at) VALUES ($1, $2, $3)")
+            .bind(name)
+            .bind(email)
+            .bind(at)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, d, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("50%_off\\"), "50\\%\\_off\\\\");
+    }
+
+    fn new_user(name: &str, email: &str) -> NewUser {
+        NewUser {
+            name: name.to_string(),
+            first_name: None,
+            last_name: None,
+            email: Email::parse(email).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rejects_over_length_name_before_query() {
+        // The pool cannot connect, so reaching the database would surface as
+        // a `Database` error rather than a validation failure.
+        let pool = unreachable_pool();
+        let name = "x".repeat(MAX_NAME_LEN + 1);
+
+        let err = create_user(&pool, &new_user(&name, "long@example.com"))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, AppError::Validation(_)), "got {err:?}");
+        assert_eq!(
+            err.into_response().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[cfg(feature = "testcontainers")]
+    #[tokio::test]
+    async fn test_create_and_read_back_user_in_container() {
+        let Some(database) = crate::test_utils::container_pool().await else {
+            return;
+        };
+
+        let created = create_user(&database.pool, &new_user("Contained", "box@example.com"))
+            .await
+            .unwrap();
+        let fetched = get_user_by_id(&database.pool, created.id).await.unwrap();
+
+        assert_eq!(fetched, Some(created));
+    }
+
+    #[tokio::test]
+    async fn test_create_user_accepts_name_at_limit() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let name = "x".repeat(MAX_NAME_LEN);
+
+        let user = create_user(&pool, &new_user(&name, "max@example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(user.name, name);
+        assert_eq!(user.email, "max@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_email_exists_case_insensitive() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Upper", "A@x.com").await;
+
+        assert!(email_exists_case_insensitive(&pool, "a@x.com")
+            .await
+            .unwrap());
+        assert!(email_exists_case_insensitive(&pool, "A@X.COM")
+            .await
+            .unwrap());
+        assert!(!email_exists_case_insensitive(&pool, "b@x.com")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_create_user_normalizes_email() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let user = create_user(&pool, &new_user("Messy", "MeSsY@Example.Com"))
+            .await
+            .unwrap();
+        assert_eq!(user.email, "messy@example.com");
+
+        let err = create_user(&pool, &new_user("Again", "messy@EXAMPLE.com"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Conflict(_)), "got {err:?}");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_insert_user_with_id_then_generated_id() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let email = |value: &str| Email::parse(value).unwrap();
+
+        let imported = insert_user_with_id(&pool, 42, "Imported", &email("Old@Example.com"))
+            .await
+            .unwrap();
+        assert_eq!(imported.id, 42);
+        assert_eq!(imported.email, "old@example.com");
+        let err = insert_user_with_id(&pool, 42, "Again", &email("again@example.com"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, AppError::Conflict(msg) if msg.contains("42")),
+            "{err:?}"
+        );
+
+        assert_eq!(reset_user_id_sequence(&pool).await.unwrap(), 43);
+        let generated = create_user(&pool, &new_user("Generated", "new@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(generated.id, 43);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_upsert_users_splits_counts() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let existing = insert_user(&pool, "Old Name", "kept@example.com").await;
+
+        let counts = bulk_upsert_users(
+            &pool,
+            &[
+                new_user("New Name", "Kept@Example.com"),
+                new_user("Fresh", "fresh@example.com"),
+                new_user("Other", "other@example.com"),
+                new_user("Fresher", "fresh@example.com"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            counts,
+            UpsertCounts {
+                inserted: 2,
+                updated: 1
+            }
+        );
+        let kept = get_user_by_id(&pool, existing.id).await.unwrap().unwrap();
+        assert_eq!(kept.name, "New Name");
+        let fresh = find_users(
+            &pool,
+            &UserFilter {
+                name_contains: Some("Fresh".to_string()),
+                ..UserFilter::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(fresh.len(), 1);
+        assert_eq!(fresh[0].name, "Fresher");
+
+        let empty = bulk_upsert_users(&pool, &[]).await.unwrap();
+        assert_eq!(empty, UpsertCounts::default());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_upsert_users_restores_deleted_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let deleted = insert_user(&pool, "Gone", "gone@example.com").await;
+        soft_delete_user(&pool, deleted.id).await.unwrap();
+
+        let counts = bulk_upsert_users(&pool, &[new_user("Back", "Gone@Example.com")])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            counts,
+            UpsertCounts {
+                inserted: 0,
+                updated: 1
+            }
+        );
+        let restored = get_user_by_id(&pool, deleted.id).await.unwrap().unwrap();
+        assert_eq!(restored.name, "Back");
+        assert_eq!(restored.deleted_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_reports_exhausted_ids() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        // Overflowing the column (22003) needs a sequence wider than it;
+        // reaching the end of the default sequence raises 2200H.
+        for widen in [false, true] {
+            if widen {
+                sqlx::query("ALTER SEQUENCE users_id_seq AS bigint")
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+            sqlx::query("SELECT setval('users_id_seq', 2147483647)")
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let err = create_user(&pool, &new_user("Last", "last@example.com"))
+                .await
+                .unwrap_err();
+
+            assert!(matches!(err, AppError::IdsExhausted), "got {err:?}");
+            assert_eq!(
+                err.to_string(),
+                "User ids exhausted; migrate users.id to bigint"
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_columns_sql_names_only_requested_columns() {
+        assert_eq!(
+            select_columns_sql(&[Column::Email, Column::Id]),
+            "SELECT email, id FROM users WHERE id = $1 AND deleted_at IS NULL"
+        );
+        assert_eq!(
+            select_columns_sql(&[]),
+            "SELECT 1 FROM users WHERE id = $1 AND deleted_at IS NULL"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_columns_returns_requested_subset() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Sparse", "sparse@example.com").await;
+
+        let value = get_user_columns(&pool, user.id, &[Column::Name, Column::Id, Column::Name])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "name": "Sparse", "id": user.id })
+        );
+
+        let value = get_user_columns(&pool, user.id, &Column::ALL)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut full = serde_json::to_value(&user).unwrap();
+        full.as_object_mut().unwrap().remove("deleted_at");
+        assert_eq!(value, full);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_counts_related_audit_rows() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Cascade", "cascade@example.com").await;
+        touch_updated_at(&pool, user.id).await.unwrap();
+        touch_updated_at(&pool, user.id).await.unwrap();
+        let other = insert_user(&pool, "Other", "other@example.com").await;
+
+        let related = delete_user(&pool, user.id).await.unwrap();
+
+        assert_eq!(related, Some(RelatedRows { audit: 3 }));
+        assert!(get_user_by_id(&pool, user.id).await.unwrap().is_none());
+        assert_eq!(
+            crate::repository::count_audit_entries(&pool, user.id)
+                .await
+                .unwrap(),
+            4
+        );
+        assert!(get_user_by_id(&pool, other.id).await.unwrap().is_some());
+        assert_eq!(delete_user(&pool, user.id).await.unwrap(), None);
+        assert_eq!(delete_user(&pool, 999_999).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_columns_missing_or_deleted() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Gone", "gone@example.com").await;
+        assert_eq!(
+            get_user_columns(&pool, user.id, &[]).await.unwrap(),
+            Some(serde_json::json!({}))
+        );
+
+        assert!(soft_delete_user(&pool, user.id).await.unwrap());
+        assert_eq!(
+            get_user_columns(&pool, user.id, &[Column::Id])
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            get_user_columns(&pool, 999, &[Column::Id]).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_id_on_pool_and_transaction() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let stored = insert_user(&pool, "Pooled", "pooled@example.com").await;
+        assert_eq!(
+            get_user_by_id(&pool, stored.id).await.unwrap(),
+            Some(stored)
+        );
+
+        let mut tx = pool.begin().await.unwrap();
+        let pending = create_user(&mut *tx, &new_user("Pending", "pending@example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_user_by_id(&mut *tx, pending.id).await.unwrap(),
+            Some(pending.clone())
+        );
+        assert_eq!(get_user_by_id(&pool, pending.id).await.unwrap(), None);
+        tx.rollback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_user_with_name_parts() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let new_user: NewUser = serde_json::from_value(serde_json::json!({
+            "first_name": "Ada",
+            "last_name": "Lovelace",
+            "email": "ada@example.com",
+        }))
+        .unwrap();
+
+        let user = create_user(&pool, &new_user).await.unwrap();
+
+        assert_eq!(user.name, "Ada Lovelace");
+        assert_eq!(user.first_name.as_deref(), Some("Ada"));
+        assert_eq!(user.last_name.as_deref(), Some("Lovelace"));
+        assert_eq!(get_user_by_id(&pool, user.id).await.unwrap(), Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_update_name_parts_keeps_name_in_sync() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let stored = insert_user(&pool, "Ada", "parts@example.com").await;
+        let update = |first: Option<&str>, last: Option<&str>, name: Option<&str>| UserUpdate {
+            name: name.map(str::to_string),
+            first_name: first.map(str::to_string),
+            last_name: last.map(str::to_string),
+            ..UserUpdate::default()
+        };
+        let apply = |update: UserUpdate| {
+            let pool = pool.clone();
+            async move {
+                update_user_returning_prev(&pool, stored.id, &update)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .1
+            }
+        };
+
+        let user = apply(update(Some("Ada"), Some("Lovelace"), None)).await;
+        assert_eq!(user.name, "Ada Lovelace");
+
+        // The part not sent is kept and combined with the new one
+        let user = apply(update(None, Some("King"), None)).await;
+        assert_eq!(user.name, "Ada King");
+        assert_eq!(user.first_name.as_deref(), Some("Ada"));
+        assert_eq!(user.last_name.as_deref(), Some("King"));
+
+        // A plain name replaces the parts
+        let user = apply(update(None, None, Some("Countess"))).await;
+        assert_eq!(user.name, "Countess");
+        assert_eq!((user.first_name, user.last_name), (None, None));
+
+        let changes = get_user_changes(&pool, stored.id).await.unwrap().unwrap();
+        assert_eq!(
+            changes[1]["fields"],
+            serde_json::json!({ "name": "Ada King", "last_name": "King" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_name_with_name_parts() {
+        let update = UserUpdate {
+            name: Some("Ada".to_string()),
+            last_name: Some("Lovelace".to_string()),
+            ..UserUpdate::default()
+        };
+        let err = update_user_returning_prev(&unreachable_pool(), 1, &update)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Validation(_)), "{err:?}");
+
+        let update = UserUpdate {
+            first_name: Some(" ".to_string()),
+            ..UserUpdate::default()
+        };
+        let err = update_user_returning_prev(&unreachable_pool(), 1, &update)
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("first_name must not be empty"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_user_returning_prev() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let stored = insert_user(&pool, "Old Name", "rename@example.com").await;
+        let update = UserUpdate {
+            name: Some("New Name".to_string()),
+            ..UserUpdate::default()
+        };
+
+        let (previous, current) = update_user_returning_prev(&pool, stored.id, &update)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(previous, stored);
+        assert_eq!(current.name, "New Name");
+        assert_eq!(current.email, "rename@example.com");
+        assert_eq!(
+            get_user_by_id(&pool, stored.id).await.unwrap(),
+            Some(current)
+        );
+
+        let changes = get_user_changes(&pool, stored.id).await.unwrap().unwrap();
+        let entries = changes.as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0]["fields"],
+            serde_json::json!({ "name": "New Name" })
+        );
+        assert!(entries[0]["at"].is_string());
+
+        // Setting a field to its current value records nothing
+        update_user_returning_prev(&pool, stored.id, &update)
+            .await
+            .unwrap();
+        let changes = get_user_changes(&pool, stored.id).await.unwrap().unwrap();
+        assert_eq!(changes.as_array().unwrap().len(), 1);
+
+        let missing = update_user_returning_prev(&pool, stored.id + 1, &update)
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_user_creates_missing_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let email = Email::parse("new@example.com").unwrap();
+
+        let (user, created) = get_or_create_user(&pool, "New", &email).await.unwrap();
+
+        assert!(created);
+        assert_eq!(user.name, "New");
+        assert_eq!(get_user_by_id(&pool, user.id).await.unwrap(), Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_user_keeps_existing_name() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let existing = insert_user(&pool, "Original", "taken@example.com").await;
+        let email = Email::parse("taken@example.com").unwrap();
+
+        let (user, created) = get_or_create_user(&pool, "Renamed", &email).await.unwrap();
+
+        assert!(!created);
+        assert_eq!(user, existing);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_user_normalizes_email() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let email = Email::parse("Mixed@Example.COM").unwrap();
+
+        let (user, created) = get_or_create_user(&pool, "Mixed", &email).await.unwrap();
+        assert!(created);
+        assert_eq!(user.email, "mixed@example.com");
+
+        let again = Email::parse("MIXED@example.com").unwrap();
+        let (found, created) = get_or_create_user(&pool, "Other", &again).await.unwrap();
+        assert!(!created);
+        assert_eq!(found, user);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_user_rejects_deleted_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+        soft_delete_user(&pool, gone.id).await.unwrap();
+        let email = Email::parse("gone@example.com").unwrap();
+
+        let err = get_or_create_user(&pool, "Back", &email).await.unwrap_err();
+
+        assert!(matches!(err, AppError::Conflict(_)));
+    }
+
+    #[tokio::test]
+    async fn test_update_user_returning_prev_normalizes_email_and_rejects_taken() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Taken", "taken@example.com").await;
+        let stored = insert_user(&pool, "Mover", "mover@example.com").await;
+
+        let update = UserUpdate {
+            email: Some(Email::parse("Moved@Example.COM").unwrap()),
+            ..UserUpdate::default()
+        };
+        let (_, current) = update_user_returning_prev(&pool, stored.id, &update)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.email, "moved@example.com");
+
+        let update = UserUpdate {
+            email: Some(Email::parse("TAKEN@example.com").unwrap()),
+            ..UserUpdate::default()
+        };
+        let err = update_user_returning_prev(&pool, stored.id, &update)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::Conflict(_)));
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_update_user_returning_prev_skips_deleted_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+        soft_delete_user(&pool, gone.id).await.unwrap();
+        let update = UserUpdate {
+            name: Some("Back".to_string()),
+            ..UserUpdate::default()
+        };
+
+        let updated = update_user_returning_prev(&pool, gone.id, &update)
+            .await
+            .unwrap();
+
+        assert!(updated.is_none());
+        let changes = get_user_changes(&pool, gone.id).await.unwrap().unwrap();
+        assert_eq!(changes, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_find_users_name_date_range_and_sort() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user_created_at(&pool, "Alice Smith", "alice@example.com", day(1)).await;
+        insert_user_created_at(&pool, "Bob Smith", "bob@example.com", day(5)).await;
+        insert_user_created_at(&pool, "Carol Smith", "carol@example.com", day(10)).await;
+        insert_user_created_at(&pool, "Dave Jones", "dave@example.com", day(5)).await;
+        insert_user_created_at(&pool, "Erin Smith", "erin@example.com", day(20)).await;
+
+        let filter = UserFilter {
+            name_contains: Some("smith".to_string()),
+            created_after: Some(day(2)),
+            created_before: Some(day(15)),
+            sort: UserSort::CreatedAtDesc,
+            ..UserFilter::default()
+        };
+        let users = find_users(&pool, &filter).await.unwrap();
+
+        let names: Vec<_> = users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Carol Smith", "Bob Smith"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_search_yields_filtered_rows() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user_created_at(&pool, "Alice Smith", "alice@corp.com", day(1)).await;
+        insert_user_created_at(&pool, "Bob Smith", "bob@example.com", day(2)).await;
+        insert_user_created_at(&pool, "Carol Smith", "carol@corp.com", day(3)).await;
+        insert_user_created_at(&pool, "Dave Jones", "dave@corp.com", day(4)).await;
+        for n in 0..STREAM_BUFFER + 10 {
+            insert_user_created_at(&pool, "Zed Smith", &format!("z{n}@corp.com"), day(5)).await;
+        }
+
+        let filter = UserFilter {
+            name_contains: Some("smith".to_string()),
+            email_domain: Some("corp.com".to_string()),
+            sort: UserSort::CreatedAtDesc,
+            limit: Some(1),
+            ..UserFilter::default()
+        };
+        let streamed: Vec<User> = stream_search(&pool, &filter)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let expected = find_users(
+            &pool,
+            &UserFilter {
+                limit: Some(MAX_LIMIT),
+                ..filter
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(streamed.len(), STREAM_BUFFER + 10 + 2);
+        assert_eq!(
+            streamed.iter().map(|u| u.id).collect::<Vec<_>>(),
+            expected.iter().map(|u| u.id).collect::<Vec<_>>()
+        );
+        let tail: Vec<_> = streamed[STREAM_BUFFER + 10..]
+            .iter()
+            .map(|u| u.name.as_str())
+            .collect();
+        assert_eq!(tail, ["Carol Smith", "Alice Smith"]);
+    }
+
+    #[tokio::test]
+    async fn test_find_users_orders_ties_by_id_across_pages() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        for n in 0..5 {
+            insert_user_created_at(&pool, "Twin", &format!("twin{n}@example.com"), day(1)).await;
+        }
+        let all = find_users(&pool, &UserFilter::default()).await.unwrap();
+        let ids: Vec<_> = all.iter().map(|u| u.id).collect();
+
+        for (sort, expected) in [
+            (UserSort::CreatedAt, ids.clone()),
+            (UserSort::CreatedAtDesc, ids.iter().rev().copied().collect()),
+            (UserSort::Name, ids.clone()),
+        ] {
+            let mut paged = Vec::new();
+            for offset in (0..5).step_by(2) {
+                let filter = UserFilter {
+                    sort,
+                    limit: Some(2),
+                    offset: Some(offset),
+                    ..UserFilter::default()
+                };
+                let page = find_users(&pool, &filter).await.unwrap();
+                paged.extend(page.iter().map(|u| u.id));
+            }
+            assert_eq!(paged, expected, "{sort:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_find_users_email_domain_and_pagination() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Ann", "ann@corp.example").await;
+        insert_user(&pool, "Ben", "ben@other.example").await;
+        insert_user(&pool, "Cid", "cid@CORP.example").await;
+        insert_user(&pool, "Dee", "dee@corp.example").await;
+
+        let filter = UserFilter {
+            email_domain: Some("corp.example".to_string()),
+            sort: UserSort::Name,
+            limit: Some(2),
+            offset: Some(1),
+            ..UserFilter::default()
+        };
+        let users = find_users(&pool, &filter).await.unwrap();
+
+        let names: Vec<_> = users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Cid", "Dee"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_user_summaries() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let first = insert_user(&pool, "Ann", "ann@example.com").await;
+        insert_user(&pool, "Ben", "ben@example.com").await;
+
+        let summaries = list_user_summaries(&pool, 1, 0).await.unwrap();
+
+        assert_eq!(
+            summaries,
+            [UserSummary {
+                id: first.id,
+                name: first.name,
+                email: first.email,
+            }]
+        );
+        let json = serde_json::to_value(&summaries[0]).unwrap();
+        assert!(json.get("created_at").is_none());
+        assert!(json.get("updated_at").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_emails_renames_domain() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let ann = insert_user(&pool, "Ann", "ann@old.example").await;
+        let ben = insert_user(&pool, "Ben", "ben@OLD.example").await;
+        let other = insert_user(&pool, "Cid", "cid@other.example").await;
+
+        let updated = batch_update_emails(&pool, "old.example", "new.example")
+            .await
+            .unwrap();
+
+        assert_eq!(updated, 2);
+        let email = |id| {
+            let pool = pool.clone();
+            async move { get_user_by_id(&pool, id).await.unwrap().unwrap().email }
+        };
+        assert_eq!(email(ann.id).await, "ann@new.example");
+        assert_eq!(email(ben.id).await, "ben@new.example");
+        assert_eq!(email(other.id).await, "cid@other.example");
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_emails_skips_collisions() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let taken = insert_user(&pool, "Ann Old", "ann@old.example").await;
+        insert_user(&pool, "Ann New", "ann@new.example").await;
+        insert_user(&pool, "Ben", "ben@old.example").await;
+
+        let updated = batch_update_emails(&pool, "old.example", "new.example")
+            .await
+            .unwrap();
+
+        assert_eq!(updated, 1);
+        let unchanged = get_user_by_id(&pool, taken.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.email, "ann@old.example");
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_emails_skips_case_variant_collisions() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Bob New", "Bob@new.com").await;
+        let bob = insert_user(&pool, "Bob Old", "bob@old.com").await;
+        let cid = insert_user(&pool, "Cid", "cid@old.com").await;
+        let dee = insert_user(&pool, "Dee", "Dee@old.com").await;
+
+        let updated = batch_update_emails(&pool, "old.com", "NEW.com")
+            .await
+            .unwrap();
+
+        assert_eq!(updated, 2);
+        let email = |id| {
+            let pool = pool.clone();
+            async move { get_user_by_id(&pool, id).await.unwrap().unwrap().email }
+        };
+        assert_eq!(email(bob.id).await, "bob@old.com");
+        assert_eq!(email(cid.id).await, "cid@new.com");
+        assert_eq!(email(dee.id).await, "Dee@new.com");
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_emails_lowercases_domain_in_place() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let ann = insert_user(&pool, "Ann", "ann@OLD.example").await;
+
+        let updated = batch_update_emails(&pool, "old.example", "old.example")
+            .await
+            .unwrap();
+
+        assert_eq!(updated, 1);
+        let user = get_user_by_id(&pool, ann.id).await.unwrap().unwrap();
+        assert_eq!(user.email, "ann@old.example");
+    }
+
+    #[tokio::test]
+    async fn test_count_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Ann", "ann@corp.example").await;
+        insert_user(&pool, "Ben", "ben@other.example").await;
+
+        let all = UserFilter {
+            limit: Some(1),
+            ..UserFilter::default()
+        };
+        assert_eq!(count_users(&pool, &all).await.unwrap(), 2);
+
+        let corp = UserFilter {
+            email_domain: Some("corp.example".to_string()),
+            ..UserFilter::default()
+        };
+        assert_eq!(count_users(&pool, &corp).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_active_since() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let day = |d| Utc.with_ymd_and_hms(2024, 3, d, 12, 0, 0).unwrap();
+        for (email, login) in [
+            ("early@example.com", Some(day(1))),
+            ("mid@example.com", Some(day(10))),
+            ("late@example.com", Some(day(20))),
+            ("never@example.com", None),
+        ] {
+            let user = insert_user(&pool, "User", email).await;
+            sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
+                .bind(login)
+                .bind(user.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(count_active_since(&pool, day(5)).await.unwrap(), 2);
+        assert_eq!(count_active_since(&pool, day(10)).await.unwrap(), 1);
+        assert_eq!(count_active_since(&pool, day(25)).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_count_active_since_skips_deleted_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let kept = insert_user(&pool, "Kept", "kept@example.com").await;
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+        touch_logins(&pool, &[kept.id, gone.id]).await.unwrap();
+        soft_delete_user(&pool, gone.id).await.unwrap();
+
+        assert_eq!(count_active_since(&pool, day(1)).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_find_users_never_logged_in() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut never = Vec::new();
+        for (email, login) in [
+            ("fresh@example.com", None),
+            ("back@example.com", Some(day(2))),
+            ("idle@example.com", None),
+            ("regular@example.com", Some(day(3))),
+        ] {
+            let user = insert_user(&pool, "User", email).await;
+            sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
+                .bind(login)
+                .bind(user.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            if login.is_none() {
+                never.push(user.email);
+            }
+        }
+        let emails = |users: Vec<User>| users.into_iter().map(|u| u.email).collect::<Vec<_>>();
+
+        let filter = UserFilter {
+            never_logged_in: Some(true),
+            ..UserFilter::default()
+        };
+        assert!(filter.has_conditions());
+        assert_eq!(emails(find_users(&pool, &filter).await.unwrap()), never);
+        assert_eq!(count_users(&pool, &filter).await.unwrap(), 2);
+
+        let filter = UserFilter {
+            never_logged_in: Some(false),
+            ..UserFilter::default()
+        };
+        assert_eq!(
+            emails(find_users(&pool, &filter).await.unwrap()),
+            ["back@example.com", "regular@example.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_touch_logins_updates_all_ids_at_once() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let long_ago = day(1);
+        let mut ids = Vec::new();
+        for email in ["a@example.com", "b@example.com", "c@example.com"] {
+            let user = insert_user(&pool, "User", email).await;
+            sqlx::query("UPDATE users SET last_login_at = $1 WHERE id = $2")
+                .bind(long_ago)
+                .bind(user.id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+
+        let touched = touch_logins(&pool, &[ids[0], ids[1], 999]).await.unwrap();
+
+        assert_eq!(touched, 2);
+        let logins: Vec<(i32, DateTime<Utc>)> =
+            sqlx::query_as("SELECT id, last_login_at FROM users ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert!(
+            logins[0].1 > long_ago && logins[1].1 > long_ago,
+            "{logins:?}"
+        );
+        assert_eq!(logins[2], (ids[2], long_ago));
+        assert_eq!(touch_logins(&pool, &[]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_count_users_created_today() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Today", "today@example.com").await;
+        insert_user_created_at(&pool, "Past", "past@example.com", day(1)).await;
+
+        assert_eq!(count_users_created_today(&pool).await.unwrap(), 1);
+
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+        soft_delete_user(&pool, gone.id).await.unwrap();
+        assert_eq!(count_users_created_today(&pool).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_users_by_created_month_fills_gaps() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let now = Utc::now();
+        let two_months_ago = now.checked_sub_months(Months::new(2)).unwrap();
+        insert_user(&pool, "Recent", "recent@example.com").await;
+        insert_user_created_at(&pool, "Early", "early@example.com", two_months_ago).await;
+        insert_user_created_at(&pool, "Old", "old@example.com", day(1)).await;
+
+        let series = count_users_by_created_month(&pool, 3).await.unwrap();
+
+        let month = |at: DateTime<Utc>| at.date_naive().with_day(1).unwrap();
+        let one_month_ago = now.checked_sub_months(Months::new(1)).unwrap();
+        assert_eq!(
+            series,
+            [
+                (month(two_months_ago), 1),
+                (month(one_month_ago), 0),
+                (month(now), 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_count_users_by_created_month_without_months() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Recent", "recent@example.com").await;
+
+        assert!(count_users_by_created_month(&pool, 0)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_find_duplicate_emails() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let upper = insert_user(&pool, "Upper", "A@x.com").await;
+        let lower = insert_user(&pool, "Lower", " a@x.com").await;
+        let padded = insert_user(&pool, "Padded", " a@x.com ").await;
+        insert_user(&pool, "Other", "b@x.com").await;
+
+        let groups = find_duplicate_emails(&pool).await.unwrap();
+
+        assert_eq!(
+            groups,
+            [DuplicateEmailGroup {
+                normalized_email: "a@x.com".to_string(),
+                count: 3,
+                user_ids: vec![upper.id, lower.id, padded.id],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_swap_emails() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let ann = insert_user(&pool, "Ann", "ann@example.com").await;
+        let bob = insert_user(&pool, "Bob", "bob@example.com").await;
+
+        let (new_ann, new_bob) = swap_emails(&pool, ann.id, bob.id).await.unwrap();
+
+        assert_eq!(new_ann.email, "bob@example.com");
+        assert_eq!(new_bob.email, "ann@example.com");
+        let stored_ann = get_user_by_id(&pool, ann.id).await.unwrap().unwrap();
+        let stored_bob = get_user_by_id(&pool, bob.id).await.unwrap().unwrap();
+        assert_eq!(stored_ann.email, "bob@example.com");
+        assert_eq!(stored_bob.email, "ann@example.com");
+        let parked: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE email LIKE '%@swap.invalid'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(parked, 0);
+    }
+
+    #[tokio::test]
+    async fn test_swap_emails_rejects_missing_and_identical_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let ann = insert_user(&pool, "Ann", "ann@example.com").await;
+
+        let missing = swap_emails(&pool, ann.id, ann.id + 1000).await.unwrap_err();
+        assert!(matches!(missing, AppError::NotFound(_)), "{missing:?}");
+        let same = swap_emails(&pool, ann.id, ann.id).await.unwrap_err();
+        assert!(matches!(same, AppError::Validation(_)), "{same:?}");
+
+        let stored = get_user_by_id(&pool, ann.id).await.unwrap().unwrap();
+        assert_eq!(stored.email, "ann@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_list_email_domains_orders_by_count_and_pages() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        for (i, domain) in ["b.com", "a.com", "c.com", "A.com", "c.com", "c.com"]
+            .into_iter()
+            .enumerate()
+        {
+            insert_user(&pool, "Domain", &format!("u{i}@{domain}")).await;
+        }
+
+        let domains = list_email_domains(&pool, 100, 0).await.unwrap();
+        let counts: Vec<_> = domains
+            .iter()
+            .map(|d| (d.domain.as_str(), d.count))
+            .collect();
+        assert_eq!(counts, [("c.com", 3), ("a.com", 2), ("b.com", 1)]);
+        assert_eq!(count_email_domains(&pool).await.unwrap(), 3);
+
+        let second = list_email_domains(&pool, 1, 1).await.unwrap();
+        assert_eq!(second, [domains[1].clone()]);
+        assert!(list_email_domains(&pool, 2, 3).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_email_domains_skips_deleted_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Kept", "kept@kept.com").await;
+        let gone = insert_user(&pool, "Gone", "gone@gone.com").await;
+        soft_delete_user(&pool, gone.id).await.unwrap();
+
+        let domains = list_email_domains(&pool, 100, 0).await.unwrap();
+        let names: Vec<_> = domains.iter().map(|d| d.domain.as_str()).collect();
+        assert_eq!(names, ["kept.com"]);
+        assert_eq!(count_email_domains(&pool).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Stats", "stats@example.com").await;
+
+        analyze_users(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_estimate_user_count() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        assert!(estimate_user_count(&pool).await.unwrap() >= 0);
+
+        for i in 0..3 {
+            insert_user(&pool, "Estimated", &format!("estimated{i}@example.com")).await;
+        }
+        analyze_users(&pool).await.unwrap();
+
+        assert_eq!(estimate_user_count(&pool).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_estimate_user_count_leaves_out_deleted_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            ids.push(
+                insert_user(&pool, "Estimated", &format!("estimated{i}@example.com"))
+                    .await
+                    .id,
+            );
+        }
+        for &id in &ids[..2] {
+            soft_delete_user(&pool, id).await.unwrap();
+        }
+        analyze_users(&pool).await.unwrap();
+
+        assert_eq!(estimate_user_count(&pool).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_page_walks_all_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            ids.push(
+                insert_user(&pool, "Paged", &format!("paged{i}@example.com"))
+                    .await
+                    .id,
+            );
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let page = get_user_page(&pool, cursor, Some(2)).await.unwrap();
+            pages += 1;
+            assert!(page.items.len() <= 2);
+            seen.extend(page.items.iter().map(|user| user.id));
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen, ids);
+        assert_eq!(pages, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_page_exact_fit_has_no_next() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "One", "one@example.com").await;
+        insert_user(&pool, "Two", "two@example.com").await;
+
+        let page = get_user_page(&pool, None, Some(2)).await.unwrap();
+
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.next, None);
+    }
+
+    #[tokio::test]
+    async fn test_soft_deleted_users_are_hidden_unless_included() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let kept = insert_user(&pool, "Kept", "kept@example.com").await;
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+
+        assert!(soft_delete_user(&pool, gone.id).await.unwrap());
+        assert!(!soft_delete_user(&pool, gone.id).await.unwrap());
+
+        assert_eq!(get_user_by_id(&pool, gone.id).await.unwrap(), None);
+        let listed = find_users(&pool, &UserFilter::default()).await.unwrap();
+        assert_eq!(listed, std::slice::from_ref(&kept));
+        assert_eq!(count_users(&pool, &UserFilter::default()).await.unwrap(), 1);
+
+        let filter = UserFilter {
+            include_deleted: true,
+            ..UserFilter::default()
+        };
+        let listed = find_users(&pool, &filter).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0], kept);
+        assert_eq!(listed[1].id, gone.id);
+        assert!(listed[1].deleted_at.is_some());
+        assert_eq!(count_users(&pool, &filter).await.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_page_bounds_honours_max_limit() {
+        let filter = UserFilter {
+            limit: Some(5000),
+            ..UserFilter::default()
+        };
+        assert_eq!(page_bounds(&filter), (MAX_LIMIT, 0));
+
+        let filter = UserFilter {
+            max_limit: Some(1000),
+            ..filter
+        };
+        assert_eq!(page_bounds(&filter), (1000, 0));
+
+        let filter = UserFilter {
+            limit: None,
+            ..filter
+        };
+        assert_eq!(page_bounds(&filter), (DEFAULT_LIMIT, 0));
+    }
+
+    #[tokio::test]
+    async fn test_touch_updated_at() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Touch", "touch@example.com").await;
+        sqlx::query("UPDATE users SET updated_at = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind(day(1))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let touched = touch_updated_at(&pool, user.id).await.unwrap().unwrap();
+        assert!(touched.updated_at > day(1));
+        assert_eq!(touched.name, "Touch");
+        assert_eq!(touched.created_at, user.created_at);
+
+        assert!(touch_updated_at(&pool, user.id + 1)
+            .await
+            .unwrap()
+            .is_none());
+
+        soft_delete_user(&pool, user.id).await.unwrap();
+        assert!(touch_updated_at(&pool, user.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_users_ranks_prefix_matches_first() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Alice Anderson", "alice@example.com").await;
+        insert_user(&pool, "Anders Berg", "anders@example.com").await;
+        insert_user(&pool, "Bob Smith", "bob@example.com").await;
+
+        let users = search_users(&pool, "anders", &PageParams::default())
+            .await
+            .unwrap();
+
+        let names: Vec<_> = users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Anders Berg", "Alice Anderson"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_users_skips_deleted_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let kept = insert_user(&pool, "Anders Kept", "kept@example.com").await;
+        let gone = insert_user(&pool, "Anders Gone", "gone@example.com").await;
+        soft_delete_user(&pool, gone.id).await.unwrap();
+
+        let users = search_users(&pool, "anders", &PageParams::default())
+            .await
+            .unwrap();
+
+        assert_eq!(users, [kept]);
+    }
+
+    #[tokio::test]
+    async fn test_find_users_treats_wildcards_literally() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "100% Real", "real@example.com").await;
+        insert_user(&pool, "100 Fake", "fake@example.com").await;
+
+        let filter = UserFilter {
+            name_contains: Some("100%".to_string()),
+            ..UserFilter::default()
+        };
+        let users = find_users(&pool, &filter).await.unwrap();
+
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "100% Real");
+    }
+}
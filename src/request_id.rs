@@ -0,0 +1,209 @@
+//! Request correlation ids
+//!
+//! Every request carries an id, taken from the inbound request id header
+//! (`X-Request-Id` unless `REQUEST_ID_HEADER` names another) when it is
+//! well-formed or generated otherwise. The id is stored in the
+//! request extensions, recorded on the request's tracing span and echoed on
+//! the response. Work spawned while handling a request should go through
+//! [`spawn_in_current_span`] so its logs stay attributed to that request.
+
+use crate::{metrics::UNMATCHED_ROUTE, state::AppState};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{fmt, future::Future};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+/// Default header carrying the request id
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest inbound id that is reused rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Correlation id of the current request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generate a fresh random id
+    #[must_use]
+    pub fn generate() -> Self {
+        Self(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Reuse an inbound header value if it is a sensible id
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LEN
+            && value.bytes().all(|b| b.is_ascii_graphic());
+        valid.then(|| Self(value.to_string()))
+    }
+
+    /// The id as a string slice
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware assigning a [`RequestId`] to each request and echoing it back
+pub async fn propagate_request_id(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let header = &state.config.request_id_header;
+    let id = request
+        .headers()
+        .get(header)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+        response.headers_mut().insert(header.clone(), value);
+    }
+    response
+}
+
+/// Span for a request, tagged with its matched route and [`RequestId`]
+///
+/// Used as the `make_span_with` callback of the trace layer.
+pub fn make_span<B>(request: &axum::http::Request<B>) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map_or("", RequestId::as_str);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, MatchedPath::as_str);
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        route = %route,
+        request_id = %request_id,
+    )
+}
+
+/// Spawn a task that inherits the current span, and with it the request id
+///
+/// Use this instead of `tokio::spawn` for background work started by a
+/// handler, so the work's logs carry the originating request's id.
+pub fn spawn_in_current_span<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.instrument(Span::current()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_utils::{capture_logs, test_config, test_state, unreachable_pool},
+    };
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+
+    fn app() -> Router {
+        app_with(test_config())
+    }
+
+    fn app_with(config: Config) -> Router {
+        let state = test_state(unreachable_pool(), config);
+        Router::new()
+            .route(
+                "/spawn",
+                get(|| async {
+                    spawn_in_current_span(async { tracing::info!("background audit write") })
+                        .await
+                        .unwrap();
+                }),
+            )
+            .layer(TraceLayer::new_for_http().make_span_with(make_span))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                propagate_request_id,
+            ))
+            .with_state(state)
+    }
+
+    #[tokio::test]
+    async fn test_spawned_task_logs_carry_request_id() {
+        let (logs, _guard) = capture_logs();
+
+        let request = axum::http::Request::get("/spawn")
+            .header(&X_REQUEST_ID, "req-abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[&X_REQUEST_ID], "req-abc-123");
+        let line = logs
+            .contents()
+            .lines()
+            .find(|line| line.contains("background audit write"))
+            .map(str::to_string)
+            .expect("spawned task did not log");
+        assert!(line.contains("request_id=req-abc-123"), "{line}");
+    }
+
+    #[tokio::test]
+    async fn test_custom_request_id_header() {
+        let config = Config {
+            request_id_header: HeaderName::from_static("x-correlation-id"),
+            ..test_config()
+        };
+
+        let request = axum::http::Request::get("/spawn")
+            .header("X-Correlation-Id", "corr-42")
+            .header(&X_REQUEST_ID, "ignored")
+            .body(Body::empty())
+            .unwrap();
+        let response = app_with(config.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-correlation-id"], "corr-42");
+        assert!(!response.headers().contains_key(&X_REQUEST_ID));
+
+        let request = axum::http::Request::get("/spawn")
+            .body(Body::empty())
+            .unwrap();
+        let response = app_with(config).oneshot(request).await.unwrap();
+        let id = response.headers()["x-correlation-id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(id).is_ok(), "{id}");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_when_missing_or_invalid() {
+        for header in [None, Some("has spaces"), Some("")] {
+            let mut request = axum::http::Request::get("/spawn");
+            if let Some(value) = header {
+                request = request.header(&X_REQUEST_ID, value);
+            }
+            let response = app()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            let id = response.headers()[&X_REQUEST_ID].to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(id).is_ok(), "{id}");
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Response helpers
+//!
+//! This module provides responders shared by the route handlers.
+
+use crate::{
+    config::{Config, JsonNaming},
+    error::{AppError, ErrorCode},
+    models::ApiResponse,
+};
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// JSON responder honouring the configured output format
+///
+/// Behaves like `axum::Json` but pretty-prints the body when `PRETTY_JSON`
+/// is enabled, which makes responses easier to read while debugging locally.
+/// With `JSON_NAMING=camel` object keys are renamed to camel case, so models
+/// keep a single snake case definition. The `Content-Type` carries the
+/// `JSON_CHARSET` charset.
+#[derive(Debug)]
+pub struct JsonResponse<T> {
+    value: T,
+    pretty: bool,
+    naming: JsonNaming,
+    content_type: HeaderValue,
+}
+
+impl<T> JsonResponse<T> {
+    /// Wrap a value using the output format from `config`
+    pub fn new(value: T, config: &Config) -> Self {
+        Self {
+            value,
+            pretty: config.pretty_json,
+            naming: config.json_naming,
+            content_type: json_content_type(config),
+        }
+    }
+}
+
+/// `Content-Type` of JSON responses, with the configured charset
+#[must_use]
+pub fn json_content_type(config: &Config) -> HeaderValue {
+    HeaderValue::try_from(format!("application/json; charset={}", config.json_charset))
+        .unwrap_or(HeaderValue::from_static("application/json"))
+}
+
+impl<T: Serialize> JsonResponse<T> {
+    /// The serialized body, as it would be sent
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be serialized
+    pub fn body(&self) -> serde_json::Result<String> {
+        match self.naming {
+            JsonNaming::Snake => self.render(&self.value),
+            JsonNaming::Camel => self.render(&camel_case_keys(serde_json::to_value(&self.value)?)),
+        }
+    }
+
+    fn render(&self, value: &impl Serialize) -> serde_json::Result<String> {
+        if self.pretty {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        }
+    }
+}
+
+/// Rename the keys of every object in `value` from snake case to camel case
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (camel_case(&key), camel_case_keys(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_case_keys).collect()),
+        other => other,
+    }
+}
+
+/// `created_at` as `createdAt`
+fn camel_case(key: &str) -> String {
+    let mut parts = key.split('_');
+    let mut out = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            out.extend(first.to_uppercase());
+            out.push_str(chars.as_str());
+        }
+    }
+    out
+}
+
+impl<T: Serialize> IntoResponse for JsonResponse<T> {
+    fn into_response(self) -> Response {
+        match self.body() {
+            Ok(body) => ([(header::CONTENT_TYPE, self.content_type)], body).into_response(),
+            Err(e) => {
+                AppError::Internal(format!("Failed to serialize response: {e}")).into_response()
+            }
+        }
+    }
+}
+
+/// Render `response` in the configured JSON format with its status
+///
+/// A failure is logged as a plain [`AppError`] response would be, and
+/// carries its [`ErrorCode`] for the metrics layer.
+pub fn api_response<T: Serialize>(response: ApiResponse<T>, config: &Config) -> Response {
+    let status = response.status();
+    let code = match &response {
+        ApiResponse::Success(_) => None,
+        ApiResponse::Failure(error) => {
+            error.log();
+            Some(ErrorCode(error.code()))
+        }
+    };
+    let mut response = (status, JsonResponse::new(response, config)).into_response();
+    if let Some(code) = code {
+        response.extensions_mut().insert(code);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Page, User},
+        test_utils::test_config,
+    };
+    use axum::body::to_bytes;
+    use serde_json::json;
+
+    async fn render(pretty: bool) -> String {
+        let config = Config {
+            pretty_json: pretty,
+            ..test_config()
+        };
+        let response = JsonResponse::new(json!({ "id": 1 }), &config).into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/json; charset=utf-8"
+        );
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_content_type_uses_configured_charset() {
+        let config = Config {
+            json_charset: "iso-8859-1".to_string(),
+            ..test_config()
+        };
+        let response = JsonResponse::new(json!({}), &config).into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/json; charset=iso-8859-1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_output() {
+        assert_eq!(render(false).await, r#"{"id":1}"#);
+    }
+
+    #[tokio::test]
+    async fn test_pretty_output() {
+        assert_eq!(render(true).await, "{\n  \"id\": 1\n}");
+    }
+
+    fn user_keys(naming: JsonNaming) -> Vec<String> {
+        let config = Config {
+            json_naming: naming,
+            ..test_config()
+        };
+        let user = User::builder().name("Ann").email("ann@example.com").build();
+        let body = JsonResponse::new(Page::new(vec![user], 1, 20, 0), &config)
+            .body()
+            .unwrap();
+        let page: Value = serde_json::from_str(&body).unwrap();
+        let mut keys: Vec<_> = page["items"][0]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_user_field_naming() {
+        assert_eq!(
+            user_keys(JsonNaming::Snake),
+            ["created_at", "email", "id", "name", "updated_at"]
+        );
+        assert_eq!(
+            user_keys(JsonNaming::Camel),
+            ["createdAt", "email", "id", "name", "updatedAt"]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! `X-Response-Time` header
+//!
+//! Reports how long the service spent producing each response, in
+//! milliseconds, so clients can time requests without extra tooling.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::{Duration, Instant};
+
+/// Header carrying the elapsed handling time
+pub static X_RESPONSE_TIME: HeaderName = HeaderName::from_static("x-response-time");
+
+/// Middleware stamping every response, including errors, with its duration
+pub async fn add_response_time(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+
+    if let Ok(value) = HeaderValue::from_str(&format_millis(start.elapsed())) {
+        response
+            .headers_mut()
+            .insert(X_RESPONSE_TIME.clone(), value);
+    }
+    response
+}
+
+/// Format a duration as `<ms>ms` with microsecond precision
+fn format_millis(elapsed: Duration) -> String {
+    format!("{:.3}ms", elapsed.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_format_millis() {
+        assert_eq!(format_millis(Duration::from_micros(1_500)), "1.500ms");
+    }
+
+    #[tokio::test]
+    async fn test_header_on_error_response() {
+        let app = Router::new()
+            .route("/fail", get(|| async { StatusCode::INTERNAL_SERVER_ERROR }))
+            .layer(middleware::from_fn(add_response_time));
+
+        let response = app
+            .oneshot(Request::get("/fail").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().contains_key(&X_RESPONSE_TIME));
+    }
+}
@@ -0,0 +1,250 @@
+//! CSV rendering of user listings
+
+use crate::models::User;
+use axum::{
+    body::Body,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, Stream, StreamExt};
+use std::pin::Pin;
+
+/// Media type of CSV responses
+const TEXT_CSV: &str = "text/csv";
+
+/// Header row matching the fields written by [`user_row`]
+const HEADER: &str = "id,name,email,created_at,updated_at\r\n";
+
+/// Whether the `Accept` header prefers CSV over JSON
+///
+/// Only an explicit `text/csv` selects CSV, and only when it has a higher
+/// quality than an explicit `application/json`. Wildcards, unknown types
+/// and a missing header all leave the JSON default in place.
+pub(super) fn wants_csv(headers: &HeaderMap) -> bool {
+    let mut csv = 0.0;
+    let mut json = 0.0;
+    for accept in headers.get_all(header::ACCEPT) {
+        let Ok(accept) = accept.to_str() else {
+            continue;
+        };
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default();
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if media_type.eq_ignore_ascii_case(TEXT_CSV) {
+                csv = f32::max(csv, quality);
+            } else if media_type.eq_ignore_ascii_case("application/json") {
+                json = f32::max(json, quality);
+            }
+        }
+    }
+    csv > json
+}
+
+/// Stream `users` as a CSV document with a header row
+///
+/// Rows are written as they arrive, so exports of any size are served in
+/// bounded memory. A database error ends the response early. A client that
+/// disconnects mid-export makes the server drop the body, which drops
+/// `users` so no further rows are fetched; that is logged at debug level,
+/// as it is routine for large downloads.
+pub(super) fn users_response(
+    users: impl Stream<Item = Result<User, sqlx::Error>> + Send + 'static,
+) -> Response {
+    let users: Pin<Box<dyn Stream<Item = _> + Send>> = Box::pin(users);
+    let rows = stream::unfold(
+        Some((users, ExportProgress::default())),
+        |state| async move {
+            let (mut users, mut progress) = state?;
+            match users.next().await {
+                Some(Ok(user)) => {
+                    progress.rows += 1;
+                    Some((Ok(user_row(&user)), Some((users, progress))))
+                }
+                Some(Err(e)) => {
+                    progress.finished = true;
+                    tracing::error!(error = %e, rows = progress.rows, "CSV export failed");
+                    Some((Err(e), None))
+                }
+                None => {
+                    progress.finished = true;
+                    None
+                }
+            }
+        },
+    );
+    let body = stream::once(async { Ok(HEADER.to_string()) }).chain(rows);
+    (
+        [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Rows written by an export, reporting one abandoned before its end
+#[derive(Default)]
+struct ExportProgress {
+    rows: usize,
+    finished: bool,
+}
+
+impl Drop for ExportProgress {
+    fn drop(&mut self) {
+        if !self.finished {
+            tracing::debug!(rows = self.rows, "Client disconnected during CSV export");
+        }
+    }
+}
+
+/// One CSV line for `user`, terminated by CRLF as RFC 4180 specifies
+fn user_row(user: &User) -> String {
+    format!(
+        "{},{},{},{},{}\r\n",
+        user.id,
+        field(&user.name),
+        field(&user.email),
+        user.created_at.to_rfc3339(),
+        user.updated_at.to_rfc3339()
+    )
+}
+
+/// Quote `value` if it contains a delimiter, quote or line break
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture_logs;
+    use axum::http::HeaderValue;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// `count` users, tallying in `pulled` how many the export asked for
+    fn users(
+        count: usize,
+        pulled: &Arc<AtomicUsize>,
+    ) -> impl Stream<Item = Result<User, sqlx::Error>> + Send + 'static {
+        let pulled = Arc::clone(pulled);
+        stream::iter(0..count).map(move |i| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+            Ok(User::builder()
+                .name(format!("User {i}"))
+                .email(format!("user{i}@example.com"))
+                .build())
+        })
+    }
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_wants_csv() {
+        assert!(!wants_csv(&HeaderMap::new()));
+        for value in [
+            "text/csv",
+            "TEXT/CSV",
+            "text/csv, */*",
+            "application/json;q=0.5, text/csv",
+        ] {
+            assert!(wants_csv(&accept(value)), "{value}");
+        }
+        for value in [
+            "*/*",
+            "text/*",
+            "application/json",
+            "text/html",
+            "application/json, text/csv",
+            "text/csv;q=0",
+            "text/csv;q=bogus",
+        ] {
+            assert!(!wants_csv(&accept(value)), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_user_row_quotes_special_characters() {
+        let user = User::builder()
+            .id(7)
+            .name("Doe, \"Jo\"")
+            .email("jo@example.com")
+            .build();
+
+        let row = user_row(&user);
+
+        assert!(
+            row.starts_with("7,\"Doe, \"\"Jo\"\"\",jo@example.com,"),
+            "{row}"
+        );
+        assert!(row.ends_with("\r\n"));
+        assert_eq!(row.matches(',').count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_stops_fetching_and_logs_at_debug() {
+        let (logs, _guard) = capture_logs();
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let response = users_response(users(1000, &pulled));
+
+        // The client reads the header and one row, then goes away
+        let mut body = response.into_body().into_data_stream();
+        body.next().await.unwrap().unwrap();
+        body.next().await.unwrap().unwrap();
+        drop(body);
+
+        assert_eq!(pulled.load(Ordering::SeqCst), 1);
+        let logs = logs.contents();
+        assert!(logs.contains("DEBUG"), "{logs}");
+        assert!(
+            logs.contains("Client disconnected during CSV export"),
+            "{logs}"
+        );
+        assert!(logs.contains("rows=1"), "{logs}");
+        assert!(!logs.contains("ERROR"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn test_complete_export_logs_nothing() {
+        let (logs, _guard) = capture_logs();
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let response = users_response(users(3, &pulled));
+
+        let chunks: Vec<_> = response.into_body().into_data_stream().collect().await;
+
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(Result::is_ok));
+        assert!(logs.contents().is_empty(), "{}", logs.contents());
+    }
+
+    #[tokio::test]
+    async fn test_database_error_ends_the_export() {
+        let (logs, _guard) = capture_logs();
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let failing = users(1, &pulled)
+            .chain(stream::iter([Err(sqlx::Error::PoolClosed)]))
+            .chain(users(5, &pulled));
+        let response = users_response(failing);
+
+        let chunks: Vec<_> = response.into_body().into_data_stream().collect().await;
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[2].is_err());
+        assert_eq!(pulled.load(Ordering::SeqCst), 1);
+        let logs = logs.contents();
+        assert!(logs.contains("CSV export failed"), "{logs}");
+        assert!(!logs.contains("disconnected"), "{logs}");
+    }
+}
@@ -0,0 +1,290 @@
+//! Health probes below the `HEALTH_PATH` prefix
+
+use crate::{error::AppError, repository, state::AppState};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde_json::{json, Value};
+use std::{sync::atomic::Ordering, time::Duration};
+
+/// Build the router of the probes below the `HEALTH_PATH` prefix
+///
+/// The liveness check at the prefix itself is registered by
+/// [`crate::build_app`].
+pub fn router(prefix: &str) -> Router<AppState> {
+    Router::new()
+        .route(&format!("{prefix}/ready"), get(readiness))
+        .route(&format!("{prefix}/startup"), get(startup_probe))
+        .route(&format!("{prefix}/stats"), get(stats))
+}
+
+/// `GET /health/ready` - readiness probe
+///
+/// Reports `503` until the startup task has reached and migrated the
+/// database, then reflects a live ping (a rolled-back write when
+/// `DEEP_HEALTH_CHECK` is set). With `WATCHDOG_INTERVAL_SECS` the
+/// [watchdog](crate::watchdog)'s last result is reported instead of pinging. Once shutdown begins it reports `503`
+/// regardless, so no new traffic is routed here while requests drain. A
+/// ready response also names the `PostgreSQL` server version. A database
+/// that does not answer within `HEALTH_CHECK_TIMEOUT_MS` gets `503` with
+/// status `timeout`.
+async fn readiness(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    if state.draining.load(Ordering::Acquire) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "draining" })),
+        );
+    }
+    if !state.db_ready.load(Ordering::Acquire) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "starting" })),
+        );
+    }
+    if state.config.watchdog_interval_secs > 0 {
+        return (StatusCode::OK, Json(json!({ "status": "ready" })));
+    }
+
+    let pool = state.pool();
+    let check = async {
+        if state.config.deep_health_check {
+            repository::healthcheck_full(&pool).await?;
+        } else {
+            repository::ping(&pool).await?;
+        }
+        Ok::<_, sqlx::Error>(repository::server_version(&pool).await)
+    };
+    let limit = state.config.health_check_timeout_ms;
+    let outcome = if limit == 0 {
+        Ok(check.await)
+    } else {
+        tokio::time::timeout(Duration::from_millis(limit), check).await
+    };
+    let Ok(probe) = outcome else {
+        tracing::warn!(timeout_ms = limit, "Readiness ping timed out");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "timeout" })),
+        );
+    };
+    match probe {
+        Ok(version) => {
+            let mut body = json!({ "status": "ready" });
+            match version {
+                Ok(version) => body["server_version"] = json!(version),
+                Err(e) => tracing::debug!(error = %e, "Could not read server version"),
+            }
+            (StatusCode::OK, Json(body))
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Readiness ping failed");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "status": "unavailable" })),
+            )
+        }
+    }
+}
+
+/// `GET /health/startup` - startup probe
+///
+/// Reports `503` until the first ping and migrations have completed, then
+/// `200` for good, like liveness, unless the [watchdog](crate::watchdog)
+/// later finds the database unreachable. It neither pings the database nor reflects
+/// draining; that is the readiness probe's job.
+async fn startup_probe(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    if state.db_ready.load(Ordering::Acquire) {
+        (StatusCode::OK, Json(json!({ "status": "started" })))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "starting" })),
+        )
+    }
+}
+
+/// `GET /health/stats` - user statistics for dashboards
+async fn stats(State(state): State<AppState>) -> Result<Json<Value>, AppError> {
+    let created_today = repository::count_users_created_today(&state.pool()).await?;
+    Ok(Json(json!({ "users_created_today": created_today })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        startup,
+        test_utils::{get_body, insert_user, test_config, test_pool, test_state, unreachable_pool},
+    };
+
+    #[tokio::test]
+    async fn test_readiness_unready_until_first_ping() {
+        let state = test_state(unreachable_pool(), test_config());
+        state.db_ready.store(false, Ordering::Release);
+
+        let init = tokio::time::timeout(
+            Duration::from_millis(300),
+            startup::initialize_database(&state),
+        )
+        .await;
+        assert!(init.is_err(), "initialization should still be retrying");
+
+        let (status, body) = get_body(router("/health").with_state(state), "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("starting"));
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_watchdog_without_pinging() {
+        let config = Config {
+            watchdog_interval_secs: 5,
+            ..test_config()
+        };
+        let state = test_state(unreachable_pool(), config);
+        let app = router("/health").with_state(state.clone());
+
+        let (status, _) = get_body(app.clone(), "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+
+        state.db_ready.store(false, Ordering::Release);
+        let (status, _) = get_body(app, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_times_out_on_a_blocked_ping() {
+        // A server that accepts connections but never answers the handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(30))
+            .connect_lazy(&format!("postgres://postgres@{addr}/blocked"))
+            .unwrap();
+        let config = Config {
+            health_check_timeout_ms: 50,
+            ..test_config()
+        };
+        let app = router("/health").with_state(test_state(pool, config));
+
+        let started = std::time::Instant::now();
+        let (status, body) = get_body(app, "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "timeout");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_readiness_with_deep_health_check() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            deep_health_check: true,
+            ..test_config()
+        };
+        let app = router("/health").with_state(test_state(pool, config));
+
+        let (status, body) = get_body(app, "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("ready"));
+    }
+
+    #[tokio::test]
+    async fn test_readiness_unavailable_while_draining() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let state = test_state(pool, test_config());
+        repository::ping(&state.pool()).await.unwrap();
+        state.draining.store(true, Ordering::Release);
+
+        let (status, body) = get_body(router("/health").with_state(state), "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("draining"));
+    }
+
+    #[tokio::test]
+    async fn test_readiness_ready_after_first_ping() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let state = test_state(pool, test_config());
+        state.db_ready.store(false, Ordering::Release);
+
+        let (status, _) =
+            get_body(router("/health").with_state(state.clone()), "/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        startup::initialize_database(&state).await.unwrap();
+        assert!(state.db_ready.load(Ordering::Acquire));
+
+        let (status, body) = get_body(router("/health").with_state(state), "/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "ready");
+        assert!(!body["server_version"].as_str().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stats_counts_users_created_today() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Today", "today@example.com").await;
+        let app = router("/health").with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, "/health/stats").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["users_created_today"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_router_serves_only_probes_under_prefix() {
+        let state = test_state(unreachable_pool(), test_config());
+        state.db_ready.store(true, Ordering::Release);
+        let app = router("/healthz").with_state(state);
+
+        let (status, body) = get_body(app.clone(), "/healthz/startup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("started"));
+        for uri in ["/health/startup", "/users", "/metrics"] {
+            let (status, _) = get_body(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_startup_probe_before_initialization() {
+        let state = test_state(unreachable_pool(), test_config());
+        state.db_ready.store(false, Ordering::Release);
+
+        let (status, body) = get_body(router("/health").with_state(state), "/health/startup").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("starting"));
+    }
+
+    #[tokio::test]
+    async fn test_startup_probe_after_initialization() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let state = test_state(pool, test_config());
+        state.db_ready.store(false, Ordering::Release);
+        startup::initialize_database(&state).await.unwrap();
+        // Once started, draining does not turn the startup probe back
+        state.draining.store(true, Ordering::Release);
+
+        let (status, body) = get_body(router("/health").with_state(state), "/health/startup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("started"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Bulk user import from newline-delimited JSON
+
+use crate::{
+    auth::{AdminRole, RequireRole},
+    error::AppError,
+    features::{self, USER_IMPORT},
+    models::{NewUser, User},
+    query_budget::RequestBudget,
+    repository,
+    state::AppState,
+};
+use axum::{
+    body::{Body, BodyDataStream},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use std::{convert::Infallible, mem};
+
+/// Longest accepted input line; longer lines are reported and skipped
+///
+/// Bounds the memory held per import regardless of the upload size.
+pub(super) const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Outcome of importing one input line
+#[derive(Debug, Serialize)]
+struct LineResult {
+    /// 1-based line number in the request body
+    line: usize,
+    status: LineStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum LineStatus {
+    Ok,
+    Error,
+}
+
+/// One non-blank input line, or why it could not be read
+enum Input {
+    Line(Vec<u8>),
+    TooLong,
+    Unreadable(axum::Error),
+}
+
+/// `POST /users/import` - create users from an NDJSON body
+///
+/// Each non-blank line is a [`NewUser`] object and is inserted on its own, so
+/// a bad line, or one whose email is already registered, does not abort the
+/// import. The body is consumed as a stream
+/// and the response streams one NDJSON result per line as it is processed,
+/// so uploads of any size are handled in bounded memory. The inserts count
+/// against the request's [query budget](crate::query_budget) although they
+/// run as the response streams. Requires the admin role, and answers `404`
+/// unless the `user_import` feature is enabled.
+pub(super) async fn import_users(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+    body: Body,
+) -> Response {
+    if !features::is_enabled(&state, USER_IMPORT) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let budget = RequestBudget::current();
+    let results = lines(body.into_data_stream()).then(move |(line, input)| {
+        let state = state.clone();
+        budget.clone().scope(async move {
+            let result = match input {
+                Input::Line(raw) => import_line(&state, line, &raw).await,
+                Input::TooLong => failed(line, format!("line exceeds {MAX_LINE_BYTES} bytes")),
+                Input::Unreadable(e) => failed(line, format!("failed to read request body: {e}")),
+            };
+            let mut json = serde_json::to_vec(&result).unwrap_or_default();
+            json.push(b'\n');
+            Ok::<_, Infallible>(json)
+        })
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(results),
+    )
+        .into_response()
+}
+
+/// Split a body stream into numbered non-blank lines
+///
+/// At most [`MAX_LINE_BYTES`] plus one chunk is buffered. The stream ends
+/// after a read error.
+fn lines(body: BodyDataStream) -> impl Stream<Item = (usize, Input)> {
+    struct Splitter {
+        body: BodyDataStream,
+        buf: Vec<u8>,
+        line: usize,
+        skipping: bool,
+        done: bool,
+    }
+
+    let splitter = Splitter {
+        body,
+        buf: Vec::new(),
+        line: 0,
+        skipping: false,
+        done: false,
+    };
+    stream::unfold(splitter, |mut s| async move {
+        loop {
+            if let Some(end) = s.buf.iter().position(|&b| b == b'\n') {
+                let raw: Vec<u8> = s.buf.drain(..=end).collect();
+                s.line += 1;
+                if mem::take(&mut s.skipping) || raw.trim_ascii().is_empty() {
+                    continue;
+                }
+                if raw.len() > MAX_LINE_BYTES {
+                    return Some(((s.line, Input::TooLong), s));
+                }
+                return Some(((s.line, Input::Line(raw)), s));
+            }
+            if s.buf.len() > MAX_LINE_BYTES {
+                s.buf.clear();
+                if !mem::replace(&mut s.skipping, true) {
+                    return Some(((s.line + 1, Input::TooLong), s));
+                }
+                continue;
+            }
+            if s.done {
+                let raw = mem::take(&mut s.buf);
+                if s.skipping || raw.trim_ascii().is_empty() {
+                    return None;
+                }
+                s.line += 1;
+                return Some(((s.line, Input::Line(raw)), s));
+            }
+            match s.body.next().await {
+                Some(Ok(chunk)) => s.buf.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    s.done = true;
+                    s.skipping = true;
+                    return Some(((s.line + 1, Input::Unreadable(e)), s));
+                }
+                None => s.done = true,
+            }
+        }
+    })
+}
+
+fn failed(line: usize, error: String) -> LineResult {
+    LineResult {
+        line,
+        status: LineStatus::Error,
+        id: None,
+        error: Some(error),
+    }
+}
+
+async fn import_line(state: &AppState, line: usize, raw: &[u8]) -> LineResult {
+    let outcome = match serde_json::from_slice::<NewUser>(raw) {
+        Ok(new_user) => create(state, &new_user).await.map_err(|e| match e {
+            AppError::Validation(msg) | AppError::Conflict(msg) => msg,
+            AppError::QueryBudgetExceeded { budget } => {
+                format!("import exceeded MAX_QUERIES_PER_REQUEST of {budget}")
+            }
+            other => {
+                tracing::error!(line, error = %other, "Import failed to create user");
+                "failed to create user".to_string()
+            }
+        }),
+        Err(e) => Err(format!("invalid JSON: {e}")),
+    };
+
+    match outcome {
+        Ok(user) => LineResult {
+            line,
+            status: LineStatus::Ok,
+            id: Some(user.id),
+            error: None,
+        },
+        Err(error) => failed(line, error),
+    }
+}
+
+async fn create(state: &AppState, new_user: &NewUser) -> Result<User, AppError> {
+    new_user.validate()?;
+    let mut conn = state.pool().acquire().await?;
+    super::users::ensure_email_free(&mut conn, &new_user.email).await?;
+    repository::create_user(&mut *conn, new_user).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_LINE_BYTES;
+    use crate::{
+        config::Config,
+        features::{self, USER_IMPORT},
+        query_budget,
+        routes::build_routes,
+        test_utils::{admin, capture_logs, test_config, test_pool, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::{to_bytes, Body, Bytes},
+        http::{header, Request, StatusCode},
+        middleware, Router,
+    };
+    use futures_util::{stream, StreamExt};
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use std::{convert::Infallible, fmt::Write};
+    use tower::ServiceExt;
+
+    fn import_app(pool: PgPool) -> Router {
+        let config = Config {
+            features: features::parse(USER_IMPORT),
+            ..test_config()
+        };
+        build_routes().with_state(test_state(pool, config))
+    }
+
+    async fn import(app: Router, body: Body) -> Vec<Value> {
+        let response = app
+            .oneshot(
+                Request::post("/users/import")
+                    .extension(admin())
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_each_line() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            features: features::parse(USER_IMPORT),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(pool, config));
+        let body = concat!(
+            r#"{"name": "Ann", "email": "ann@example.com"}"#,
+            "\n",
+            r#"{"name": "Ben", "email": "not-an-email"}"#,
+            "\n",
+            r#"{"name": "Cid", "email": "cid@example.com"}"#,
+            "\n",
+        );
+
+        let response = app
+            .oneshot(
+                Request::post("/users/import")
+                    .extension(admin())
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let results: Vec<Value> = String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|r| r["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["ok", "error", "ok"]);
+        assert_eq!(results[1]["line"], 2);
+        assert!(results[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("invalid email"));
+        assert!(results[0]["id"].is_i64());
+        assert!(results[2]["id"].is_i64());
+    }
+
+    #[tokio::test]
+    async fn test_import_streams_large_body() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        // Well past the 2 MiB buffered body limit, delivered in chunks that
+        // split lines at arbitrary points
+        let padding = format!("{}\n", " ".repeat(1023));
+        let mut upload = String::new();
+        for i in 0..200 {
+            writeln!(
+                upload,
+                r#"{{"name": "User {i}", "email": "user{i}@example.com"}}"#
+            )
+            .unwrap();
+            upload.push_str(&padding.repeat(30));
+        }
+        assert!(upload.len() > 4 * 1024 * 1024);
+        let chunks: Vec<Bytes> = upload
+            .into_bytes()
+            .chunks(10_007)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        let body = Body::from_stream(stream::iter(chunks).map(Ok::<_, Infallible>));
+
+        let results = import(import_app(pool.clone()), body).await;
+
+        assert_eq!(results.len(), 200);
+        assert!(results.iter().all(|r| r["status"] == "ok"));
+        assert_eq!(results[199]["line"], 199 * 31 + 1);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 200);
+    }
+
+    #[tokio::test]
+    async fn test_import_skips_overlong_line() {
+        let body = format!("{}\n\n{}", "x".repeat(MAX_LINE_BYTES * 3), "not json");
+
+        let results = import(import_app(unreachable_pool()), Body::from(body)).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["line"], 1);
+        assert!(results[0]["error"].as_str().unwrap().contains("exceeds"));
+        assert_eq!(results[1]["line"], 3);
+        assert!(results[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("invalid JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_registered_email_as_conflict() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let (logs, _guard) = capture_logs();
+        let body = concat!(
+            r#"{"name": "Ann", "email": "ann@example.com"}"#,
+            "\n",
+            r#"{"name": "Ann Again", "email": "ANN@example.com"}"#,
+            "\n",
+        );
+
+        let results = import(import_app(pool), Body::from(body)).await;
+
+        assert_eq!(results[0]["status"], "ok");
+        assert_eq!(results[1]["status"], "error");
+        assert_eq!(results[1]["error"], "email is already registered");
+        let output = logs.contents();
+        assert!(!output.contains("Import failed to create user"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_import_inserts_count_against_query_budget() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            features: features::parse(USER_IMPORT),
+            // Each line checks its email, then inserts
+            max_queries_per_request: 4,
+            ..test_config()
+        };
+        let state = test_state(pool.clone(), config);
+        let app = build_routes()
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                query_budget::count_queries,
+            ))
+            .with_state(state);
+        let body = (0..3).fold(String::new(), |mut body, i| {
+            writeln!(
+                body,
+                r#"{{"name": "User {i}", "email": "user{i}@example.com"}}"#
+            )
+            .unwrap();
+            body
+        });
+
+        let results = import(app, Body::from(body)).await;
+
+        let statuses: Vec<_> = results
+            .iter()
+            .map(|r| r["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["ok", "ok", "error"]);
+        assert!(results[2]["error"]
+            .as_str()
+            .unwrap()
+            .contains("MAX_QUERIES_PER_REQUEST"));
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_import_hidden_when_feature_disabled() {
+        let app = build_routes().with_state(test_state(unreachable_pool(), test_config()));
+
+        let response = app
+            .oneshot(
+                Request::post("/users/import")
+                    .extension(admin())
+                    .body(Body::from(r#"{"name": "Ann", "email": "ann@example.com"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_requires_admin_role() {
+        let config = Config {
+            features: features::parse(USER_IMPORT),
+            api_key: Some("secret".to_string()),
+            api_key_tiers: vec![("client".to_string(), crate::config::ApiTier::Basic)],
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        for (key, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("wrong"), StatusCode::UNAUTHORIZED),
+            (Some("client"), StatusCode::FORBIDDEN),
+        ] {
+            let mut request = Request::post("/users/import");
+            if let Some(key) = key {
+                request = request.header(crate::auth::API_KEY_HEADER, key);
+            }
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::from("{}\n")).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{key:?}");
+        }
+    }
+}
@@ -2,9 +2,419 @@
 //!
 //! This module contains all HTTP route handlers and endpoint definitions.
 
-use axum::Router;
+mod csv;
+pub mod health;
+mod import;
+mod schema;
+pub mod users;
 
-/// Build the application router with all routes
-pub fn build_routes() -> Router {
-    Router::new()
+use crate::{
+    auth::{AdminRole, RequireRole},
+    cache_control,
+    config::AppEnv,
+    error::AppError,
+    models::{DuplicateEmailGroup, User},
+    repository,
+    response::JsonResponse,
+    state::AppState,
+    transaction,
+    webhook::WebhookEvent,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+
+/// Build the application router with all routes but the health probes
+///
+/// Each resource with its own submodule contributes its `router()`; the
+/// probes are added by [`crate::build_app`] through [`health::router`], as
+/// they depend on `HEALTH_PATH`. With the `profiling` cargo feature,
+/// `GET /debug/profile` is added too.
+pub fn build_routes() -> Router<AppState> {
+    let router = Router::new();
+    #[cfg(feature = "profiling")]
+    let router = router.route("/debug/profile", get(crate::profiling::profile));
+    let router = router
+        .route("/metrics", get(metrics))
+        .route("/schema/user", get(schema::user))
+        .route("/schema/new-user", get(schema::new_user))
+        .route("/admin/maintenance/analyze", post(analyze))
+        .route("/admin/diagnostics/duplicate-emails", get(duplicate_emails))
+        .route("/admin/users/:id/touch", post(touch_user))
+        .route("/admin/db/reconnect", post(reconnect_db))
+        .route("/admin/purge", post(purge));
+    scoped(router).merge(users::router())
+}
+
+/// Wrap the routes of `router` in the per-request transaction scope and the
+/// `no-store` policy for writes
+///
+/// Applied by each resource router to its own routes, so it serves them the
+/// same alone as merged into [`build_routes`].
+fn scoped(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route_layer(middleware::from_fn(transaction::transaction_scope))
+        .route_layer(middleware::from_fn(cache_control::no_store_for_writes))
+}
+
+/// `GET /metrics` - Prometheus scrape endpoint
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        state.metrics.render(),
+    )
+}
+/// `POST /admin/maintenance/analyze` - refresh planner statistics
+async fn analyze(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    repository::analyze_users(&state.pool()).await?;
+    tracing::info!("Refreshed planner statistics for users");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /admin/diagnostics/duplicate-emails` - users whose emails differ
+/// only in case or surrounding whitespace
+async fn duplicate_emails(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DuplicateEmailGroup>>, AppError> {
+    Ok(Json(
+        repository::find_duplicate_emails(&state.pool()).await?,
+    ))
+}
+
+/// `POST /admin/users/:id/touch` - reset a user's `updated_at` to now
+///
+/// Returns the refreshed user, for cache testing and data fixes.
+async fn touch_user(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<(Extension<WebhookEvent>, JsonResponse<User>), AppError> {
+    let user = repository::touch_updated_at(&state.pool(), id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user {id}")))?;
+    if let Some(cache) = &state.user_cache {
+        cache.invalidate(id);
+    }
+    tracing::info!(user_id = id, "Touched user updated_at");
+    Ok((
+        Extension(WebhookEvent::updated(&user)),
+        JsonResponse::new(user, &state.config),
+    ))
+}
+
+/// `POST /admin/db/reconnect` - replace the database pool with a fresh one
+///
+/// Recovers from a pool stuck in a bad state, such as after a failover,
+/// without restarting. Requests already holding the old pool finish on it
+/// before it is closed.
+async fn reconnect_db(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    let pool = repository::reconnect(&state.config).await?;
+    let old = state.replace_pool(pool);
+    tokio::spawn(async move { old.close().await });
+    tracing::warn!("Replaced the database pool");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /admin/purge` - delete every user, for test and staging resets
+///
+/// Answers `404` unless `ALLOW_PURGE` is set, and `403` in production even
+/// when it is.
+async fn purge(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    if !state.config.allow_purge {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    if state.config.app_env == AppEnv::Prod {
+        tracing::warn!("Refused to purge users in production");
+        return Err(AppError::Forbidden);
+    }
+
+    repository::purge_all(&state.pool()).await?;
+    if let Some(cache) = &state.user_cache {
+        cache.clear();
+    }
+    tracing::warn!("Purged all users");
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_utils::{insert_user, test_config, test_pool, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    fn analyze_request(api_key: Option<&str>) -> Request<Body> {
+        let mut request = Request::post("/admin/maintenance/analyze");
+        if let Some(key) = api_key {
+            request = request.header(crate::auth::API_KEY_HEADER, key);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_analyze_requires_api_key() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        for key in [None, Some("wrong")] {
+            let response = app.clone().oneshot(analyze_request(key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analyze_disabled_without_configured_key() {
+        let app = build_routes().with_state(test_state(unreachable_pool(), test_config()));
+
+        let response = app.oneshot(analyze_request(Some(""))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_with_api_key() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(pool, config));
+
+        let response = app.oneshot(analyze_request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_emails_reports_groups() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let first = insert_user(&pool, "First", "Dup@example.com").await;
+        let second = insert_user(&pool, "Second", "dup@example.com ").await;
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(pool, config));
+
+        let request = Request::get("/admin/diagnostics/duplicate-emails")
+            .header(crate::auth::API_KEY_HEADER, "secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let groups: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            groups,
+            json!([{
+                "normalized_email": "dup@example.com",
+                "count": 2,
+                "user_ids": [first.id, second.id],
+            }])
+        );
+    }
+
+    fn touch_request(id: i32, api_key: Option<&str>) -> Request<Body> {
+        let mut request = Request::post(format!("/admin/users/{id}/touch"));
+        if let Some(key) = api_key {
+            request = request.header(crate::auth::API_KEY_HEADER, key);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_touch_user_refreshes_updated_at() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Touch", "touch@example.com").await;
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(pool, config));
+
+        let response = app
+            .oneshot(touch_request(user.id, Some("secret")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let touched: User = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(touched.id, user.id);
+        assert!(touched.updated_at >= user.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_touch_user_not_found() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(pool, config));
+
+        let response = app
+            .oneshot(touch_request(999, Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_touch_user_requires_api_key() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        for key in [None, Some("wrong")] {
+            let response = app.clone().oneshot(touch_request(1, key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    fn reconnect_request(key: Option<&str>) -> Request<Body> {
+        let mut request = Request::post("/admin/db/reconnect");
+        if let Some(key) = key {
+            request = request.header(crate::auth::API_KEY_HEADER, key);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_swaps_in_a_working_pool() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            database_url: std::env::var("TEST_DATABASE_URL").unwrap(),
+            ..test_config()
+        };
+        let state = test_state(pool.clone(), config);
+        let app = build_routes().with_state(state.clone());
+
+        let response = app
+            .oneshot(reconnect_request(Some("secret")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let new_pool = state.pool();
+        let one: i32 = sqlx::query_scalar("SELECT 1")
+            .fetch_one(&new_pool)
+            .await
+            .unwrap();
+        assert_eq!(one, 1);
+        // The replaced pool is closed once its requests finish
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !pool.is_closed() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_requires_api_key() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        for key in [None, Some("wrong")] {
+            let response = app.clone().oneshot(reconnect_request(key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    fn purge_config(app_env: AppEnv, allow_purge: bool) -> Config {
+        Config {
+            api_key: Some("secret".to_string()),
+            app_env,
+            allow_purge,
+            ..test_config()
+        }
+    }
+
+    fn purge_request() -> Request<Body> {
+        Request::post("/admin/purge")
+            .header(crate::auth::API_KEY_HEADER, "secret")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_purge_deletes_all_users_when_allowed() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Ann", "ann@example.com").await;
+        let config = purge_config(AppEnv::Staging, true);
+        let app = build_routes().with_state(test_state(pool.clone(), config));
+
+        let response = app.oneshot(purge_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_purge_forbidden_in_prod() {
+        let config = purge_config(AppEnv::Prod, true);
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        let response = app.oneshot(purge_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_purge_hidden_unless_allowed() {
+        let config = purge_config(AppEnv::Dev, false);
+        let app = build_routes().with_state(test_state(unreachable_pool(), config));
+
+        let response = app.oneshot(purge_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,70 @@
+//! JSON Schema documents for the request and response bodies of `/users`
+
+use crate::models::{NewUser, User};
+use axum::Json;
+use schemars::{schema::RootSchema, schema_for};
+
+/// `GET /schema/user` - JSON Schema of a user record as returned
+pub(super) async fn user() -> Json<RootSchema> {
+    Json(schema_for!(User))
+}
+
+/// `GET /schema/new-user` - JSON Schema of the body `POST /users` accepts
+pub(super) async fn new_user() -> Json<RootSchema> {
+    Json(schema_for!(NewUser))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<_> = match value {
+            Value::Object(map) => map.keys().map(String::as_str).collect(),
+            Value::Array(items) => items.iter().filter_map(Value::as_str).collect(),
+            _ => panic!("{value}"),
+        };
+        keys.sort_unstable();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_user_schema() {
+        let Json(schema) = user().await;
+        let schema = serde_json::to_value(schema).unwrap();
+
+        assert_eq!(
+            keys(&schema["properties"]),
+            [
+                "created_at",
+                "deleted_at",
+                "email",
+                "first_name",
+                "id",
+                "last_name",
+                "name",
+                "updated_at"
+            ]
+        );
+        assert_eq!(
+            keys(&schema["required"]),
+            ["created_at", "email", "id", "name", "updated_at"]
+        );
+        assert_eq!(schema["properties"]["email"]["format"], "email");
+    }
+
+    #[tokio::test]
+    async fn test_new_user_schema() {
+        let Json(schema) = new_user().await;
+        let schema = serde_json::to_value(schema).unwrap();
+
+        assert_eq!(
+            keys(&schema["properties"]),
+            ["email", "first_name", "last_name", "name"]
+        );
+        assert_eq!(keys(&schema["required"]), ["email"]);
+        assert_eq!(schema["properties"]["email"]["format"], "email");
+        assert_eq!(schema["properties"]["email"]["maxLength"], 255);
+    }
+}
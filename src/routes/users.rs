@@ -0,0 +1,1370 @@
+//! `/users` endpoints
+
+use super::{csv, import};
+use crate::{
+    auth::{self, AdminRole, RequireRole},
+    cache_control,
+    config::{Config, CountMode},
+    deadline::Deadline,
+    error::AppError,
+    json_body::JsonBody,
+    models::{
+        decode_cursor, AuditEntry, CursorPage, Email, EmailDomain, FilteredPage, NewUser, Page,
+        PageParams, User, UserFilter, UserView,
+    },
+    repository::{self, StoredResponse},
+    response::{self, JsonResponse},
+    state::AppState,
+    transaction::Tx,
+    webhook::WebhookEvent,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgConnection;
+
+/// Build the router of the `/users` resource
+pub fn router() -> Router<AppState> {
+    super::scoped(
+        Router::new()
+            .route("/users", get(list_users).post(create_user))
+            .route("/users/import", post(import::import_users))
+            .route("/users/active", get(count_active_users))
+            .route("/users/page", get(get_user_page))
+            .route("/users/search", get(search_users))
+            .route("/users/by-email", get(get_user_by_email))
+            .route("/users/domains", get(list_email_domains))
+            .route("/users/:id", get(get_user).delete(delete_user))
+            .route("/users/:id/audit", get(get_user_audit)),
+    )
+}
+
+/// `GET /users` - list users, optionally filtered, sorted and paginated
+///
+/// `view=summary` returns records without timestamps. Soft-deleted users are
+/// left out unless an admin asks for them with `include_deleted=true`. Keys
+/// of a premium tier may request larger pages.
+///
+/// With `Accept: text/csv` every matching user is streamed as CSV instead;
+/// paging and `view` do not apply there.
+async fn list_users(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    admin: Result<RequireRole<AdminRole>, AppError>,
+    headers: HeaderMap,
+    Query(mut filter): Query<UserFilter>,
+) -> Result<Response, AppError> {
+    if filter.include_deleted {
+        admin?;
+    }
+    let vary = [(header::VARY, HeaderValue::from_static("accept"))];
+    if csv::wants_csv(&headers) {
+        let users = repository::stream_search(&state.pool(), &filter)?;
+        return Ok((vary, csv::users_response(users)).into_response());
+    }
+    filter.max_limit = auth::tier(&headers, &state.config).max_limit();
+    check_offset(&filter, state.config.max_offset)?;
+    let total = repository::with_deadline(deadline, count_matching_users(&state, &filter)).await?;
+    let (limit, offset) = repository::page_bounds(&filter);
+    let filters = filter.applied();
+    let response = match filter.view {
+        UserView::Full => {
+            let items =
+                repository::with_deadline(deadline, repository::find_users(&state.pool(), &filter))
+                    .await?;
+            let page = Page::new(items, total, limit, offset);
+            JsonResponse::new(FilteredPage { page, filters }, &state.config).into_response()
+        }
+        UserView::Summary => {
+            let pool = state.pool();
+            let query = repository::find_user_summaries(&pool, &filter);
+            let items = repository::with_deadline(deadline, query).await?;
+            let page = Page::new(items, total, limit, offset);
+            JsonResponse::new(FilteredPage { page, filters }, &state.config).into_response()
+        }
+    };
+    Ok((cache_control::for_reads(&state.config), vary, response).into_response())
+}
+
+/// Reject offsets past `max`, which make the database skip that many rows
+fn check_offset(filter: &UserFilter, max: u64) -> Result<(), AppError> {
+    let (_, offset) = repository::page_bounds(filter);
+    if offset.unsigned_abs() > max {
+        return Err(AppError::BadRequest(format!(
+            "offset must be at most {max}; narrow the listing with created_after or \
+             created_before and page from there instead"
+        )));
+    }
+    Ok(())
+}
+
+/// Total for a listing; the unfiltered total is served from a short-lived cache
+///
+/// With `COUNT_MODE=estimate` the unfiltered total is the planner's estimate;
+/// filtered totals are always counted exactly.
+async fn count_matching_users(state: &AppState, filter: &UserFilter) -> Result<i64, AppError> {
+    if filter.has_conditions() {
+        return repository::count_users(&state.pool(), filter).await;
+    }
+    state
+        .user_count
+        .get_or_load(|| async {
+            match state.config.count_mode {
+                CountMode::Exact => repository::count_users(&state.pool(), filter).await,
+                CountMode::Estimate => repository::estimate_user_count(&state.pool()).await,
+            }
+        })
+        .await
+}
+
+/// Request header making `POST /users` safe to retry
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Response header marking a replayed idempotent response
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// `POST /users` - create a user
+///
+/// With an `Idempotency-Key` header the response is recorded in the same
+/// transaction as the user, and a retry with that key replays it instead of
+/// creating the user again. An email already registered in any case is
+/// rejected with `409`. A new user is announced to the
+/// [webhook](crate::webhook); replays are not. Requires the admin role.
+async fn create_user(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    mut tx: Tx,
+    JsonBody(new_user): JsonBody<NewUser>,
+) -> Result<Response, AppError> {
+    new_user.validate()?;
+    let key = idempotency_key(&headers)?;
+    let conn = tx.conn().await?;
+    let Some(key) = key else {
+        ensure_email_free(&mut *conn, &new_user.email).await?;
+        let user = repository::create_user(conn, &new_user).await?;
+        let event = WebhookEvent::created(&user);
+        return Ok((
+            StatusCode::CREATED,
+            Extension(event),
+            JsonResponse::new(user, &state.config),
+        )
+            .into_response());
+    };
+
+    if let Some(stored) = repository::lock_idempotency_key(conn, &key).await? {
+        return Ok(replay(stored, true, &state.config));
+    }
+    ensure_email_free(&mut *conn, &new_user.email).await?;
+    let user = repository::create_user(&mut *conn, &new_user).await?;
+    let event = WebhookEvent::created(&user);
+    let body = JsonResponse::new(user, &state.config)
+        .body()
+        .map_err(|e| AppError::Internal(format!("Failed to serialize response: {e}")))?;
+    let stored = StoredResponse {
+        status: i16::try_from(StatusCode::CREATED.as_u16()).unwrap_or(i16::MAX),
+        body,
+    };
+    repository::store_idempotent_response(conn, &key, &stored).await?;
+    Ok((Extension(event), replay(stored, false, &state.config)).into_response())
+}
+
+/// Reject `email` with `409` if a user already has it, in any case
+///
+/// Answers before the insert is attempted; creates racing for the same
+/// address are still settled by the unique `lower(email)` index.
+pub(super) async fn ensure_email_free(
+    conn: &mut PgConnection,
+    email: &Email,
+) -> Result<(), AppError> {
+    if repository::email_exists_case_insensitive(conn, email.as_str()).await? {
+        return Err(AppError::Conflict(
+            "email is already registered".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The `Idempotency-Key` header, if present
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= repository::MAX_IDEMPOTENCY_KEY_LEN => {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(AppError::Validation(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters",
+            repository::MAX_IDEMPOTENCY_KEY_LEN
+        ))),
+    }
+}
+
+/// Send a recorded JSON response, marking it if it is a replay
+fn replay(stored: StoredResponse, replayed: bool, config: &Config) -> Response {
+    let status = u16::try_from(stored.status)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut response = (
+        status,
+        [(header::CONTENT_TYPE, response::json_content_type(config))],
+        stored.body,
+    )
+        .into_response();
+    if replayed {
+        response
+            .headers_mut()
+            .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+/// Query parameters of `GET /users/active`
+#[derive(Debug, Deserialize)]
+struct ActiveQuery {
+    /// Only logins strictly after this instant count
+    since: DateTime<Utc>,
+}
+
+/// `GET /users/active?since=` - number of users who logged in after `since`
+async fn count_active_users(
+    State(state): State<AppState>,
+    Query(query): Query<ActiveQuery>,
+) -> Result<Json<Value>, AppError> {
+    let active = repository::count_active_since(&state.pool(), query.since).await?;
+    Ok(Json(json!({ "since": query.since, "active": active })))
+}
+
+/// Query of `GET /users/page`
+#[derive(Debug, Deserialize)]
+struct CursorQuery {
+    /// Token from the previous page's `next`; the first page when absent
+    after: Option<String>,
+    /// Maximum number of users to return
+    limit: Option<i64>,
+}
+
+/// `GET /users/page?after=` - users in id order, paged by opaque cursor
+async fn get_user_page(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Query(query): Query<CursorQuery>,
+) -> Result<JsonResponse<CursorPage<User>>, AppError> {
+    let cursor = query
+        .after
+        .as_deref()
+        .map(decode_cursor)
+        .transpose()
+        .map_err(|e| AppError::BadRequest(format!("after: {e}")))?;
+    let page = repository::with_deadline(
+        deadline,
+        repository::get_user_page(&state.pool(), cursor, query.limit),
+    )
+    .await?;
+    Ok(JsonResponse::new(page, &state.config))
+}
+
+/// Query of `GET /users/search`
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    /// Text to look for in names
+    q: String,
+    /// Maximum number of users to return
+    limit: Option<i64>,
+    /// Number of matches to skip
+    offset: Option<i64>,
+}
+
+/// `GET /users/search?q=` - users whose name contains `q`, best matches first
+///
+/// Queries shorter than `MIN_SEARCH_LEN` match nearly every user and are
+/// refused with `400`.
+async fn search_users(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Query(query): Query<SearchQuery>,
+) -> Result<JsonResponse<Vec<User>>, AppError> {
+    let q = query.q.trim();
+    let min = state.config.min_search_len;
+    if q.chars().count() < min {
+        return Err(AppError::BadRequest(format!(
+            "q must be at least {min} characters"
+        )));
+    }
+    let page = PageParams {
+        limit: query.limit,
+        offset: query.offset,
+    };
+    let pool = state.pool();
+    let users =
+        repository::with_deadline(deadline, repository::search_users(&pool, q, &page)).await?;
+    Ok(JsonResponse::new(users, &state.config))
+}
+
+/// Query of `GET /users/by-email`
+#[derive(Debug, Deserialize)]
+struct EmailQuery {
+    /// Address to look up, in any case
+    email: String,
+}
+
+/// `GET /users/by-email?email=` - fetch a single user by email address
+///
+/// The address is normalized as on create, so any casing finds the user.
+async fn get_user_by_email(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Query(query): Query<EmailQuery>,
+) -> Response {
+    let user = async {
+        let email = Email::parse(query.email).map_err(|e| AppError::BadRequest(e.to_string()))?;
+        repository::with_deadline(
+            deadline,
+            repository::get_user_by_email(&state.pool(), &email),
+        )
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user with email {email}")))
+    }
+    .await;
+    user_response(user, &state.config)
+}
+
+/// `GET /users/:id` - fetch a single user
+///
+/// With `USER_CACHE_CAPACITY` the user is served from the read cache, which
+/// also coalesces concurrent misses. With `SINGLEFLIGHT_READS` instead (the
+/// two cannot be combined), concurrent requests for the same id share the
+/// query of whichever arrived first.
+async fn get_user(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Path(id): Path<i32>,
+) -> Response {
+    let pool = state.pool();
+    let load = || repository::with_deadline(deadline, repository::get_user_by_id(&pool, id));
+    let user = match (&state.user_cache, &state.user_reads) {
+        (Some(cache), _) => repository::with_deadline(deadline, cache.get_user_by_id(id)).await,
+        (None, Some(reads)) => reads.run(id, load).await,
+        (None, None) => load().await,
+    }
+    .and_then(|user| user.ok_or_else(|| AppError::NotFound(format!("user {id}"))));
+    user_response(user, &state.config)
+}
+
+/// Respond with a single user, or in the
+/// [`ApiResponse`](crate::models::ApiResponse) envelope when
+/// `UNIFIED_RESPONSES` is set
+fn user_response(user: Result<User, AppError>, config: &Config) -> Response {
+    let cache = user.is_ok().then(|| cache_control::for_reads(config));
+    if config.unified_responses {
+        return (cache, response::api_response(user.into(), config)).into_response();
+    }
+    match user {
+        Ok(user) => (cache, JsonResponse::new(user, config)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// `DELETE /users/:id` - soft-delete a user, reporting the rows referring to it
+///
+/// Related rows are kept and only counted, as `{"deleted": true, "related":
+/// {"audit": n}}`. Runs in the request's transaction, like [`create_user`],
+/// and evicts the cached user only once that has committed.
+async fn delete_user(
+    _: RequireRole<AdminRole>,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    mut tx: Tx,
+) -> Result<(Extension<WebhookEvent>, JsonResponse<Value>), AppError> {
+    let related = repository::delete_user(tx.conn().await?, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("user {id}")))?;
+    if let Some(cache) = state.user_cache.clone() {
+        tx.after_commit(move || cache.invalidate(id));
+    }
+    tracing::info!(user_id = id, audit = related.audit, "Deleted user");
+    Ok((
+        Extension(WebhookEvent::deleted(id)),
+        JsonResponse::new(
+            json!({ "deleted": true, "related": related }),
+            &state.config,
+        ),
+    ))
+}
+
+/// `GET /users/:id/audit` - a user's change history, oldest first
+///
+/// History outlives the user, so only an id with no entries at all is `404`.
+async fn get_user_audit(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Path(id): Path<i32>,
+    Query(params): Query<PageParams>,
+) -> Result<JsonResponse<Page<AuditEntry>>, AppError> {
+    let total =
+        repository::with_deadline(deadline, repository::count_audit_entries(&state.pool(), id))
+            .await?;
+    if total == 0 {
+        return Err(AppError::NotFound(format!("audit log for user {id}")));
+    }
+    let pool = state.pool();
+    let query = repository::find_audit_entries(&pool, id, &params);
+    let items = repository::with_deadline(deadline, query).await?;
+    let (limit, offset) = repository::clamp_page(params.limit, params.offset);
+
+    Ok(JsonResponse::new(
+        Page::new(items, total, limit, offset),
+        &state.config,
+    ))
+}
+
+/// `GET /users/domains` - distinct email domains with their user counts,
+/// most users first
+async fn list_email_domains(
+    State(state): State<AppState>,
+    deadline: Deadline,
+    Query(params): Query<PageParams>,
+) -> Result<JsonResponse<Page<EmailDomain>>, AppError> {
+    let (limit, offset) = repository::clamp_page(params.limit, params.offset);
+    let pool = state.pool();
+    let total = repository::with_deadline(deadline, repository::count_email_domains(&pool)).await?;
+    let items = repository::with_deadline(
+        deadline,
+        repository::list_email_domains(&pool, limit, offset),
+    )
+    .await?;
+
+    Ok(JsonResponse::new(
+        Page::new(items, total, limit, offset),
+        &state.config,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ApiTier,
+        test_utils::{
+            admin, get_body, insert_user, test_config, test_pool, test_state, unreachable_pool,
+        },
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::Request,
+        middleware::{self, Next},
+    };
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_get_user_pretty_json() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Pretty", "pretty@example.com").await;
+        let config = Config {
+            pretty_json: true,
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+
+        let (status, body) = get_body(app, &format!("/users/{}", user.id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains('\n'));
+        assert!(body.contains("  \"name\": \"Pretty\""));
+    }
+
+    #[tokio::test]
+    async fn test_get_user_compact_json_by_default() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Compact", "compact@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, &format!("/users/{}", user.id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.contains('\n'));
+    }
+
+    async fn list_with_accept(app: Router, accept: Option<&str>) -> (String, String) {
+        let mut request = Request::get("/users?name_contains=Csv");
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_list_users_negotiates_csv_or_json() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let first = insert_user(&pool, "Csv One", "csv1@example.com").await;
+        let second = insert_user(&pool, "Csv, Two", "csv2@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (content_type, body) = list_with_accept(app.clone(), Some("text/csv")).await;
+        assert_eq!(content_type, "text/csv; charset=utf-8");
+        let lines: Vec<&str> = body.split_terminator("\r\n").collect();
+        assert_eq!(lines[0], "id,name,email,created_at,updated_at");
+        assert_eq!(lines.len(), 3, "{body}");
+        assert!(lines[1].starts_with(&format!("{},Csv One,csv1@example.com,", first.id)));
+        assert!(lines[2].starts_with(&format!("{},\"Csv, Two\",csv2@example.com,", second.id)));
+
+        for accept in [
+            None,
+            Some("application/json"),
+            Some("*/*"),
+            Some("text/html"),
+        ] {
+            let (content_type, body) = list_with_accept(app.clone(), accept).await;
+            assert!(content_type.starts_with("application/json"), "{accept:?}");
+            let body: Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["total"], 2, "{accept:?}");
+            assert_eq!(body["items"].as_array().unwrap().len(), 2, "{accept:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_serves_only_user_routes() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+
+        let (status, _) = get_body(app.clone(), "/users/search?q=a").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        for uri in ["/metrics", "/schema/user", "/health/ready"] {
+            let (status, _) = get_body(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_rejects_short_query() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+
+        for uri in [
+            "/users/search?q=a",
+            "/users/search?q=%20a%20",
+            "/users/search?q=",
+        ] {
+            let (status, body) = get_body(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert!(body.contains("q must be at least 2 characters"), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_with_long_enough_query() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Alice", "alice@example.com").await;
+        insert_user(&pool, "Bob", "bob@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, "/users/search?q=al").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let users: Vec<User> = serde_json::from_str(&body).unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "Alice");
+    }
+
+    #[tokio::test]
+    async fn test_list_users_with_filter() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Zed", "zed@example.com").await;
+        insert_user(&pool, "Amy", "amy@example.com").await;
+        insert_user(&pool, "Other", "other@elsewhere.test").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, "/users?email_domain=example.com&sort=name").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let users: Vec<User> = serde_json::from_value(body["items"].clone()).unwrap();
+        let names: Vec<_> = users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["Amy", "Zed"]);
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["limit"], 20);
+        assert_eq!(body["offset"], 0);
+        assert_eq!(
+            body["filters"],
+            json!({ "email_domain": "example.com", "sort": "name" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_users_echoes_only_given_filters() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(
+            app.clone(),
+            "/users?name_contains=am&created_after=2024-01-01T00:00:00Z&sort=-name&limit=5",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            body["filters"],
+            json!({
+                "name_contains": "am",
+                "created_after": "2024-01-01T00:00:00Z",
+                "sort": "-name",
+            })
+        );
+
+        let (_, body) = get_body(app, "/users").await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["filters"], json!({ "sort": "id" }));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_rejects_offset_over_max() {
+        let config = Config {
+            max_offset: 10,
+            ..test_config()
+        };
+        let app = router().with_state(test_state(unreachable_pool(), config.clone()));
+
+        let (status, body) = get_body(app, "/users?offset=11").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("offset must be at most 10"), "{body}");
+
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool, config));
+        let (status, body) = get_body(app, "/users?offset=10").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["offset"], 10);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_reuses_cached_total() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Amy", "amy@example.com").await;
+        let app = router().with_state(test_state(pool.clone(), test_config()));
+
+        let (_, body) = get_body(app.clone(), "/users").await;
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 1);
+
+        // Within the TTL the unfiltered total is not recounted
+        insert_user(&pool, "Zed", "zed@example.com").await;
+        let (_, body) = get_body(app.clone(), "/users").await;
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 1);
+
+        // Filtered totals are always counted
+        let (_, body) = get_body(app, "/users?email_domain=example.com").await;
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_total_by_count_mode() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Amy", "amy@example.com").await;
+        insert_user(&pool, "Bob", "bob@example.com").await;
+        repository::analyze_users(&pool).await.unwrap();
+        // Not yet reflected in the planner's statistics
+        insert_user(&pool, "Zed", "zed@example.com").await;
+
+        let exact = router().with_state(test_state(pool.clone(), test_config()));
+        let (_, body) = get_body(exact, "/users").await;
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 3);
+
+        let config = Config {
+            count_mode: CountMode::Estimate,
+            ..test_config()
+        };
+        let estimate = router().with_state(test_state(pool, config));
+        let (status, body) = get_body(estimate.clone(), "/users").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 2);
+
+        let (_, body) = get_body(estimate, "/users?email_domain=example.com").await;
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["total"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_includes_deleted_only_for_admins() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Kept", "kept@example.com").await;
+        let gone = insert_user(&pool, "Gone", "gone@example.com").await;
+        repository::soft_delete_user(&pool, gone.id).await.unwrap();
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            api_key_tiers: vec![("client".to_string(), crate::config::ApiTier::Premium)],
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+        let list = |uri: &str, key: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(key) = key {
+                request = request.header(crate::auth::API_KEY_HEADER, key);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        for key in [None, Some("secret")] {
+            let response = list("/users", key).await.unwrap();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(body["total"], 1);
+            assert!(body["items"][0].get("deleted_at").is_none());
+        }
+
+        for key in [None, Some("wrong")] {
+            let response = list("/users?include_deleted=true", key).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = list("/users?include_deleted=true", Some("client"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let request = Request::get("/users?include_deleted=true")
+            .extension(crate::auth::Principal {
+                role: crate::auth::Role::Admin,
+            })
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = list("/users?include_deleted=true", Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["items"][1]["id"], gone.id);
+        assert!(body["items"][1]["deleted_at"].is_string());
+        assert!(body["items"][0].get("deleted_at").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_users_page_cap_follows_key_tier() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            api_key_tiers: vec![
+                ("gold".to_string(), ApiTier::Premium),
+                ("free".to_string(), ApiTier::Basic),
+            ],
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+        let limit_for = |key: Option<&str>| {
+            let mut request = Request::get("/users?limit=5000");
+            if let Some(key) = key {
+                request = request.header(crate::auth::API_KEY_HEADER, key);
+            }
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<Value>(&bytes).unwrap()["limit"].clone()
+            }
+        };
+
+        assert_eq!(limit_for(Some("gold")).await, 1000);
+        assert_eq!(limit_for(Some("free")).await, 100);
+        assert_eq!(limit_for(Some("unknown")).await, 100);
+        assert_eq!(limit_for(None).await, 100);
+    }
+
+    #[tokio::test]
+    async fn test_list_users_summary_view() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Amy", "amy@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, "/users?view=summary").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        let keys: Vec<_> = body["items"][0].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["email", "id", "name"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_email_domains_paginates() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Ann", "ann@big.example").await;
+        insert_user(&pool, "Bob", "bob@big.example").await;
+        insert_user(&pool, "Cat", "cat@small.example").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app.clone(), "/users/domains?limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            page["items"],
+            json!([{ "domain": "big.example", "count": 2 }])
+        );
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["next_offset"], 1);
+
+        let (_, body) = get_body(app, "/users/domains?limit=1&offset=1").await;
+        let page: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            page["items"],
+            json!([{ "domain": "small.example", "count": 1 }])
+        );
+        assert_eq!(page["next_offset"], Value::Null);
+    }
+
+    fn delete_request(id: i32, api_key: Option<&str>) -> Request<Body> {
+        let mut request = Request::delete(format!("/users/{id}"));
+        if let Some(key) = api_key {
+            request = request.header(crate::auth::API_KEY_HEADER, key);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_reports_related_rows() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Leaving", "leaving@example.com").await;
+        repository::touch_updated_at(&pool, user.id).await.unwrap();
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+
+        let response = app
+            .clone()
+            .oneshot(delete_request(user.id, Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, json!({ "deleted": true, "related": { "audit": 2 } }));
+
+        let (status, _) = get_body(app.clone(), &format!("/users/{}", user.id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let response = app
+            .oneshot(delete_request(user.id, Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_requires_api_key() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = router().with_state(test_state(unreachable_pool(), config));
+
+        let response = app.oneshot(delete_request(1, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    fn create_request(body: &Value) -> Request<Body> {
+        Request::post("/users")
+            .extension(admin())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let request = create_request(&json!({ "name": "Alice", "email": "alice@example.com" }));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let user: User = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(user.name, "Alice");
+        assert_eq!(user.email, "alice@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_create_user_requires_admin_role() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            api_key_tiers: vec![("client".to_string(), crate::config::ApiTier::Basic)],
+            ..test_config()
+        };
+        let app = router().with_state(test_state(unreachable_pool(), config));
+
+        for (key, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("wrong"), StatusCode::UNAUTHORIZED),
+            (Some("client"), StatusCode::FORBIDDEN),
+        ] {
+            let mut request = Request::post("/users")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "name": "Anon", "email": "anon@example.com" }).to_string(),
+                ))
+                .unwrap();
+            if let Some(key) = key {
+                request
+                    .headers_mut()
+                    .insert(crate::auth::API_KEY_HEADER, HeaderValue::from_static(key));
+            }
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), expected, "{key:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rejects_case_variant_email() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Upper", "A@x.com").await;
+        let app = router().with_state(test_state(pool.clone(), test_config()));
+
+        let request = create_request(&json!({ "name": "Lower", "email": "a@x.com" }));
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "email is already registered");
+        let total = repository::count_users(&pool, &UserFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_idempotency_key_survives_restart() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let body = json!({ "name": "Retry", "email": "retry@example.com" });
+        let send = |pool: PgPool| {
+            let mut request = create_request(&body);
+            request
+                .headers_mut()
+                .insert(IDEMPOTENCY_KEY, HeaderValue::from_static("create-retry-1"));
+            // A fresh state per request, as after a restart
+            router()
+                .with_state(test_state(pool, test_config()))
+                .oneshot(request)
+        };
+
+        let first = send(pool.clone()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(IDEMPOTENT_REPLAYED).is_none());
+        let first_body = to_bytes(first.into_body(), usize::MAX).await.unwrap();
+
+        let retried = send(pool.clone()).await.unwrap();
+        assert_eq!(retried.status(), StatusCode::CREATED);
+        assert_eq!(retried.headers()[IDEMPOTENT_REPLAYED], "true");
+        let retried_body = to_bytes(retried.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(first_body, retried_body);
+        let total = repository::count_users(&pool, &UserFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rejects_empty_idempotency_key() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+        let mut request = create_request(&json!({ "name": "A", "email": "a@example.com" }));
+        request
+            .headers_mut()
+            .insert(IDEMPOTENCY_KEY, HeaderValue::from_static(""));
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_create_user_over_length_name_is_unprocessable() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+
+        let name = "x".repeat(crate::models::MAX_NAME_LEN + 1);
+        let request = create_request(&json!({ "name": name, "email": "alice@example.com" }));
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_uses_configured_cache_control() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Cached", "cached@example.com").await;
+        let config = Config {
+            get_cache_control: "public, max-age=60".to_string(),
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+
+        for uri in [format!("/users/{}", user.id), "/users".to_string()] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::CACHE_CONTROL],
+                "public, max-age=60"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_is_never_cacheable() {
+        let config = Config {
+            get_cache_control: "public, max-age=60".to_string(),
+            ..test_config()
+        };
+        let app = router().with_state(test_state(unreachable_pool(), config));
+
+        let name = "x".repeat(crate::models::MAX_NAME_LEN + 1);
+        let request = create_request(&json!({ "name": name, "email": "alice@example.com" }));
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
+
+    #[tokio::test]
+    async fn test_user_audit_lists_create_then_update() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool.clone(), test_config()));
+        let response = app
+            .clone()
+            .oneshot(create_request(
+                &json!({ "name": "Ann", "email": "ann@example.com" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let user: User = serde_json::from_slice(&bytes).unwrap();
+        sqlx::query("UPDATE users SET name = 'Anne' WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let (status, body) = get_body(app.clone(), &format!("/users/{}/audit", user.id)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["total"], 2);
+        assert_eq!(body["items"][0]["action"], "create");
+        assert_eq!(body["items"][1]["action"], "update");
+
+        let (status, _) = get_body(app, "/users/999/audit").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_count_active_users() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let recent = insert_user(&pool, "Recent", "recent@example.com").await;
+        insert_user(&pool, "Never", "never@example.com").await;
+        sqlx::query("UPDATE users SET last_login_at = '2024-06-01T00:00:00Z' WHERE id = $1")
+            .bind(recent.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) =
+            get_body(app.clone(), "/users/active?since=2024-05-01T00:00:00Z").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["active"], 1);
+
+        let (status, _) = get_body(app, "/users/active").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_user_page_follows_next_cursor() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        insert_user(&pool, "Amy", "amy@example.com").await;
+        let last = insert_user(&pool, "Bob", "bob@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app.clone(), "/users/page?limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        let first: Value = serde_json::from_str(&body).unwrap();
+        let next = first["next"].as_str().unwrap();
+
+        let (status, body) = get_body(app, &format!("/users/page?limit=1&after={next}")).await;
+        assert_eq!(status, StatusCode::OK);
+        let second: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(second["items"][0]["id"], last.id);
+        assert_eq!(second["next"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_user_page_rejects_malformed_cursor() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+
+        let (status, body) = get_body(app, "/users/page?after=not-a-cursor").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({ "error": "after: invalid cursor" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_with_singleflight_reads() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Shared", "shared@example.com").await;
+        let config = Config {
+            singleflight_reads: true,
+            ..test_config()
+        };
+        let state = test_state(pool, config);
+        assert!(state.user_reads.is_some());
+        let app = router().with_state(state);
+
+        let uri = format!("/users/{}", user.id);
+        let responses =
+            futures_util::future::join_all((0..10).map(|_| get_body(app.clone(), &uri))).await;
+        for (status, body) in responses {
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(serde_json::from_str::<User>(&body).unwrap().id, user.id);
+        }
+        let (status, _) = get_body(app, "/users/999999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_from_cache_until_deleted() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Cached", "cached@example.com").await;
+        let config = Config {
+            user_cache_capacity: 10,
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool.clone(), config));
+        let uri = format!("/users/{}", user.id);
+        let (status, _) = get_body(app.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+
+        sqlx::query("UPDATE users SET name = 'Changed' WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (_, body) = get_body(app.clone(), &uri).await;
+        assert_eq!(serde_json::from_str::<User>(&body).unwrap().name, "Cached");
+
+        let response = app
+            .clone()
+            .oneshot(delete_request(user.id, Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (status, _) = get_body(app, &uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_evicts_reads_made_before_commit() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Racing", "racing@example.com").await;
+        let config = Config {
+            user_cache_capacity: 10,
+            api_key: Some("secret".to_string()),
+            ..test_config()
+        };
+        let state = test_state(pool, config);
+        let uri = format!("/users/{}", user.id);
+
+        // A read of the user after the handler is done but before its
+        // transaction commits, which still sees the user and caches it
+        let reader = router().with_state(state.clone());
+        let racing_get = middleware::from_fn(move |request: Request<Body>, next: Next| {
+            let (reader, uri) = (reader.clone(), uri.clone());
+            async move {
+                let response = next.run(request).await;
+                let (status, _) = get_body(reader, &uri).await;
+                assert_eq!(status, StatusCode::OK);
+                response
+            }
+        });
+        let deleter = super::super::scoped(
+            Router::new()
+                .route("/users/:id", axum::routing::delete(delete_user))
+                .route_layer(racing_get),
+        )
+        .with_state(state.clone());
+
+        let response = deleter
+            .oneshot(delete_request(user.id, Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let app = router().with_state(state);
+        let (status, _) = get_body(app, &format!("/users/{}", user.id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_email_normalizes_query() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Mixed", "mixed@example.com").await;
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, body) = get_body(app, "/users/by-email?email=Mixed%40Example.COM").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let found: User = serde_json::from_str(&body).unwrap();
+        assert_eq!(found.id, user.id);
+        assert_eq!(found.email, "mixed@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_email_not_found() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, _) = get_body(app, "/users/by-email?email=nobody%40example.com").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_by_email_requires_valid_email() {
+        let app = router().with_state(test_state(unreachable_pool(), test_config()));
+
+        for uri in [
+            "/users/by-email",
+            "/users/by-email?email=",
+            "/users/by-email?email=nope",
+        ] {
+            let (status, _) = get_body(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unified_responses_wrap_errors() {
+        let config = Config {
+            unified_responses: true,
+            ..test_config()
+        };
+        let app = router().with_state(test_state(unreachable_pool(), config));
+
+        let (status, body) = get_body(app, "/users/by-email?email=nope").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["ok"], false);
+        assert_eq!(body["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn test_unified_responses_wrap_user() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let user = insert_user(&pool, "Wrapped", "wrapped@example.com").await;
+        let config = Config {
+            unified_responses: true,
+            ..test_config()
+        };
+        let app = router().with_state(test_state(pool, config));
+
+        let (status, body) = get_body(app.clone(), &format!("/users/{}", user.id)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["data"]["email"], "wrapped@example.com");
+
+        let (status, body) = get_body(app, "/users/999999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            json!({ "ok": false, "error": { "code": "not_found", "message": "Resource not found" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_user_not_found() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let app = router().with_state(test_state(pool, test_config()));
+
+        let (status, _) = get_body(app, "/users/999").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
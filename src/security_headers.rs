@@ -0,0 +1,39 @@
+//! Baseline security response headers
+//!
+//! Unless `SECURITY_HEADERS` is turned off, every response carries
+//! `X-Content-Type-Options: nosniff`, `X-Frame-Options: DENY` and the
+//! configured `Referrer-Policy`. Headers a handler sets itself are kept.
+
+use crate::{config::Config, state::AppState};
+use axum::{
+    http::{header, HeaderValue},
+    Router,
+};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// Default `Referrer-Policy`; an API has no reason to leak its URLs
+pub const DEFAULT_REFERRER_POLICY: &str = "no-referrer";
+
+/// Add the security header layers to `router` when enabled in `config`
+pub fn apply(router: Router<AppState>, config: &Config) -> Router<AppState> {
+    if !config.security_headers {
+        return router;
+    }
+    // `Config::validate` has checked the value; fall back defensively anyway
+    let referrer_policy = HeaderValue::from_str(&config.referrer_policy)
+        .unwrap_or_else(|_| HeaderValue::from_static(DEFAULT_REFERRER_POLICY));
+
+    router
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_static("DENY"),
+        ))
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::REFERRER_POLICY,
+            referrer_policy,
+        ))
+}
@@ -0,0 +1,96 @@
+//! Settings of the HTTP connection builder
+//!
+//! Both [`conn_limit::serve`](crate::conn_limit::serve) and the Unix socket
+//! server build their connections with [`ServerConfig::builder`], so slow
+//! clients are cut off the same way whichever listener they arrive on.
+
+use crate::config::Config;
+use hyper_util::{
+    rt::{TokioExecutor, TokioTimer},
+    server::conn::auto::Builder,
+};
+use std::time::Duration;
+
+/// Connection-level timeouts applied to every accepted connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Interval of keep-alive pings on idle HTTP/2 connections; `None`
+    /// disables keep-alive, so HTTP/1 connections close after one request
+    pub keep_alive: Option<Duration>,
+    /// Time allowed to send a request's headers; `None` is unlimited
+    ///
+    /// Hyper starts this timer as soon as an HTTP/1 connection waits for a
+    /// request, so it also closes idle keep-alive connections.
+    pub header_read_timeout: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: Some(Duration::from_mins(1)),
+            header_read_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Settings from `HTTP_KEEPALIVE_SECS` and `HTTP_HEADER_READ_TIMEOUT_SECS`,
+    /// where `0` turns the setting off
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        let secs = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        Self {
+            keep_alive: secs(config.http_keepalive_secs),
+            header_read_timeout: secs(config.http_header_read_timeout_secs),
+        }
+    }
+
+    /// A connection builder serving HTTP/1 and HTTP/2 with these settings
+    #[must_use]
+    pub fn builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive.is_some())
+            .header_read_timeout(self.header_read_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.keep_alive);
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_config;
+
+    #[test]
+    fn test_from_config_maps_zero_to_off() {
+        assert_eq!(
+            ServerConfig::from_config(&test_config()),
+            ServerConfig::default()
+        );
+
+        let config = Config {
+            http_keepalive_secs: 0,
+            http_header_read_timeout_secs: 5,
+            ..test_config()
+        };
+        assert_eq!(
+            ServerConfig::from_config(&config),
+            ServerConfig {
+                keep_alive: None,
+                header_read_timeout: Some(Duration::from_secs(5)),
+            }
+        );
+
+        let config = Config {
+            http_header_read_timeout_secs: 0,
+            ..test_config()
+        };
+        assert_eq!(ServerConfig::from_config(&config).header_read_timeout, None);
+    }
+}
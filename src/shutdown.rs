@@ -0,0 +1,179 @@
+//! Graceful shutdown
+//!
+//! On `SIGTERM`, Ctrl-C or an embedder's shutdown trigger the service is
+//! marked as draining, which fails the
+//! readiness probe so load balancers stop routing to it, and the server stops
+//! accepting connections while in-flight requests complete. The number of
+//! requests in flight when the signal arrived and the time taken to drain
+//! them are logged.
+
+use crate::state::AppState;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+use tokio::sync::watch;
+
+/// Count of requests being handled, and when draining began
+#[derive(Debug, Default)]
+pub struct InFlight {
+    count: AtomicUsize,
+    drain_started: OnceLock<Instant>,
+}
+
+impl InFlight {
+    /// Requests currently being handled
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Log how long draining took, if shutdown was requested
+    pub fn log_drained(&self) {
+        if let Some(started) = self.drain_started.get() {
+            tracing::info!(
+                drain_ms = started.elapsed().as_millis(),
+                "Drained in-flight requests"
+            );
+        }
+    }
+}
+
+/// Decrements the in-flight count when the request is done, even on panic
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Middleware counting requests until their response is produced
+///
+/// Streaming response bodies still being sent are not counted.
+pub async fn track_in_flight(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    state.in_flight.count.fetch_add(1, Ordering::AcqRel);
+    let _guard = InFlightGuard(&state.in_flight);
+    next.run(request).await
+}
+
+/// Resolve once shutdown is requested, after marking `state` as draining
+///
+/// Shutdown is requested by an OS signal or by `true` being sent on
+/// `trigger`. Pass to [`axum::serve::Serve::with_graceful_shutdown`].
+pub async fn shutdown_signal(state: AppState, trigger: Option<watch::Receiver<bool>>) {
+    tokio::select! {
+        () = wait_for_signal() => {}
+        () = wait_for_trigger(trigger) => {}
+    }
+    begin_drain(&state);
+}
+
+/// Resolve once `true` is sent on `trigger`; never if there is none or its
+/// sender is dropped first
+async fn wait_for_trigger(trigger: Option<watch::Receiver<bool>>) {
+    if let Some(mut trigger) = trigger {
+        if trigger.wait_for(|&fired| fired).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await;
+}
+
+fn begin_drain(state: &AppState) {
+    state.draining.store(true, Ordering::Release);
+    let _ = state.in_flight.drain_started.set(Instant::now());
+    tracing::info!(
+        in_flight = state.in_flight.count(),
+        "Shutdown requested; draining in-flight requests"
+    );
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to install SIGTERM handler; only Ctrl-C stops the server");
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{capture_logs, test_config, test_state, unreachable_pool};
+    use axum::{body::Body, middleware, routing::get, Router};
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_drain_reports_in_flight_requests() {
+        let (logs, _guard) = capture_logs();
+        let state = test_state(unreachable_pool(), test_config());
+        let release = Arc::new(Semaphore::new(0));
+        let app = {
+            let release = release.clone();
+            Router::new()
+                .route(
+                    "/slow",
+                    get(move || async move {
+                        let _permit = release.acquire().await;
+                    }),
+                )
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    track_in_flight,
+                ))
+                .with_state(state.clone())
+        };
+
+        let requests: Vec<_> = (0..2)
+            .map(|_| {
+                let request = Request::get("/slow").body(Body::empty()).unwrap();
+                tokio::spawn(app.clone().oneshot(request))
+            })
+            .collect();
+        while state.in_flight.count() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        begin_drain(&state);
+        assert!(logs.contents().contains("in_flight=2"));
+        assert_eq!(state.in_flight.count(), 2);
+
+        release.add_permits(2);
+        for request in requests {
+            request.await.unwrap().unwrap();
+        }
+        assert_eq!(state.in_flight.count(), 0);
+        state.in_flight.log_drained();
+        assert!(logs.contents().contains("Drained in-flight requests"));
+    }
+}
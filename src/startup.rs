@@ -0,0 +1,438 @@
+//! Startup tasks
+//!
+//! This module contains the work performed alongside the HTTP server while the
+//! service boots, before it reports itself ready.
+
+use crate::{config::Config, repository, state::AppState};
+use sqlx::migrate::Migrator;
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{net::TcpListener, runtime::Runtime};
+
+/// Process exit code used when the listen port is already taken
+pub const EXIT_PORT_IN_USE: u8 = 3;
+
+/// Delay before the first database ping retry; later retries back off
+/// exponentially, jittered by `DB_RETRY_JITTER`
+const DB_PING_RETRY_BASE: Duration = Duration::from_secs(1);
+
+/// Reasons the service can fail to start or keep running
+#[derive(Error, Debug)]
+pub enum StartupError {
+    /// Another process is already listening on the configured port
+    #[error(
+        "Port {port} already in use; stop the other process or set SERVER_PORT to a free port"
+    )]
+    PortInUse {
+        /// The port that could not be bound
+        port: u16,
+    },
+
+    /// Binding the listener failed for another reason
+    #[error("Failed to bind {addr}: {source}")]
+    Bind {
+        /// The address that could not be bound
+        addr: SocketAddr,
+        /// Underlying IO error
+        source: io::Error,
+    },
+
+    /// Binding the Unix domain socket failed
+    #[error("Failed to bind {}: {source}", path.display())]
+    BindUnix {
+        /// The socket path that could not be bound
+        path: PathBuf,
+        /// Underlying IO error
+        source: io::Error,
+    },
+
+    /// The connection pool could not be created
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    /// The binary was built without any migrations to apply
+    #[error(
+        "No database migrations are embedded in this build; rebuild with the migrations/ \
+         directory in place, or set RUN_MIGRATIONS=false if the schema is managed elsewhere"
+    )]
+    NoMigrations,
+
+    /// Applying migrations failed
+    #[error("Migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+
+    /// The schema does not match what the code expects
+    #[error("Schema error: {0}")]
+    Schema(#[from] repository::SchemaError),
+
+    /// The HTTP server stopped with an error
+    #[error("Server error: {0}")]
+    Serve(io::Error),
+}
+
+impl StartupError {
+    /// Process exit code reported for this error
+    ///
+    /// A taken port gets [`EXIT_PORT_IN_USE`] so supervisors can tell it apart
+    /// from other failures.
+    #[must_use]
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::PortInUse { .. } => ExitCode::from(EXIT_PORT_IN_USE),
+            _ => ExitCode::FAILURE,
+        }
+    }
+}
+
+/// Build the multi-threaded runtime with `WORKER_THREADS` workers
+///
+/// # Errors
+///
+/// Returns an error if the runtime cannot be created
+pub fn build_runtime(config: &Config) -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .enable_all()
+        .build()
+}
+
+/// Bind the HTTP listener, classifying an already-taken port
+///
+/// # Errors
+///
+/// Returns [`StartupError::PortInUse`] if the address is taken, or
+/// [`StartupError::Bind`] for any other failure
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener, StartupError> {
+    TcpListener::bind(addr).await.map_err(|source| {
+        if source.kind() == io::ErrorKind::AddrInUse {
+            StartupError::PortInUse { port: addr.port() }
+        } else {
+            StartupError::Bind { addr, source }
+        }
+    })
+}
+
+/// Bind the Unix domain socket listener at `path`
+///
+/// A socket file left behind by a previous run is removed first; any other
+/// kind of file at `path` is left alone and makes binding fail.
+///
+/// # Errors
+///
+/// Returns [`StartupError::BindUnix`] if the socket cannot be bound
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> Result<tokio::net::UnixListener, StartupError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let bind_error = |source| StartupError::BindUnix {
+        path: path.to_path_buf(),
+        source,
+    };
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path).map_err(bind_error)?;
+    }
+    tokio::net::UnixListener::bind(path).map_err(bind_error)
+}
+
+/// Wait for the database, apply migrations, then mark the service ready
+///
+/// Pings until the database answers, so readiness reflects real connectivity
+/// rather than the optimism of a lazily created pool. Migrations are skipped
+/// when `RUN_MIGRATIONS` is off. Either way the schema is then checked, so a
+/// partially migrated database is caught before the service reports ready. Logs a warning if the whole phase takes longer than
+/// `STARTUP_WARN_SECS`.
+///
+/// # Errors
+///
+/// Returns [`StartupError::NoMigrations`] before touching the database if
+/// migrations are enabled but none were embedded, or an error if migrations
+/// fail to apply or the schema is incomplete, which with migrations disabled
+/// includes a database nobody has migrated
+pub async fn initialize_database(state: &AppState) -> Result<(), StartupError> {
+    if state.config.run_migrations {
+        ensure_migrations(&sqlx::migrate!())?;
+    }
+    let started = Instant::now();
+    let mut attempt: u32 = 1;
+    while let Err(e) = repository::ping(&state.pool()).await {
+        let delay = repository::next_backoff(
+            attempt - 1,
+            DB_PING_RETRY_BASE,
+            state.config.db_retry_jitter,
+        );
+        tracing::warn!(attempt, error = %e, ?delay, "Database not reachable yet, retrying");
+        attempt += 1;
+        tokio::time::sleep(delay).await;
+    }
+
+    if state.config.run_migrations {
+        repository::run_migrations(&state.pool(), state.config.migration_lock_key).await?;
+        tracing::info!("Database migrations applied");
+    } else {
+        tracing::info!("Skipping migrations; RUN_MIGRATIONS is off");
+    }
+    repository::ensure_schema(&state.pool()).await?;
+    warn_if_slow(
+        started.elapsed(),
+        Duration::from_secs(state.config.startup_warn_secs),
+    );
+
+    state.db_ready.store(true, Ordering::Release);
+    tracing::info!("Database ready");
+
+    Ok(())
+}
+
+/// Fail unless `migrator` has at least one migration
+///
+/// `sqlx::migrate!()` embeds whatever `migrations/` held at build time, so a
+/// build made without the files migrates nothing, and every query would
+/// later fail on missing tables.
+fn ensure_migrations(migrator: &Migrator) -> Result<(), StartupError> {
+    if migrator.iter().next().is_none() {
+        return Err(StartupError::NoMigrations);
+    }
+    Ok(())
+}
+
+/// Optional behaviours and whether `config` turns each on
+///
+/// Flags named in `FEATURES` are listed separately by
+/// [`log_feature_summary`].
+#[must_use]
+pub fn feature_summary(config: &Config) -> Vec<(&'static str, bool)> {
+    vec![
+        ("api_key_auth", config.api_key.is_some()),
+        ("api_key_tiers", !config.api_key_tiers.is_empty()),
+        ("jwt_auth", config.jwt_secret.is_some()),
+        ("webhooks", config.webhook_url.is_some()),
+        ("metrics", true),
+        ("security_headers", config.security_headers),
+        ("purge", config.allow_purge),
+        ("load_shedding", config.shed_on_pool_saturation),
+        ("write_limit", config.max_concurrent_writes > 0),
+        ("conn_per_ip_limit", config.max_conn_per_ip > 0),
+        ("query_budget", config.max_queries_per_request > 0),
+        ("singleflight_reads", config.singleflight_reads),
+        ("user_cache", config.user_cache_capacity > 0),
+        ("unified_responses", config.unified_responses),
+        ("strict_slashes", config.strict_slashes),
+        ("slow_request_log", config.slow_request_ms > 0),
+        ("trace_sampling", config.trace_sample_rate > 0.0),
+        ("deep_health_check", config.deep_health_check),
+        ("listen_user_changes", config.listen_user_changes),
+        ("run_migrations", config.run_migrations),
+        ("pool_stats", config.pool_stats_interval_secs > 0),
+        ("db_watchdog", config.watchdog_interval_secs > 0),
+    ]
+}
+
+/// Log a warning for each [risky setting](Config::diagnose) of `config`
+pub fn log_diagnostics(config: &Config) {
+    for diagnostic in config.diagnose() {
+        tracing::warn!(key = diagnostic.key, "Risky configuration: {diagnostic}");
+    }
+}
+
+/// Log one line listing the optional behaviours enabled by `config`
+///
+/// Lets operators confirm the runtime profile of an instance at a glance.
+pub fn log_feature_summary(config: &Config) {
+    let (enabled, disabled): (Vec<_>, Vec<_>) =
+        feature_summary(config).into_iter().partition(|&(_, on)| on);
+    let names = |features: Vec<(&str, bool)>| {
+        features
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let flags = config
+        .features
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+    tracing::info!(
+        app_env = ?config.app_env,
+        enabled = %names(enabled),
+        disabled = %names(disabled),
+        flags = %flags,
+        "Runtime features"
+    );
+}
+
+/// Log a warning if database initialization took longer than `threshold`
+///
+/// Returns whether the warning was logged.
+fn warn_if_slow(elapsed: Duration, threshold: Duration) -> bool {
+    let slow = elapsed > threshold;
+    if slow {
+        tracing::warn!(
+            elapsed_ms = elapsed.as_millis(),
+            threshold_secs = threshold.as_secs(),
+            "Database connect and migrate took longer than expected"
+        );
+    }
+    slow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{capture_logs, test_config, test_pool};
+
+    #[test]
+    fn test_build_runtime_uses_configured_worker_threads() {
+        let config = Config {
+            worker_threads: 3,
+            ..test_config()
+        };
+
+        let runtime = build_runtime(&config).unwrap();
+
+        assert_eq!(runtime.metrics().num_workers(), 3);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+
+    #[test]
+    fn test_warn_if_slow() {
+        let (logs, _guard) = capture_logs();
+        let threshold = Duration::from_secs(10);
+
+        assert!(!warn_if_slow(Duration::from_secs(3), threshold));
+        assert!(!logs.contents().contains("WARN"));
+
+        assert!(warn_if_slow(Duration::from_millis(12_500), threshold));
+        let output = logs.contents();
+        assert!(output.contains("WARN"), "{output}");
+        assert!(output.contains("elapsed_ms=12500"), "{output}");
+        assert!(output.contains("threshold_secs=10"), "{output}");
+    }
+
+    #[test]
+    fn test_feature_summary_reflects_config() {
+        let enabled = |config: &Config| -> Vec<&str> {
+            feature_summary(config)
+                .into_iter()
+                .filter_map(|(name, on)| on.then_some(name))
+                .collect()
+        };
+        let defaults = test_config();
+        assert_eq!(
+            enabled(&defaults),
+            [
+                "metrics",
+                "security_headers",
+                "slow_request_log",
+                "run_migrations",
+                "pool_stats"
+            ]
+        );
+
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            security_headers: false,
+            max_concurrent_writes: 4,
+            singleflight_reads: true,
+            features: crate::features::parse("user_import"),
+            ..test_config()
+        };
+        let toggled = enabled(&config);
+        assert!(toggled.contains(&"api_key_auth"), "{toggled:?}");
+        assert!(toggled.contains(&"write_limit"), "{toggled:?}");
+        assert!(toggled.contains(&"singleflight_reads"), "{toggled:?}");
+        assert!(!toggled.contains(&"security_headers"), "{toggled:?}");
+
+        let (logs, _guard) = capture_logs();
+        log_feature_summary(&config);
+        let output = logs.contents();
+        assert!(output.contains("Runtime features"), "{output}");
+        assert!(
+            output.contains("enabled=api_key_auth,metrics,write_limit,singleflight_reads,"),
+            "{output}"
+        );
+        assert!(output.contains("security_headers"), "{output}");
+        assert!(output.contains("flags=user_import"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_migrations_rejects_empty_set() {
+        let dir = std::env::temp_dir().join(format!("rust-basic-api-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let empty = Migrator::new(dir.as_path()).await.unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+
+        let err = ensure_migrations(&empty).unwrap_err();
+
+        assert!(matches!(err, StartupError::NoMigrations));
+        assert!(err.to_string().contains("migrations/"), "{err}");
+        ensure_migrations(&sqlx::migrate!()).unwrap();
+    }
+
+    fn without_migrations() -> Config {
+        Config {
+            run_migrations: false,
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_initialize_without_migrations_on_migrated_database() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let state = AppState::new(pool, without_migrations());
+
+        initialize_database(&state).await.unwrap();
+
+        assert!(state.db_ready.load(Ordering::Acquire));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_without_migrations_on_unmigrated_database() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        sqlx::raw_sql("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let state = AppState::new(pool.clone(), without_migrations());
+
+        let err = initialize_database(&state).await.unwrap_err();
+
+        assert!(matches!(err, StartupError::Schema(_)), "{err}");
+        assert!(!state.db_ready.load(Ordering::Acquire));
+        let migrated: bool = sqlx::query_scalar("SELECT to_regclass('users') IS NOT NULL")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(!migrated);
+    }
+
+    #[tokio::test]
+    async fn test_bind_port_in_use_is_classified() {
+        let first = bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = first.local_addr().unwrap();
+
+        let err = bind(addr).await.unwrap_err();
+
+        assert!(matches!(err, StartupError::PortInUse { port } if port == addr.port()));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Port {} already in use; stop the other process or set SERVER_PORT to a free port",
+                addr.port()
+            )
+        );
+        assert_eq!(err.exit_code(), ExitCode::from(EXIT_PORT_IN_USE));
+    }
+}
@@ -0,0 +1,120 @@
+//! Shared application state
+//!
+//! This module defines the state handed to every route handler.
+
+use crate::{
+    cache::{CountCache, SingleFlight},
+    config::Config,
+    metrics::Metrics,
+    models::User,
+    repository::{CachedUserRepo, PgUsers},
+    shutdown::InFlight,
+    webhook::Webhook,
+};
+use arc_swap::ArcSwap;
+use sqlx::PgPool;
+use std::{
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+
+/// State shared across all request handlers
+#[derive(Clone)]
+pub struct AppState {
+    /// `PostgreSQL` connection pool, replaceable at runtime; see [`Self::pool`]
+    pool: Arc<ArcSwap<PgPool>>,
+    /// Application configuration
+    pub config: Arc<Config>,
+    /// Set once the database has answered its first ping and been migrated
+    pub db_ready: Arc<AtomicBool>,
+    /// Set when shutdown begins; readiness fails while requests drain
+    pub draining: Arc<AtomicBool>,
+    /// Requests being handled, reported when shutdown begins
+    pub in_flight: Arc<InFlight>,
+    /// Request metrics exported via `GET /metrics`
+    pub metrics: Arc<Metrics>,
+    /// Recent total of the `users` table, for unfiltered listings
+    pub user_count: Arc<CountCache>,
+    /// Slots for writes in progress; `None` when `MAX_CONCURRENT_WRITES` is 0
+    pub write_permits: Option<Arc<Semaphore>>,
+    /// Reads of single users in flight; `None` unless `SINGLEFLIGHT_READS` is on
+    pub user_reads: Option<Arc<SingleFlight<i32, Option<User>>>>,
+    /// Read cache of single users; `None` unless `USER_CACHE_CAPACITY` is set
+    pub user_cache: Option<Arc<CachedUserRepo<PgUsers>>>,
+    /// Endpoint of user lifecycle events; `None` unless `WEBHOOK_URL` is set
+    pub webhook: Option<Webhook>,
+}
+
+impl AppState {
+    /// Build the state for a service that has not reached its database yet
+    ///
+    /// Neither ready nor draining, with no requests in flight, empty metrics,
+    /// a count cache living `COUNT_CACHE_MS`, every write slot free, no
+    /// user reads in flight, an empty user cache if `USER_CACHE_CAPACITY` is
+    /// set and a webhook client if `WEBHOOK_URL` is set.
+    #[must_use]
+    pub fn new(pool: PgPool, config: Config) -> Self {
+        let pool = Arc::new(ArcSwap::from_pointee(pool));
+        let count_ttl = Duration::from_millis(config.count_cache_ms);
+        let user_cache = (config.user_cache_capacity > 0).then(|| {
+            Arc::new(CachedUserRepo::new(
+                PgUsers::new(pool.clone()),
+                config.user_cache_capacity,
+                Duration::from_millis(config.user_cache_ttl_ms),
+            ))
+        });
+        let user_reads = config.singleflight_reads.then(Arc::default);
+        let write_permits = (config.max_concurrent_writes > 0)
+            .then(|| Arc::new(Semaphore::new(config.max_concurrent_writes)));
+        let webhook = config.webhook_url.as_deref().and_then(Webhook::new);
+        Self {
+            pool,
+            config: Arc::new(config),
+            db_ready: Arc::new(AtomicBool::new(false)),
+            draining: Arc::new(AtomicBool::new(false)),
+            in_flight: Arc::default(),
+            metrics: Arc::default(),
+            user_count: Arc::new(CountCache::new(count_ttl)),
+            write_permits,
+            user_reads,
+            user_cache,
+            webhook,
+        }
+    }
+
+    /// The current connection pool
+    ///
+    /// A cheap handle; take a fresh one per request rather than keeping it,
+    /// so a pool installed by [`Self::replace_pool`] is picked up.
+    #[must_use]
+    pub fn pool(&self) -> PgPool {
+        PgPool::clone(&self.pool.load())
+    }
+
+    /// Install `pool` for all subsequent requests, returning the previous one
+    ///
+    /// Requests already holding the previous pool finish on it; the caller
+    /// decides when to close it.
+    #[must_use = "the previous pool stays open until closed"]
+    pub fn replace_pool(&self, pool: PgPool) -> PgPool {
+        PgPool::clone(&self.pool.swap(Arc::new(pool)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config, unreachable_pool};
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_new_starts_unready_and_idle() {
+        let state = AppState::new(unreachable_pool(), test_config());
+
+        assert!(!state.db_ready.load(Ordering::Acquire));
+        assert!(!state.draining.load(Ordering::Acquire));
+        assert_eq!(state.in_flight.count(), 0);
+        assert_eq!(state.config.server_port, test_config().server_port);
+    }
+}
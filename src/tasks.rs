@@ -0,0 +1,128 @@
+//! Background tasks stopped on graceful shutdown
+//!
+//! Long-running tasks are spawned through a [`TaskRegistry`], which hands
+//! each a [`Shutdown`] signal. Once the server has drained, the registry
+//! signals every task and joins them, logging and aborting any that have not
+//! stopped within the timeout.
+
+use std::{future::Future, time::Duration};
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+/// Signal telling a registered task to stop
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Resolve once shutdown is requested or the registry has been dropped
+    pub async fn requested(&mut self) {
+        let _ = self.0.wait_for(|&requested| requested).await;
+    }
+}
+
+/// Background tasks to signal and join on shutdown
+#[derive(Debug)]
+pub struct TaskRegistry {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskRegistry {
+    /// An empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            shutdown: watch::Sender::new(false),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Spawn the task built by `task`, which should return soon after its
+    /// [`Shutdown`] resolves; `name` identifies it in logs
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: FnOnce(Shutdown) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task(Shutdown(self.shutdown.subscribe())));
+        self.tasks.push((name, handle));
+    }
+
+    /// Signal every task to stop and wait up to `timeout` for all of them
+    ///
+    /// Tasks still running after `timeout` are logged and aborted; their
+    /// names are returned.
+    pub async fn shutdown(self, timeout: Duration) -> Vec<&'static str> {
+        self.shutdown.send_replace(true);
+        let deadline = Instant::now() + timeout;
+        let mut stragglers = Vec::new();
+        for (name, mut handle) in self.tasks {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    tracing::error!(task = name, error = %e, "Background task failed");
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        task = name,
+                        timeout_ms = timeout.as_millis(),
+                        "Background task did not stop in time; aborting it"
+                    );
+                    handle.abort();
+                    stragglers.push(name);
+                }
+            }
+        }
+        stragglers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::capture_logs;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn test_shutdown_joins_cooperative_task() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut tasks = TaskRegistry::new();
+        tasks.spawn("cooperative", {
+            let stopped = stopped.clone();
+            |mut shutdown| async move {
+                shutdown.requested().await;
+                stopped.store(true, Ordering::Release);
+            }
+        });
+
+        let stragglers = tasks.shutdown(Duration::from_secs(5)).await;
+
+        assert!(stragglers.is_empty());
+        assert!(stopped.load(Ordering::Acquire));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_task_ignoring_signal() {
+        let (logs, _guard) = capture_logs();
+        let mut tasks = TaskRegistry::new();
+        tasks.spawn("cooperative", |mut shutdown| async move {
+            shutdown.requested().await;
+        });
+        tasks.spawn("stubborn", |_| std::future::pending());
+
+        let stragglers = tasks.shutdown(Duration::from_millis(50)).await;
+
+        assert_eq!(stragglers, ["stubborn"]);
+        let logs = logs.contents();
+        assert!(logs.contains("did not stop in time"), "{logs}");
+        assert!(logs.contains("task=\"stubborn\""), "{logs}");
+    }
+}
@@ -0,0 +1,319 @@
+//! Shared helpers for tests
+//!
+//! Database-backed tests read `TEST_DATABASE_URL` and are skipped when it is
+//! unset. Each call to [`test_pool`] provisions a fresh database on that server
+//! and applies all migrations, so tests never observe each other's rows.
+//!
+//! With the `testcontainers` feature, [`container_pool`] instead starts a
+//! throwaway `PostgreSQL` in Docker, so no database has to be provisioned.
+
+use crate::{
+    config::{AppEnv, Config, CountMode, ErrorDetail, JsonNaming, Port, RetryJitter},
+    repository,
+    request_id::X_REQUEST_ID,
+    state::AppState,
+};
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use serde_json::Value;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    Connection, Executor, PgConnection, PgPool,
+};
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+use tracing::subscriber::DefaultGuard;
+
+static DATABASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Log output captured by [`capture_logs`]
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// Everything logged so far
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture all log output on the current thread while the guard is alive
+///
+/// Works with the default single-threaded `#[tokio::test]` runtime, where
+/// spawned tasks run on the test thread too.
+pub fn capture_logs() -> (CapturedLogs, DefaultGuard) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .with_max_level(tracing::Level::TRACE)
+        .finish();
+    let guard = tracing::subscriber::set_default(subscriber);
+    (logs, guard)
+}
+
+/// Configuration with every optional setting at its default
+pub fn test_config() -> Config {
+    Config {
+        database_url: String::new(),
+        server_port: Port::DEFAULT,
+        listen_uds: None,
+        health_path: "/health".to_string(),
+        app_env: AppEnv::Dev,
+        allow_purge: false,
+        pretty_json: false,
+        unified_responses: false,
+        json_naming: JsonNaming::Snake,
+        json_max_depth: 32,
+        json_charset: "utf-8".to_string(),
+        error_detail: ErrorDetail::Minimal,
+        api_key: None,
+        api_key_tiers: Vec::new(),
+        jwt_secret: None,
+        jwt_issuer: None,
+        webhook_url: None,
+        db_ssl_mode: None,
+        db_extra_params: Vec::new(),
+        get_cache_control: "no-store".to_string(),
+        security_headers: true,
+        referrer_policy: "no-referrer".to_string(),
+        request_timeout_secs: 30,
+        body_read_timeout_secs: 30,
+        route_timeouts: Vec::new(),
+        slow_request_ms: 1000,
+        trace_sample_rate: 0.0,
+        trace_sample_seed: 0,
+        count_cache_ms: 2000,
+        singleflight_reads: false,
+        user_cache_capacity: 0,
+        user_cache_ttl_ms: 5000,
+        count_mode: CountMode::Exact,
+        max_offset: 100_000,
+        max_uri_len: 2048,
+        max_decompressed_bytes: 10 * 1024 * 1024,
+        min_search_len: 2,
+        db_fair_acquire: true,
+        db_acquire_retries: 0,
+        max_queries_per_request: 0,
+        db_retry_jitter: RetryJitter::Full,
+        db_conn_max_idle_ping_secs: 30,
+        shed_on_pool_saturation: false,
+        max_concurrent_writes: 0,
+        max_conn_per_ip: 0,
+        http_keepalive_secs: 60,
+        http_header_read_timeout_secs: 30,
+        deep_health_check: false,
+        health_check_timeout_ms: 2000,
+        features: BTreeSet::new(),
+        disabled_routes: Vec::new(),
+        strict_slashes: false,
+        listen_user_changes: false,
+        run_migrations: true,
+        migration_lock_key: repository::DEFAULT_MIGRATION_LOCK_KEY,
+        startup_warn_secs: 10,
+        pool_stats_interval_secs: 60,
+        watchdog_interval_secs: 0,
+        worker_threads: 1,
+        request_id_header: X_REQUEST_ID.clone(),
+    }
+}
+
+/// Connect to a freshly created and migrated test database
+///
+/// Returns `None` when `TEST_DATABASE_URL` is not set.
+pub async fn test_pool() -> Option<PgPool> {
+    let url = std::env::var("TEST_DATABASE_URL").ok()?;
+    let name = format!(
+        "rust_basic_api_test_{}_{}",
+        std::process::id(),
+        DATABASE_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+
+    let mut admin = PgConnection::connect(&url)
+        .await
+        .expect("Failed to connect to TEST_DATABASE_URL");
+    admin
+        .execute(format!("DROP DATABASE IF EXISTS {name}").as_str())
+        .await
+        .expect("Failed to drop stale test database");
+    admin
+        .execute(format!("CREATE DATABASE {name}").as_str())
+        .await
+        .expect("Failed to create test database");
+
+    let options = PgConnectOptions::from_str(&url)
+        .expect("Invalid TEST_DATABASE_URL")
+        .database(&name);
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(options)
+        .await
+        .expect("Failed to connect to test database");
+    repository::run_migrations(&pool, repository::DEFAULT_MIGRATION_LOCK_KEY)
+        .await
+        .expect("Failed to run migrations");
+
+    Some(pool)
+}
+
+/// Image tag of the `PostgreSQL` started by [`container_pool`]
+#[cfg(feature = "testcontainers")]
+const CONTAINER_POSTGRES_TAG: &str = "16-alpine";
+
+/// A migrated pool on a `PostgreSQL` container, removed when this is dropped
+#[cfg(feature = "testcontainers")]
+pub struct ContainerDatabase {
+    /// Pool on the container's database
+    pub pool: PgPool,
+    _container: testcontainers_modules::testcontainers::ContainerAsync<
+        testcontainers_modules::postgres::Postgres,
+    >,
+}
+
+/// Start a `PostgreSQL` container, apply all migrations and connect to it
+///
+/// Returns `None`, logging why, when the container cannot be
+/// started, typically because Docker is not available.
+///
+/// # Panics
+///
+/// Panics if the started container cannot be connected to or migrated
+#[cfg(feature = "testcontainers")]
+pub async fn container_pool() -> Option<ContainerDatabase> {
+    use testcontainers_modules::{
+        postgres::Postgres,
+        testcontainers::{runners::AsyncRunner, ImageExt},
+    };
+
+    let container = match Postgres::default()
+        .with_tag(CONTAINER_POSTGRES_TAG)
+        .start()
+        .await
+    {
+        Ok(container) => container,
+        Err(e) => {
+            tracing::warn!(error = %e, "Skipping: could not start a PostgreSQL container");
+            return None;
+        }
+    };
+    let host = container.get_host().await.expect("No container host");
+    let port = container
+        .get_host_port_ipv4(5432)
+        .await
+        .expect("PostgreSQL port not mapped");
+
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&format!(
+            "postgres://postgres:postgres@{host}:{port}/postgres"
+        ))
+        .await
+        .expect("Failed to connect to the PostgreSQL container");
+    repository::run_migrations(&pool, repository::DEFAULT_MIGRATION_LOCK_KEY)
+        .await
+        .expect("Failed to run migrations");
+
+    Some(ContainerDatabase {
+        pool,
+        _container: container,
+    })
+}
+
+/// A lazily created pool whose server never answers
+pub fn unreachable_pool() -> PgPool {
+    PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(100))
+        .connect_lazy("postgres://postgres@127.0.0.1:1/unreachable")
+        .expect("Invalid connection URL")
+}
+
+/// Build application state around a pool with the given configuration
+///
+/// The database is reported as ready, as [`test_pool`] has already migrated it.
+pub fn test_state(pool: PgPool, config: Config) -> AppState {
+    let state = AppState::new(pool, config);
+    state.db_ready.store(true, Ordering::Release);
+    state
+}
+
+/// The admin [`Principal`](crate::auth::Principal), for requests to endpoints
+/// requiring that role, e.g. `Request::post("/users").extension(admin())`
+pub fn admin() -> crate::auth::Principal {
+    crate::auth::Principal {
+        role: crate::auth::Role::Admin,
+    }
+}
+
+/// Send `GET uri` to `app`, returning the status and the body as text
+pub async fn get_body(app: Router, uri: &str) -> (StatusCode, String) {
+    let response = app
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+/// Insert a user directly, bypassing the HTTP layer
+pub async fn insert_user(pool: &PgPool, name: &str, email: &str) -> crate::models::User {
+    sqlx::query_as(
+        "INSERT INTO users (name, email) VALUES ($1, $2) \
+         RETURNING id, name, email, created_at, updated_at",
+    )
+    .bind(name)
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .expect("Failed to insert test user")
+}
+
+/// Start a local webhook endpoint, returning its URL and the bodies it
+/// receives
+///
+/// The first `failures` requests are answered `500`, later ones `204`.
+pub async fn mock_webhook(failures: usize) -> (String, mpsc::UnboundedReceiver<Value>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let seen = Arc::new(AtomicUsize::new(0));
+    let app = Router::new().route(
+        "/hook",
+        post(move |Json(body): Json<Value>| async move {
+            tx.send(body).unwrap();
+            if seen.fetch_add(1, Ordering::SeqCst) < failures {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (format!("http://{addr}/hook"), rx)
+}
@@ -0,0 +1,116 @@
+//! Sampled verbose tracing
+//!
+//! With `TRACE_SAMPLE_RATE` above zero, that fraction of requests runs inside
+//! an extra debug-level `verbose` span carrying the HTTP version and header
+//! names, and logs a debug event on entry, so debugging output can be
+//! enabled in production for a slice of traffic only. Other requests keep
+//! the usual request span and access line.
+//!
+//! The decision is a hash of the request id and `TRACE_SAMPLE_SEED`, so the
+//! same request id is always sampled the same way for a given seed.
+
+use crate::{request_id::RequestId, state::AppState};
+use axum::{
+    extract::{Request, State},
+    http::HeaderName,
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+/// Whether the request with `request_id` is traced verbosely
+///
+/// A rate of `0.0` samples nothing and `1.0` samples everything.
+#[must_use]
+#[allow(clippy::cast_precision_loss)] // 53 bits fit an f64 mantissa exactly
+pub fn is_sampled(rate: f64, seed: u64, request_id: &str) -> bool {
+    // FNV-1a over the id, then a splitmix64 finalizer to spread the bits
+    let mut hash = request_id
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64 ^ seed, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    let fraction = (hash >> 11) as f64 / (1_u64 << 53) as f64;
+    fraction < rate
+}
+
+/// Middleware running sampled requests inside a debug-level `verbose` span
+pub async fn sample_verbose_tracing(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let rate = state.config.trace_sample_rate;
+    let sampled = rate > 0.0
+        && request
+            .extensions()
+            .get::<RequestId>()
+            .is_some_and(|id| is_sampled(rate, state.config.trace_sample_seed, id.as_str()));
+    if !sampled {
+        return next.run(request).await;
+    }
+
+    let headers: Vec<_> = request.headers().keys().map(HeaderName::as_str).collect();
+    let span = tracing::debug_span!(
+        "verbose",
+        version = ?request.version(),
+        headers = ?headers,
+    );
+    tracing::debug!(parent: &span, "request sampled for verbose tracing");
+    next.run(request).instrument(span).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{capture_logs, test_config, test_state, unreachable_pool},
+    };
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn verbose_spans(rate: f64, requests: usize) -> usize {
+        let (logs, _guard) = capture_logs();
+        let config = Config {
+            trace_sample_rate: rate,
+            ..test_config()
+        };
+        let app = build_app(test_state(unreachable_pool(), config));
+        for _ in 0..requests {
+            let request = Request::get("/health").body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+        logs.contents()
+            .matches("request sampled for verbose tracing")
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_rate_zero_emits_no_verbose_spans() {
+        assert_eq!(verbose_spans(0.0, 20).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rate_one_emits_verbose_span_for_every_request() {
+        assert_eq!(verbose_spans(1.0, 20).await, 20);
+    }
+
+    #[test]
+    fn test_is_sampled_is_deterministic_per_seed() {
+        let ids: Vec<String> = (0..1000).map(|i| format!("req-{i}")).collect();
+        let decisions =
+            |seed| -> Vec<bool> { ids.iter().map(|id| is_sampled(0.5, seed, id)).collect() };
+
+        assert_eq!(decisions(7), decisions(7));
+        assert_ne!(decisions(7), decisions(8));
+        let sampled = decisions(7).into_iter().filter(|&s| s).count();
+        assert!((400..600).contains(&sampled), "{sampled}");
+    }
+}
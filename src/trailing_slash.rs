@@ -0,0 +1,104 @@
+//! Trailing slash normalization
+//!
+//! Routes are registered without a trailing slash, so `/users/` would
+//! otherwise be `404` while `/users` works. Unless `STRICT_SLASHES` is set,
+//! requests whose path ends in `/` and matches no route are redirected with
+//! `308 Permanent Redirect` to the path without it, which keeps the method
+//! and body.
+
+use crate::state::AppState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Middleware redirecting unmatched trailing-slash paths to the canonical form
+pub async fn redirect_trailing_slash(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.config.strict_slashes || request.extensions().get::<MatchedPath>().is_some() {
+        return next.run(request).await;
+    }
+    match canonical_location(request.uri().path(), request.uri().query()) {
+        Some(location) => (
+            StatusCode::PERMANENT_REDIRECT,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+        None => next.run(request).await,
+    }
+}
+
+/// `path` without trailing slashes, with `query` re-attached
+///
+/// `None` if there is nothing to strip; `/` is left alone.
+fn canonical_location(path: &str, query: Option<&str>) -> Option<HeaderValue> {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.len() == path.len() || trimmed.is_empty() {
+        return None;
+    }
+    let location = match query {
+        Some(query) => format!("{trimmed}?{query}"),
+        None => trimmed.to_string(),
+    };
+    HeaderValue::try_from(location).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_canonical_location() {
+        assert_eq!(
+            canonical_location("/users/", Some("limit=5")).unwrap(),
+            "/users?limit=5"
+        );
+        assert_eq!(canonical_location("/users//", None).unwrap(), "/users");
+        assert!(canonical_location("/users", None).is_none());
+        assert!(canonical_location("/", None).is_none());
+    }
+
+    async fn send(strict_slashes: bool, uri: &str) -> Response {
+        let config = Config {
+            strict_slashes,
+            ..test_config()
+        };
+        build_app(test_state(unreachable_pool(), config))
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_redirects_to_canonical_route() {
+        let response = send(false, "/health/startup/?verbose=1").await;
+
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/health/startup?verbose=1"
+        );
+
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert_eq!(send(false, location).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_strict_slashes_leaves_trailing_slash_unmatched() {
+        let response = send(true, "/health/startup/").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,213 @@
+//! Per-request database transactions
+//!
+//! Write handlers take a [`Tx`] extractor instead of using the pool directly.
+//! Its first use begins a transaction; the [`transaction_scope`]
+//! middleware commits it once the handler has produced a successful response
+//! and rolls it back otherwise, so a handler that fails part-way through
+//! leaves nothing behind. Work that must only follow a committed write, such
+//! as evicting a cached user, is deferred with [`Tx::after_commit`].
+
+use crate::{error::AppError, repository, state::AppState};
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::request::Parts,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::{PgConnection, PgPool, Postgres, Transaction};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Callback run once the request's transaction has committed
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// The request's transaction, once begun, and its [`Hook`]s
+#[derive(Default)]
+struct Scope {
+    tx: Option<Transaction<'static, Postgres>>,
+    after_commit: Vec<Hook>,
+}
+
+type Slot = Arc<Mutex<Scope>>;
+
+/// Middleware completing the transaction opened by a [`Tx`] extractor
+///
+/// Commits after a `1xx`-`3xx` response and rolls back after an error
+/// response. A failed commit turns the response into a `500`. Hooks
+/// registered with [`Tx::after_commit`] run after a successful commit, or
+/// after a successful response when no transaction was begun, and are
+/// dropped otherwise.
+pub async fn transaction_scope(mut request: Request, next: Next) -> Response {
+    let slot = Slot::default();
+    request.extensions_mut().insert(slot.clone());
+
+    let response = next.run(request).await;
+
+    let Scope { tx, after_commit } =
+        std::mem::take(&mut *slot.lock().unwrap_or_else(PoisonError::into_inner));
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        if let Some(tx) = tx {
+            if let Err(e) = tx.rollback().await {
+                tracing::warn!(error = %e, "Failed to roll back request transaction");
+            }
+        }
+        return response;
+    }
+    if let Some(tx) = tx {
+        if let Err(e) = tx.commit().await {
+            return AppError::Database(e).into_response();
+        }
+    }
+    for hook in after_commit {
+        hook();
+    }
+    response
+}
+
+/// Extractor providing the request's database transaction
+///
+/// The transaction begins on the first call to [`Tx::conn`], so a handler
+/// that rejects its input early never touches the database. Use at most one
+/// per handler; routes using it must be wrapped in [`transaction_scope`].
+/// Acquiring the connection is retried `DB_ACQUIRE_RETRIES` times when the
+/// pool times out.
+pub struct Tx {
+    pool: PgPool,
+    acquire_retries: u32,
+    open: Option<Transaction<'static, Postgres>>,
+    slot: Slot,
+}
+
+impl Tx {
+    /// The transaction's connection, beginning the transaction if needed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transaction cannot be started
+    pub async fn conn(&mut self) -> Result<&mut PgConnection, sqlx::Error> {
+        let tx = if let Some(tx) = self.open.take() {
+            tx
+        } else {
+            let conn = repository::acquire(&self.pool, self.acquire_retries).await?;
+            Transaction::begin(conn, None).await?
+        };
+        Ok(&mut **self.open.insert(tx))
+    }
+
+    /// Run `hook` once the transaction has committed
+    ///
+    /// For side effects that must not be seen before the write is, such as
+    /// evicting a cached user a concurrent read could otherwise load again
+    /// from the uncommitted state. Never runs if the transaction rolls back.
+    pub fn after_commit(&self, hook: impl FnOnce() + Send + 'static) {
+        self.slot
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .after_commit
+            .push(Box::new(hook));
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Tx {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let slot =
+            parts.extensions.get::<Slot>().cloned().ok_or_else(|| {
+                AppError::Internal("transaction_scope is not installed".to_string())
+            })?;
+
+        Ok(Self {
+            pool: state.pool(),
+            acquire_retries: state.config.db_acquire_retries,
+            open: None,
+            slot,
+        })
+    }
+}
+
+impl Drop for Tx {
+    /// Hand the transaction back to the middleware to commit or roll back
+    fn drop(&mut self) {
+        if let Some(tx) = self.open.take() {
+            self.slot.lock().unwrap_or_else(PoisonError::into_inner).tx = Some(tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_config, test_pool, test_state};
+    use axum::{body::Body, http::StatusCode, middleware, routing::post, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tower::ServiceExt;
+
+    async fn insert_then(
+        mut tx: Tx,
+        fail: bool,
+        committed: Arc<AtomicBool>,
+    ) -> Result<StatusCode, AppError> {
+        sqlx::query("INSERT INTO users (name, email) VALUES ('Tx', 'tx@example.com')")
+            .execute(tx.conn().await?)
+            .await?;
+        tx.after_commit(move || committed.store(true, Ordering::SeqCst));
+        if fail {
+            return Err(AppError::Validation("second step failed".to_string()));
+        }
+        Ok(StatusCode::CREATED)
+    }
+
+    /// The app and a flag its after-commit hook sets
+    fn app(pool: PgPool) -> (Router, Arc<AtomicBool>) {
+        let committed = Arc::new(AtomicBool::new(false));
+        let (ok, fail) = (committed.clone(), committed.clone());
+        let app = Router::new()
+            .route("/ok", post(move |tx: Tx| insert_then(tx, false, ok)))
+            .route("/fail", post(move |tx: Tx| insert_then(tx, true, fail)))
+            .route_layer(middleware::from_fn(transaction_scope))
+            .with_state(test_state(pool, test_config()));
+        (app, committed)
+    }
+
+    async fn user_count(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_rolls_back() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let (app, committed) = app(pool.clone());
+        let request = Request::post("/fail").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(user_count(&pool).await, 0);
+        assert!(!committed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_success_commits() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+
+        let (app, committed) = app(pool.clone());
+        let request = Request::post("/ok").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(user_count(&pool).await, 1);
+        assert!(committed.load(Ordering::SeqCst));
+    }
+}
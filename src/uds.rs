@@ -0,0 +1,73 @@
+//! Serving over a Unix domain socket
+//!
+//! With `LISTEN_UDS` set, [`run`](crate::run) serves on a socket file instead
+//! of the TCP port, for sidecars and proxies on the same host. Connections
+//! carry no peer address, so `MAX_CONN_PER_IP` does not apply to them.
+
+use crate::{
+    conn_limit::{is_connection_error, ACCEPT_ERROR_BACKOFF},
+    server_config::ServerConfig,
+};
+use axum::Router;
+use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown, service::TowerToHyperService};
+use std::{future::Future, io};
+use tokio::net::UnixListener;
+
+/// Serve `app` on `listener` until `signal` completes
+///
+/// Like [`conn_limit::serve`](crate::conn_limit::serve), open connections are
+/// then allowed to finish. The socket file is removed once the server has
+/// stopped.
+///
+/// # Errors
+///
+/// Never fails at present; failures to accept are logged and retried
+pub async fn serve(
+    listener: UnixListener,
+    app: Router,
+    server: ServerConfig,
+    signal: impl Future<Output = ()> + Send,
+) -> io::Result<()> {
+    let builder = server.builder();
+    let graceful = GracefulShutdown::new();
+    let path = listener
+        .local_addr()
+        .ok()
+        .and_then(|addr| addr.as_pathname().map(ToOwned::to_owned));
+    tokio::pin!(signal);
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) if is_connection_error(&e) => continue,
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to accept connection");
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            },
+            () = &mut signal => break,
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "Connection closed with an error");
+            }
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    if let Some(path) = path {
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::debug!(error = %e, path = %path.display(), "Could not remove socket file");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! Request target length limit
+//!
+//! Very long query strings cost time to parse and filter on, so requests
+//! whose path and query exceed `MAX_URI_LEN` bytes are refused with `414`
+//! before reaching a handler.
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Middleware answering `414` to request targets longer than `MAX_URI_LEN`
+pub async fn reject_long_uri(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let len = request
+        .uri()
+        .path_and_query()
+        .map_or(0, |target| target.as_str().len());
+    if len > state.config.max_uri_len {
+        tracing::warn!(
+            len,
+            max = state.config.max_uri_len,
+            "Rejected over-long request URI"
+        );
+        return AppError::UriTooLong.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{test_config, test_state, unreachable_pool},
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn get(config: Config, uri: &str) -> (StatusCode, Vec<u8>) {
+        let app = build_app(test_state(unreachable_pool(), config));
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_over_long_query_gets_414() {
+        let uri = format!("/health?q={}", "a".repeat(2048));
+
+        let (status, body) = get(test_config(), &uri).await;
+
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({ "error": "URI too long" }));
+    }
+
+    #[tokio::test]
+    async fn test_uri_at_limit_passes() {
+        let config = Config {
+            max_uri_len: 20,
+            ..test_config()
+        };
+        let uri = format!("/health?q={}", "a".repeat(20 - "/health?q=".len()));
+        assert_eq!(uri.len(), 20);
+
+        let (status, _) = get(config.clone(), &uri).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = get(config, &format!("{uri}a")).await;
+        assert_eq!(status, StatusCode::URI_TOO_LONG);
+    }
+}
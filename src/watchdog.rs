@@ -0,0 +1,116 @@
+//! Database connectivity watchdog
+//!
+//! With `WATCHDOG_INTERVAL_SECS` set, a background task pings the database
+//! every interval and stores the outcome in `AppState::db_ready`, so the
+//! readiness probe answers from that flag instead of pinging on every probe.
+//! Consecutive failures stretch the wait between pings with
+//! [`next_backoff`], up to [`MAX_BACKOFF`](crate::repository::MAX_BACKOFF);
+//! the first successful ping restores readiness and the regular interval.
+
+use crate::{config::RetryJitter, repository::next_backoff, tasks::TaskRegistry};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Ping with `ping` every `interval`, recording the result in `ready`
+///
+/// Starts one interval from now; the caller sets `ready` for the initial
+/// state. Registered with `tasks`, so it stops on shutdown.
+pub fn spawn<F, Fut>(tasks: &mut TaskRegistry, ready: Arc<AtomicBool>, interval: Duration, ping: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), sqlx::Error>> + Send,
+{
+    tasks.spawn("database watchdog", move |mut shutdown| async move {
+        let mut failures: u32 = 0;
+        loop {
+            tokio::select! {
+                () = shutdown.requested() => return,
+                () = tokio::time::sleep(delay(failures, interval)) => {}
+            }
+            match ping().await {
+                Ok(()) => {
+                    if failures > 0 {
+                        tracing::info!(failures, "Database reachable again; reporting ready");
+                    }
+                    failures = 0;
+                    ready.store(true, Ordering::Release);
+                }
+                Err(e) => {
+                    failures = failures.saturating_add(1);
+                    if ready.swap(false, Ordering::AcqRel) {
+                        tracing::warn!(error = %e, "Database unreachable; reporting not ready");
+                    } else {
+                        tracing::debug!(error = %e, failures, "Database still unreachable");
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Wait before the next ping after `failures` consecutive failed ones
+///
+/// Never shorter than `interval`, so backing off only ever slows pinging.
+fn delay(failures: u32, interval: Duration) -> Duration {
+    match failures.checked_sub(1) {
+        None => interval,
+        Some(attempt) => next_backoff(attempt, interval, RetryJitter::None).max(interval),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_delay_backs_off_from_interval() {
+        let interval = Duration::from_secs(2);
+        assert_eq!(delay(0, interval), interval);
+        assert_eq!(delay(1, interval), interval);
+        assert_eq!(delay(2, interval), Duration::from_secs(4));
+        assert_eq!(delay(10, interval), Duration::from_secs(30));
+        let long = Duration::from_secs(45);
+        assert_eq!(delay(5, long), long);
+    }
+
+    /// Wait until `ready` holds `expected`, panicking after `limit`
+    async fn wait_for(ready: &AtomicBool, expected: bool, limit: Duration) {
+        let deadline = Instant::now() + limit;
+        while ready.load(Ordering::Acquire) != expected {
+            assert!(
+                Instant::now() < deadline,
+                "readiness never became {expected}"
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_follows_ping_result() {
+        let interval = Duration::from_millis(50);
+        let reachable = Arc::new(AtomicBool::new(true));
+        let ready = Arc::new(AtomicBool::new(true));
+        let mut tasks = TaskRegistry::new();
+        spawn(&mut tasks, ready.clone(), interval, {
+            let reachable = reachable.clone();
+            move || {
+                let ok = reachable.load(Ordering::Acquire);
+                async move { ok.then_some(()).ok_or(sqlx::Error::PoolTimedOut) }
+            }
+        });
+
+        reachable.store(false, Ordering::Release);
+        wait_for(&ready, false, interval * 2).await;
+        reachable.store(true, Ordering::Release);
+        wait_for(&ready, true, interval * 2).await;
+
+        assert!(tasks.shutdown(Duration::from_secs(1)).await.is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! Outbound webhooks on user lifecycle events
+//!
+//! With `WEBHOOK_URL` set, handlers that create, update or delete a user
+//! attach a [`WebhookEvent`] to their response, and [`dispatch`] posts it as
+//! JSON once the response is known to be successful, so a rolled-back write
+//! is never announced. Delivery runs in a background task and never delays
+//! the response; it is retried a few times and then given up with a warning.
+
+use crate::{config::RetryJitter, models::User, repository, state::AppState};
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, Method, Uri},
+    middleware::Next,
+    response::Response,
+};
+use http_body_util::Full;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Deliveries attempted per event before giving up
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Time allowed for one delivery attempt
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay before the first retry, doubled for each further one
+const RETRY_BASE: Duration = Duration::from_millis(500);
+
+/// A user lifecycle event, serialized as the webhook's body
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookEvent {
+    /// `user.created`, `user.updated` or `user.deleted`
+    pub event: &'static str,
+    /// The user as returned by the API; only its `id` once deleted
+    pub user: Value,
+}
+
+impl WebhookEvent {
+    /// `user.created` for a newly stored user
+    #[must_use]
+    pub fn created(user: &User) -> Self {
+        Self::with_user("user.created", user)
+    }
+
+    /// `user.updated` for a changed user
+    #[must_use]
+    pub fn updated(user: &User) -> Self {
+        Self::with_user("user.updated", user)
+    }
+
+    /// `user.deleted` for the user with `id`
+    #[must_use]
+    pub fn deleted(id: i32) -> Self {
+        Self {
+            event: "user.deleted",
+            user: json!({ "id": id }),
+        }
+    }
+
+    fn with_user(event: &'static str, user: &User) -> Self {
+        Self {
+            event,
+            user: serde_json::to_value(user).unwrap_or_default(),
+        }
+    }
+}
+
+/// Client posting events to the configured endpoint
+#[derive(Clone)]
+pub struct Webhook {
+    url: Uri,
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl Webhook {
+    /// A client for `url`, or `None` if it is not a valid URI
+    #[must_use]
+    pub fn new(url: &str) -> Option<Self> {
+        let url = url.parse().ok()?;
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Some(Self { url, client })
+    }
+
+    /// Deliver `event` in the background
+    pub fn send(&self, event: WebhookEvent) {
+        let webhook = self.clone();
+        tokio::spawn(async move { webhook.deliver(&event).await });
+    }
+
+    /// Post `event` until it is accepted or [`MAX_ATTEMPTS`] have failed,
+    /// returning whether it was delivered
+    pub async fn deliver(&self, event: &WebhookEvent) -> bool {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => Bytes::from(body),
+            Err(e) => {
+                tracing::warn!(error = %e, event = event.event, "Failed to serialize webhook event");
+                return false;
+            }
+        };
+        let mut last_error = String::new();
+        for attempt in 0..MAX_ATTEMPTS {
+            if attempt > 0 {
+                let delay = repository::next_backoff(attempt - 1, RETRY_BASE, RetryJitter::Equal);
+                tokio::time::sleep(delay).await;
+            }
+            match self.post(body.clone()).await {
+                Ok(()) => return true,
+                Err(e) => {
+                    tracing::debug!(error = %e, attempt, event = event.event, "Webhook attempt failed");
+                    last_error = e;
+                }
+            }
+        }
+        tracing::warn!(
+            error = %last_error,
+            attempts = MAX_ATTEMPTS,
+            event = event.event,
+            "Giving up on webhook delivery"
+        );
+        false
+    }
+
+    /// One delivery attempt; anything but a `2xx` answer is an error
+    async fn post(&self, body: Bytes) -> Result<(), String> {
+        let request = axum::http::Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(body))
+            .map_err(|e| e.to_string())?;
+        let response = tokio::time::timeout(ATTEMPT_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| format!("no answer within {ATTEMPT_TIMEOUT:?}"))?
+            .map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("endpoint answered {}", response.status()))
+        }
+    }
+}
+
+/// Middleware sending the [`WebhookEvent`] a handler attached to a
+/// successful response
+///
+/// Wraps the routes, outside their [transaction](crate::transaction), so an
+/// event is only sent once the write has been committed.
+pub async fn dispatch(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let event = response.extensions_mut().remove::<WebhookEvent>();
+    if let (Some(event), Some(webhook)) = (event, &state.webhook) {
+        if response.status().is_success() {
+            webhook.send(event);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::mock_webhook;
+
+    #[test]
+    fn test_event_payloads() {
+        let user = User::builder().id(3).email("ann@example.com").build();
+
+        let created = serde_json::to_value(WebhookEvent::created(&user)).unwrap();
+        assert_eq!(created["event"], "user.created");
+        assert_eq!(created["user"]["email"], "ann@example.com");
+        assert_eq!(WebhookEvent::updated(&user).event, "user.updated");
+        assert_eq!(
+            serde_json::to_value(WebhookEvent::deleted(3)).unwrap(),
+            json!({ "event": "user.deleted", "user": { "id": 3 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries_until_accepted() {
+        let (url, mut received) = mock_webhook(1).await;
+        let webhook = Webhook::new(&url).unwrap();
+
+        assert!(webhook.deliver(&WebhookEvent::deleted(7)).await);
+
+        for _ in 0..2 {
+            assert_eq!(received.recv().await.unwrap()["user"]["id"], 7);
+        }
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_gives_up_after_max_attempts() {
+        let (url, mut received) = mock_webhook(usize::MAX).await;
+        let webhook = Webhook::new(&url).unwrap();
+
+        assert!(!webhook.deliver(&WebhookEvent::deleted(7)).await);
+
+        for _ in 0..MAX_ATTEMPTS {
+            received.recv().await.unwrap();
+        }
+        assert!(received.try_recv().is_err());
+    }
+}
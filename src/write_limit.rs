@@ -0,0 +1,141 @@
+//! Concurrency limit for write requests
+//!
+//! Writes hold row locks and contend on the database far more than reads, so
+//! a burst of them can starve everything else. With `MAX_CONCURRENT_WRITES`
+//! set, a `POST`, `PUT`, `PATCH` or `DELETE` arriving while that many are in
+//! progress is refused with `503` instead of queueing; reads are never
+//! limited here.
+
+use crate::{error::AppError, state::AppState};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Whether requests with `method` count against the write limit
+fn is_write(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+/// Middleware answering `503` to writes beyond `MAX_CONCURRENT_WRITES`
+pub async fn limit_concurrent_writes(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(permits) = state
+        .write_permits
+        .as_ref()
+        .filter(|_| is_write(request.method()))
+    else {
+        return next.run(request).await;
+    };
+    let Ok(_permit) = permits.clone().try_acquire_owned() else {
+        tracing::warn!(
+            limit = state.config.max_concurrent_writes,
+            "Shedding write, too many in progress"
+        );
+        return AppError::Overloaded.into_response();
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_app,
+        config::Config,
+        test_utils::{admin, test_config, test_pool, test_state},
+    };
+    use axum::{body::Body, http::StatusCode, Router};
+    use serde_json::json;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn post_user(email: &str) -> Request {
+        Request::post("/users")
+            .extension(admin())
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({ "name": "Writer", "email": email }).to_string(),
+            ))
+            .unwrap()
+    }
+
+    async fn status(app: &Router, request: Request) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn test_is_write() {
+        for method in [Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            assert!(is_write(&method), "{method}");
+        }
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            assert!(!is_write(&method), "{method}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_beyond_limit_are_shed_while_reads_pass() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let config = Config {
+            max_concurrent_writes: 1,
+            ..test_config()
+        };
+        let state = test_state(pool.clone(), config);
+        let permits = state.write_permits.clone().unwrap();
+        let app = build_app(state);
+
+        // Inserts wait for this lock; reads do not
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query("LOCK TABLE users IN EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        let slow = tokio::spawn({
+            let app = app.clone();
+            async move { status(&app, post_user("slow@example.com")).await }
+        });
+        while permits.available_permits() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            status(&app, post_user("shed@example.com")).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let read = Request::get("/users").body(Body::empty()).unwrap();
+        assert_eq!(status(&app, read).await, StatusCode::OK);
+
+        tx.commit().await.unwrap();
+        assert_eq!(slow.await.unwrap(), StatusCode::CREATED);
+        assert_eq!(
+            status(&app, post_user("after@example.com")).await,
+            StatusCode::CREATED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_without_max_concurrent_writes() {
+        let Some(pool) = test_pool().await else {
+            return;
+        };
+        let state = test_state(pool, test_config());
+        assert!(state.write_permits.is_none());
+        let app = build_app(state);
+
+        assert_eq!(
+            status(&app, post_user("free@example.com")).await,
+            StatusCode::CREATED
+        );
+    }
+}